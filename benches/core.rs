@@ -0,0 +1,99 @@
+//! Headless throughput of the emulator core.
+//!
+//! Every program runs from $0400 until it traps in a jump to itself. The
+//! `cycles` group reports emulated clock cycles per second, so its
+//! throughput in Melem/s reads as emulated MHz, and the `instructions`
+//! group reports instructions per second.
+
+use std::fs;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, SamplingMode, Throughput};
+
+use rust_test::computer::Computer;
+
+/// Give up on programs that never reach their trap
+const MAX_CYCLES: u64 = 1_000_000_000;
+const START: usize = 0x400;
+
+/// Add and subtract through zero page, 64 times 256 iterations
+const ARITHMETIC: [u8; 25] = [
+    0xa0, 0x40,       //         ldy #$40
+    0xa2, 0x00,       // outer:  ldx #$00
+    0x18,             // inner:  clc
+    0xa5, 0x10,       //         lda $10
+    0x69, 0x07,       //         adc #$07
+    0x85, 0x10,       //         sta $10
+    0x38,             //         sec
+    0xe5, 0x11,       //         sbc $11
+    0x85, 0x11,       //         sta $11
+    0xe8,             //         inx
+    0xd0, 0xf1,       //         bne inner
+    0x88,             //         dey
+    0xd0, 0xec,       //         bne outer
+    0x4c, 0x16, 0x04, // done:   jmp done
+];
+
+/// Copy and increment pages through indexed addressing and the stack,
+/// 64 times 256 iterations
+const MEMORY: [u8; 24] = [
+    0xa0, 0x40,       //         ldy #$40
+    0xa2, 0x00,       // outer:  ldx #$00
+    0xbd, 0x00, 0x10, // inner:  lda $1000,x
+    0x9d, 0x00, 0x20, //         sta $2000,x
+    0xfe, 0x00, 0x30, //         inc $3000,x
+    0x48,             //         pha
+    0x68,             //         pla
+    0xe8,             //         inx
+    0xd0, 0xf2,       //         bne inner
+    0x88,             //         dey
+    0xd0, 0xed,       //         bne outer
+    0x4c, 0x15, 0x04, // done:   jmp done
+];
+
+/// A 64 KiB image with `program` at the start address
+fn image(program: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; 0x10000];
+    data[START..START + program.len()].copy_from_slice(program);
+    data
+}
+
+/// Cycles and instructions one run of `data` takes
+fn measure(data: &[u8]) -> (u64, u64) {
+    let mut computer = Computer::headless(data.to_vec());
+    let instructions = computer.run_to_trap(MAX_CYCLES);
+    (computer.processor().clock, instructions)
+}
+
+fn programs() -> Vec<(&'static str, Vec<u8>)> {
+    let functional = concat!(env!("CARGO_MANIFEST_DIR"), "/6502_functional_test.bin");
+    vec![
+        ("functional_test", fs::read(functional).expect("could not read the functional test binary")),
+        ("arithmetic_loop", image(&ARITHMETIC)),
+        ("memory_loop", image(&MEMORY)),
+    ]
+}
+
+fn bench_programs(c: &mut Criterion) {
+    for (name, data) in programs() {
+        let (cycles, instructions) = measure(&data);
+        for &(group_name, count) in [("cycles", cycles), ("instructions", instructions)].iter() {
+            let mut group = c.benchmark_group(group_name);
+            if name == "functional_test" {
+                // Close to a second per run, too slow for the default sampling
+                group.sample_size(10).sampling_mode(SamplingMode::Flat);
+            }
+            group.throughput(Throughput::Elements(count));
+            group.bench_function(name, |b| {
+                b.iter_batched(
+                    || Computer::headless(data.clone()),
+                    |mut computer| computer.run_to_trap(MAX_CYCLES),
+                    BatchSize::LargeInput,
+                )
+            });
+            group.finish();
+        }
+    }
+}
+
+criterion_group!(benches, bench_programs);
+criterion_main!(benches);
@@ -0,0 +1,121 @@
+#ifndef EMU6502_H
+#define EMU6502_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ An emulated 6502 with 64 KiB of memory, opaque to C
+ */
+typedef struct Emu6502 Emu6502;
+
+/*
+ The registers, with B and bit 5 always clear in `flags`
+ */
+typedef struct Emu6502Registers {
+  uint8_t a;
+  uint8_t x;
+  uint8_t y;
+  uint8_t sp;
+  uint8_t flags;
+  uint16_t pc;
+} Emu6502Registers;
+
+/*
+ Called for each read of a mapped address, returns the byte read
+ */
+typedef uint8_t (*Emu6502ReadFn)(void *user, uint16_t addr);
+
+/*
+ Called for each write to a mapped address
+ */
+typedef void (*Emu6502WriteFn)(void *user, uint16_t addr, uint8_t value);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ A 6502 with zeroed memory, or a 65C02 when `cmos` is set. Free it with
+ `emu6502_free`.
+ */
+struct Emu6502 *emu6502_new(bool cmos);
+
+/*
+ Free an emulator. Does nothing when given NULL.
+ */
+void emu6502_free(struct Emu6502 *emu);
+
+/*
+ Why the last call that returned -1 failed. The string belongs to the
+ emulator and lasts until the next failure.
+ */
+const char *emu6502_last_error(const struct Emu6502 *emu);
+
+/*
+ Copy the binary file at `path` into memory at `addr`
+ */
+int emu6502_load(struct Emu6502 *emu, const char *path, uint16_t addr);
+
+/*
+ Put the processor back in its power on state, with PC at $0400. Memory
+ and the cycle count are left alone.
+ */
+void emu6502_reset(struct Emu6502 *emu);
+
+/*
+ Run one instruction and return the cycles it took
+ */
+uint32_t emu6502_step(struct Emu6502 *emu);
+
+/*
+ Run until the program traps in a jump or branch to itself, or at least
+ `max_cycles` have passed, and return the cycles run
+ */
+uint64_t emu6502_run(struct Emu6502 *emu, uint64_t max_cycles);
+
+/*
+ Cycles run since the emulator was created
+ */
+uint64_t emu6502_clock(const struct Emu6502 *emu);
+
+/*
+ Copy the registers to `registers`
+ */
+void emu6502_get_registers(const struct Emu6502 *emu, struct Emu6502Registers *registers);
+
+/*
+ Set every register. B and bit 5 of `flags` are ignored.
+ */
+void emu6502_set_registers(struct Emu6502 *emu, const struct Emu6502Registers *registers);
+
+/*
+ Copy `len` bytes of memory from `addr` to `out`, bypassing devices
+ */
+int emu6502_read_memory(struct Emu6502 *emu, uint16_t addr, uint8_t *out, size_t len);
+
+/*
+ Copy `len` bytes from `bytes` into memory at `addr`, bypassing devices
+ */
+int emu6502_write_memory(struct Emu6502 *emu, uint16_t addr, const uint8_t *bytes, size_t len);
+
+/*
+ Send the processor's reads and writes of `start` to `end`, inclusive,
+ to `read` and `write`, which get `user` back. Later mappings do not
+ take over addresses an earlier one has.
+ */
+int emu6502_map(struct Emu6502 *emu,
+                uint16_t start,
+                uint16_t end,
+                Emu6502ReadFn read,
+                Emu6502WriteFn write,
+                void *user);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* EMU6502_H */
@@ -1,8 +1,32 @@
+use std::mem;
 use std::sync::mpsc;
 use std::time;
 use std::thread;
 
-mod decode;
+pub mod decode;
+pub mod device;
+pub mod acia;
+pub mod riot;
+pub mod banking;
+pub mod profiler;
+pub mod coverage;
+pub mod memory;
+pub mod protocol;
+pub mod callstack;
+pub mod assembler;
+pub mod alu;
+pub mod cycle;
+pub mod machine;
+
+use device::Device;
+use banking::BankInfo;
+use profiler::Profiler;
+use coverage::Coverage;
+use callstack::CallStack;
+use memory::Memory;
+use cycle::{Bus, BusCycle, Completed, CycleCpu};
+use protocol::{Command, Event, Register, Reply, Request, State};
+
 #[derive(Clone, Debug)]
 pub struct Info {
     pub msg: String,
@@ -11,8 +35,20 @@ pub struct Info {
 
 const LOG_LEVEL:i16 = 0;
 
-const OUTPUT_BTM:u16 = 0xf000;
-const OUTPUT_TOP:u16 = 0xf100;
+pub const OUTPUT_BTM:u16 = 0xf000;
+pub const OUTPUT_TOP:u16 = 0xf100;
+
+/// Clock rates stepped through by Faster and Slower, in Hz. 0 is unthrottled.
+pub const CLOCK_RATES: [u64; 10] = [10, 100, 1_000, 10_000, 100_000, 500_000, 1_000_000, 1_789_773, 2_000_000, 0];
+/// Time slices per second when throttled. Instructions run in a burst
+/// for each slice and the thread sleeps until wall time catches up.
+const SLICES_PER_SECOND: u64 = 100;
+/// Where the processor starts after power on or reset
+pub const START_PC: u16 = 0x400;
+/// Cycles run between checks for messages when unthrottled
+const UNTHROTTLED_SLICE: u64 = 20_000;
+/// How far behind wall time we may fall before giving up on catching up
+const MAX_LAG: time::Duration = time::Duration::from_millis(250);
 
 #[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
 pub enum ADRESSING_MODE {
@@ -31,17 +67,13 @@ pub enum ADRESSING_MODE {
     NONE = 11,
 }
 
-pub enum ControllerMessage {
-    ButtonPressed(String),
-    UpdatedProcessorAvailable(Processor),
-    UpdatedDataAvailable(u16, u8),
-    UpdatedStackAvailable(Vec<u8>),
-    UpdatedOutputAvailable(Vec<u8>),
-}
-
-pub enum ComputerMessage {
-    ButtonPressed(String),
-    GetData(),
+/// Which processor to emulate, where the two behave differently
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    /// The original 6502
+    Nmos,
+    /// The 65C02, which fixes up decimal results
+    Cmos,
 }
 
 #[derive(Clone, Debug)]
@@ -58,122 +90,659 @@ pub struct Processor {
     pub inst: u8,
 }
 
+impl Processor {
+    pub fn new() -> Processor {
+        Processor {
+            flags: 0,
+            acc: 0,
+            rx: 0,
+            ry: 0,
+            pc: START_PC,
+            sp: 0,
+            test: vec![],
+            info: vec![],
+            clock: 0,
+            inst: 0xea,
+        }
+    }
+}
+
+impl Default for Processor {
+    fn default() -> Processor {
+        Processor::new()
+    }
+}
+
+/// The preset rate above or below `hz`, for stepping through `CLOCK_RATES`
+pub fn next_clock_rate(hz: u64, faster: bool) -> u64 {
+    let order = |hz: u64| if hz == 0 { u64::MAX } else { hz };
+    let current = order(hz);
+    let next = if faster {
+        CLOCK_RATES.iter().find(|&&rate| order(rate) > current)
+    } else {
+        CLOCK_RATES.iter().rev().find(|&&rate| order(rate) < current)
+    };
+    *next.unwrap_or(&hz)
+}
+
 #[derive(Debug)]
 pub struct Computer {
     processor: Processor,
     paused: bool,
-    step: bool,
-    start: bool,
-    speed: u64,
-    data: Vec<u8>,
-    tx: mpsc::Sender<ControllerMessage>,
-    rx: mpsc::Receiver<ComputerMessage>,
+    /// Instructions left to run for a `Step` command, and its request id
+    steps: u32,
+    step_request: Option<u64>,
+    /// Address and lowest stack pointer a step over or out runs until
+    run_until: Option<(u16, u8)>,
+    /// Whether each address has a breakpoint
+    breakpoints: Vec<bool>,
+    /// Whether writes to each address are recorded in `writes`
+    watched: Vec<bool>,
+    writes: Vec<(u16, u8)>,
+    /// Target clock rate in Hz, 0 when unthrottled
+    target_hz: u64,
+    /// Wall time and cycle count the pacing is measured from. The time is
+    /// only read when a throttled slice starts, as that panics on wasm32.
+    pace_start: Option<time::Instant>,
+    pace_clock: u64,
+    data: Memory,
+    /// Memory generation the controller has seen
+    shown_generation: u64,
+    /// Coverage generation the controller has seen
+    shown_coverage: u64,
+    devices: Vec<Box<dyn Device>>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    call_stack: CallStack,
+    variant: Variant,
+    /// Whether instructions run a bus cycle at a time through `cycle`
+    cycle_exact: bool,
+    cycle: CycleCpu,
+    /// Whether a device pulled the IRQ line low on the last cycle
+    irq: bool,
+    /// Set by `Command::Shutdown`, after which `step` returns false
+    stopped: bool,
+    tx: mpsc::Sender<Event>,
+    rx: mpsc::Receiver<Request>,
 }
+const OPCODE_JSR: u8 = 0x20;
+
 const FLAG_C: u8 = 1;
 const FLAG_Z: u8 = 2;
 const FLAG_I: u8 = 4;
 const FLAG_D: u8 = 8;
+const FLAG_B: u8 = 0x10;
+const FLAG_UNUSED: u8 = 0x20;
 const FLAG_O: u8 = 0x40;
 const FLAG_N: u8 = 0x80;
 
 
 
 impl Computer {
-    pub fn new(tx: mpsc::Sender<ControllerMessage>, rx:  mpsc::Receiver<ComputerMessage>, data: Vec<u8>) -> Computer {
+    pub fn new(tx: mpsc::Sender<Event>, rx:  mpsc::Receiver<Request>, data: Vec<u8>) -> Computer {
         let mut computer = Computer {
-            data,
+            data: Memory::new(data),
+            shown_generation: 0,
+            shown_coverage: 0,
+            devices: vec![],
+            profiler: None,
+            coverage: None,
+            call_stack: CallStack::new(),
+            variant: Variant::Nmos,
+            cycle_exact: false,
+            cycle: CycleCpu::new(Variant::Nmos),
+            irq: false,
+            stopped: false,
             tx,
             rx,
             paused: true,
-            start: true,
-            step: false,
-            speed: 0,
-            processor: Processor {
-                flags: 0b00110000,
-                acc: 0,
-                rx: 0,
-                ry: 0,
-                /// Start at 0x400
-                pc: 0x400,
-                sp: 0,
-                test: vec![],
-                info: vec![],
-                clock: 0,
-                inst: 0xea,
-            }
+            steps: 0,
+            step_request: None,
+            run_until: None,
+            breakpoints: vec![false; 0x10000],
+            watched: vec![false; 0x10000],
+            writes: vec![],
+            target_hz: 0,
+            pace_start: None,
+            pace_clock: 0,
+            processor: Processor::new(),
         };
         computer
     }
 
+    /// A computer with no front end, driven directly through `run_one`,
+    /// `run_to_trap` and `tick`. Its events go nowhere and `step` returns
+    /// false straight away.
+    pub fn headless(data: Vec<u8>) -> Computer {
+        let (tx, _) = mpsc::channel();
+        let (_, rx) = mpsc::channel();
+        Computer::new(tx, rx, data)
+    }
+
+    /// Attach a memory mapped device. Accesses to the addresses it
+    /// handles are routed to it instead of to memory.
+    pub fn add_device(&mut self, mut device: Box<dyn Device>) {
+        self.add_info(format!("Attached {}", device.name()));
+        device.map(&mut self.data);
+        self.devices.push(device);
+    }
+
+    /// Start counting executions and cycles per address and routine
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    /// Set the target clock rate in Hz, 0 to run as fast as possible
+    pub fn set_clock(&mut self, hz: u64) {
+        self.target_hz = hz;
+        self.reset_pacing();
+    }
+
+    fn reset_pacing(&mut self) {
+        self.pace_start = None;
+        self.pace_clock = self.processor.clock;
+    }
+
+    /// Emulate the 6502 or the 65C02, the 6502 by default. Only decimal
+    /// arithmetic differs so far. Call it before running.
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        self.cycle = CycleCpu::new(variant);
+    }
+
+    /// Run instructions one bus cycle at a time, with the dummy reads and
+    /// writes of the real processor and the devices ticked after every
+    /// cycle, instead of an instruction at a time
+    pub fn set_cycle_exact(&mut self, on: bool) {
+        self.cycle_exact = on;
+    }
+
+    /// Start recording which bytes are executed, read and written
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    fn cover(&mut self, address: u16, access: u8) {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark(address, access);
+        }
+    }
+
+    /// Mark both bytes of a pointer or vector as read
+    fn cover_word(&mut self, address: u16) {
+        self.cover(address, coverage::READ);
+        self.cover(address.wrapping_add(1), coverage::READ);
+    }
+
+    /// Banked windows, in the order their devices were attached
+    fn bank_infos(&self) -> Vec<BankInfo> {
+        self.devices.iter().filter_map(|d| d.bank_info()).collect()
+    }
+
+    /// Handle the pending commands, then run a step or a time slice, or
+    /// wait for a command while paused. Returns false once the front end
+    /// has sent `Command::Shutdown` or dropped its end of the channel, when
+    /// the thread driving the computer should end.
     pub fn step(&mut self) -> bool {
-        while let Some(message) = self.rx.try_iter().next() {
-            // Handle messages arriving from the controller.
-            match message {
-                ComputerMessage::ButtonPressed(btn) => {
-                    if btn == "faster" && self.speed > 0 {
-                        if (self.speed >= 4) {
-                            self.speed /= 2;
-                        } else if self.speed >= 1 {
-                            self.speed -= 1;
-                        }
-                    } else if btn == "slower" && self.speed <= 10000 {
-                        if (self.speed >= 2) {
-                            self.speed *= 2;
-                        } else {
-                            self.speed += 2;
-                        }
-                    } else if btn == "pause" {
-                        self.paused = !self.paused;
-                    } else if btn == "step" {
-                        self.step = true;
-                    }
-                },
-                ComputerMessage::GetData() => {
-                    //if (self.paused && self.step) || self.start {
-                        self.start = false;
-                        let l = self.processor.info.len();
-                        if l > 30 {
-                            self.processor.info = self.processor.info[l-30..].to_vec();
-                        }
-                        //println!("{:?}", self.processor);
-                        self.processor.test = self.data[0x200 as usize .. 0x220 as usize].to_vec();
-                        self.tx.send(
-                            ControllerMessage::UpdatedProcessorAvailable(self.processor.clone())
-                        );
-    
-                        let stack = self.data[0x100 as usize..=0x1ff as usize].to_vec();
-    
-                        self.tx.send(
-                            ControllerMessage::UpdatedStackAvailable(stack)
-                        );
-    
-                        let output = self.data[OUTPUT_BTM as usize..OUTPUT_TOP as usize].to_vec();
-    
-                        self.tx.send(
-                            ControllerMessage::UpdatedOutputAvailable(output)
-                        );
-                    //}
-                },
-            };
+        loop {
+            match self.rx.try_recv() {
+                Ok(request) => self.handle(request),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return false,
+            }
+        }
+        if self.stopped {
+            return false;
         }
 
-        if self.paused && !self.step {
-            thread::sleep(time::Duration::from_millis(100));
-            return true;
+        if self.paused && self.steps == 0 {
+            // Nothing to run, so wait for the next command
+            match self.rx.recv_timeout(time::Duration::from_millis(100)) {
+                Ok(request) => self.handle(request),
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return false,
+            }
+            return !self.stopped;
         }
 
-        if (self.paused && self.step) || !self.paused {
-            self.step = false;
-            let changed = self.run_instruction();
-            if self.speed > 0 {
-                thread::sleep(time::Duration::from_millis(self.speed));
+        if self.paused {
+            self.execute();
+            self.steps -= 1;
+            if !self.hit_breakpoint() && self.steps == 0 {
+                self.finish_steps();
             }
+        } else {
+            self.run_slice();
+        }
+
+        true
+    }
+
+    /// Carry out a command from the front end and reply to it. `Step` is
+    /// replied to once its instructions have run.
+    fn handle(&mut self, request: Request) {
+        let reply = match request.command {
+            Command::WriteMemory(..) | Command::FillMemory(..) | Command::CopyMemory(..) | Command::SetRegister(..) if !self.paused => {
+                Reply::Error("pause before editing registers or memory".to_string())
+            },
+            Command::Run => {
+                self.finish_steps();
+                self.paused = false;
+                self.reset_pacing();
+                Reply::Done
+            },
+            Command::Pause => {
+                self.finish_steps();
+                self.paused = true;
+                Reply::Done
+            },
+            Command::Step(count) => {
+                self.finish_steps();
+                self.paused = true;
+                if count == 0 {
+                    Reply::Done
+                } else {
+                    self.steps = count;
+                    self.step_request = Some(request.id);
+                    return;
+                }
+            },
+            Command::StepOver => {
+                self.finish_steps();
+                self.paused = true;
+                if self.data[self.processor.pc as usize] == OPCODE_JSR {
+                    self.run_to(self.processor.pc.wrapping_add(3), self.processor.sp);
+                } else {
+                    self.steps = 1;
+                }
+                self.step_request = Some(request.id);
+                return;
+            },
+            Command::StepOut => {
+                self.finish_steps();
+                self.paused = true;
+                match self.call_stack.frames().last() {
+                    Some(frame) => {
+                        let (addr, sp) = (frame.return_addr, frame.sp);
+                        self.run_to(addr, sp);
+                        self.step_request = Some(request.id);
+                        return;
+                    },
+                    None => Reply::Error("no call to step out of".to_string()),
+                }
+            },
+            Command::SetSpeed(hz) => {
+                self.set_clock(hz);
+                Reply::Done
+            },
+            Command::SetBreakpoint(addr, set) => {
+                self.set_breakpoint(addr, set);
+                Reply::Done
+            },
+            Command::ReadMemory(range) => {
+                if range.start > range.end || range.end > 0x10000 {
+                    Reply::Error(format!("cannot read {:#x}..{:#x}", range.start, range.end))
+                } else {
+                    Reply::Memory(range.start, self.data.bytes()[range].to_vec())
+                }
+            },
+            Command::WriteMemory(addr, bytes) => match self.load(addr, &bytes) {
+                Ok(()) => Reply::Done,
+                Err(e) => Reply::Error(e),
+            },
+            Command::FillMemory(range, value) => {
+                if range.start > range.end || range.end > 0x10000 {
+                    Reply::Error(format!("cannot fill {:#x}..{:#x}", range.start, range.end))
+                } else {
+                    self.data.write_range(range.start, &vec![value; range.len()]);
+                    Reply::Done
+                }
+            },
+            Command::CopyMemory(range, to) => {
+                if range.start > range.end || range.end > 0x10000 || to as usize + range.len() > 0x10000 {
+                    Reply::Error(format!("cannot copy {:#x}..{:#x} to {:#06x}", range.start, range.end, to))
+                } else {
+                    let bytes = self.data.bytes()[range].to_vec();
+                    self.data.write_range(to as usize, &bytes);
+                    Reply::Done
+                }
+            },
+            Command::SetRegister(register, value) => match self.set_register(register, value) {
+                Ok(()) => Reply::Done,
+                Err(e) => Reply::Error(e),
+            },
+            Command::Reset => {
+                self.finish_steps();
+                self.reset();
+                Reply::Done
+            },
+            Command::GetState => Reply::State(self.state()),
+            Command::GetBank(window, bank) => {
+                let data = self.data.bytes();
+                let contents = self.devices.iter()
+                    .filter(|d| d.bank_info().is_some())
+                    .nth(window)
+                    .and_then(|d| d.read_bank(bank, data));
+                match contents {
+                    Some(contents) => Reply::Bank(window, bank, contents),
+                    None => Reply::Error(format!("no bank {} in window {}", bank, window)),
+                }
+            },
+            Command::GetProfile => match &self.profiler {
+                Some(profiler) => Reply::Profile(profiler.clone()),
+                None => Reply::Error("profiling is off".to_string()),
+            },
+            Command::GetCoverage => match &self.coverage {
+                Some(coverage) => Reply::Coverage(coverage.clone()),
+                None => Reply::Error("coverage is off".to_string()),
+            },
+            Command::Shutdown => {
+                self.finish_steps();
+                self.paused = true;
+                self.stopped = true;
+                Reply::Done
+            },
+        };
+        // The front end may have quit, or never listen when the computer
+        // is driven headless, and either way there is nobody to tell
+        let _ = self.tx.send(Event::Reply(request.id, reply));
+    }
+
+    /// Snapshot for the front end, with the memory written since the last one
+    fn state(&mut self) -> State {
+        let l = self.processor.info.len();
+        if l > 30 {
+            self.processor.info = self.processor.info[l-30..].to_vec();
+        }
+        self.processor.test = self.data.bytes()[0x200..0x220].to_vec();
+
+        let memory = self.data.changes(self.shown_generation);
+        self.shown_generation = memory.generation;
+        let coverage = match &self.coverage {
+            Some(coverage) if coverage.generation() != self.shown_coverage => {
+                self.shown_coverage = coverage.generation();
+                Some(coverage.clone())
+            },
+            _ => None,
+        };
+        State {
+            processor: self.processor.clone(),
+            memory,
+            paused: self.paused,
+            target_hz: self.target_hz,
+            banks: self.bank_infos(),
+            coverage,
+            call_stack: self.call_stack.frames().to_vec(),
+        }
+    }
+
+    pub fn set_register(&mut self, register: Register, value: u16) -> Result<(), String> {
+        if register != Register::PC && value > 0xff {
+            return Err(format!("{:#x} does not fit in {:?}", value, register));
+        }
+        let byte = value as u8;
+        match register {
+            Register::A => self.processor.acc = byte,
+            Register::X => self.processor.rx = byte,
+            Register::Y => self.processor.ry = byte,
+            Register::SP => self.processor.sp = byte,
+            Register::PC => self.processor.pc = value,
+            Register::Flags => self.processor.flags = byte & !(FLAG_B | FLAG_UNUSED),
+        }
+        Ok(())
+    }
+
+    /// Run until execution reaches `addr` with the stack no deeper than `sp`
+    fn run_to(&mut self, addr: u16, sp: u8) {
+        self.run_until = Some((addr, sp));
+        self.paused = false;
+        self.reset_pacing();
+    }
+
+    /// Pause if a step over or out has come back to where it was headed
+    fn reached_target(&mut self) -> bool {
+        match self.run_until {
+            Some((addr, sp)) if self.processor.pc == addr && callstack::returned_past(self.processor.sp, sp) => {
+                self.paused = true;
+                self.finish_steps();
+                true
+            },
+            _ => false,
         }
+    }
 
+    /// Stop stepping and reply to the step command that started it
+    fn finish_steps(&mut self) {
+        self.steps = 0;
+        self.run_until = None;
+        if let Some(id) = self.step_request.take() {
+            // Fine if the front end is gone, as in `handle`
+            let _ = self.tx.send(Event::Reply(id, Reply::Done));
+        }
+    }
+
+    /// Pause if the next instruction has a breakpoint on it
+    fn hit_breakpoint(&mut self) -> bool {
+        let pc = self.processor.pc;
+        if !self.breakpoints[pc as usize] {
+            return false;
+        }
+        self.paused = true;
+        self.finish_steps();
+        self.add_info(format!("{:#x} - Breakpoint", pc));
+        // Fine if the front end is gone, as in `handle`
+        let _ = self.tx.send(Event::Breakpoint(pc));
         true
     }
 
+    /// Put the processor back in its power on state. Memory, devices and
+    /// the cycle count are left alone.
+    pub fn reset(&mut self) {
+        let clock = self.processor.clock;
+        self.processor = Processor::new();
+        self.processor.clock = clock;
+        self.cycle = CycleCpu::new(self.variant);
+        self.call_stack.clear();
+        self.add_info(format!("Reset to {:#x}", START_PC));
+    }
+
+    /// Run flat out without a UI until the program traps in a jump or
+    /// branch to itself, or `max_cycles` have passed. Returns the number
+    /// of instructions executed.
+    pub fn run_to_trap(&mut self, max_cycles: u64) -> u64 {
+        let end = self.processor.clock + max_cycles;
+        let mut instructions = 0;
+        while self.processor.clock < end {
+            let pc = self.processor.pc;
+            self.execute();
+            instructions += 1;
+            if self.processor.pc == pc {
+                break;
+            }
+        }
+        instructions
+    }
 
+    pub fn processor(&self) -> &Processor {
+        &self.processor
+    }
+
+    /// The whole address space as stored, without going through devices
+    pub fn memory(&self) -> &[u8] {
+        self.data.bytes()
+    }
+
+    /// Whether the next `tick` starts a new instruction rather than
+    /// carrying on with one
+    pub fn at_boundary(&self) -> bool {
+        self.cycle.at_boundary()
+    }
+
+    /// Run one instruction, for front ends that drive the core directly
+    /// rather than through `step`. Returns the cycles it took.
+    pub fn run_one(&mut self) -> u64 {
+        self.execute()
+    }
+
+    pub fn set_breakpoint(&mut self, addr: u16, set: bool) {
+        self.breakpoints[addr as usize] = set;
+    }
+
+    pub fn is_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints[addr as usize]
+    }
+
+    /// Start or stop recording what the program writes to `addr`, devices
+    /// included
+    pub fn watch_writes(&mut self, addr: u16, watch: bool) {
+        self.watched[addr as usize] = watch;
+    }
+
+    /// Watched writes since the last call, as address and value
+    pub fn take_writes(&mut self) -> Vec<(u16, u8)> {
+        mem::take(&mut self.writes)
+    }
+
+    /// Copy `bytes` into memory at `addr`, bypassing devices
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) -> Result<(), String> {
+        if addr as usize + bytes.len() > 0x10000 {
+            return Err(format!("{} bytes do not fit at {:#06x}", bytes.len(), addr));
+        }
+        self.data.write_range(addr as usize, bytes);
+        Ok(())
+    }
+
+    /// Run one time slice worth of cycles, then sleep until wall time
+    /// catches up with the emulated clock
+    fn run_slice(&mut self) {
+        if self.target_hz != 0 && self.pace_start.is_none() {
+            self.pace_start = Some(time::Instant::now());
+        }
+        let cycles = if self.target_hz == 0 { UNTHROTTLED_SLICE } else { (self.target_hz / SLICES_PER_SECOND).max(1) };
+        let end = self.processor.clock + cycles;
+        let hz = self.target_hz;
+        while self.processor.clock < end && !self.paused && self.target_hz == hz {
+            // An instruction that takes no cycles would never end the slice
+            if self.execute() == 0 || self.hit_breakpoint() || self.reached_target() {
+                break;
+            }
+        }
+        if self.target_hz == 0 {
+            return;
+        }
+
+        let nanos = (self.processor.clock - self.pace_clock) as u128 * 1_000_000_000 / self.target_hz as u128;
+        let emulated = time::Duration::from_nanos(nanos as u64);
+        let elapsed = self.pace_start.get_or_insert_with(time::Instant::now).elapsed();
+        if emulated > elapsed {
+            thread::sleep(emulated - elapsed);
+        } else if elapsed - emulated > MAX_LAG {
+            // The host can't keep up, or we were stopped for a while. Start
+            // over rather than running flat out to make up for lost time.
+            self.reset_pacing();
+        }
+    }
+
+    /// Run one instruction and let the devices catch up with it,
+    /// returning the cycles it took
+    fn execute(&mut self) -> u64 {
+        let clock = self.processor.clock;
+        if self.cycle_exact {
+            // An interrupt taken on the way counts as part of the instruction
+            loop {
+                self.tick();
+                if let Some(Completed::Instruction { .. }) = self.cycle.completed() {
+                    return self.processor.clock - clock;
+                }
+            }
+        }
+        // Finish any instruction `tick` left halfway
+        while !self.cycle.at_boundary() {
+            self.tick();
+        }
+        let clock = self.processor.clock;
+        let pc = self.processor.pc;
+        let sp = self.processor.sp;
+        let cycles = self.instruction_cycles();
+        self.run_instruction();
+        self.processor.clock = clock + cycles;
+        self.retire(pc, self.processor.inst, sp, cycles);
+        self.tick_devices(cycles);
+        cycles
+    }
+
+    /// Run one clock cycle of the cycle stepped core and of the devices,
+    /// returning the bus access the processor made. Works whether or not
+    /// the cycle exact mode is on, so a front end can interleave its own
+    /// hardware with the processor cycle by cycle.
+    pub fn tick(&mut self) -> BusCycle {
+        let mut cpu = self.cycle;
+        let access = cpu.tick(self);
+        self.cycle = cpu;
+        match cpu.completed() {
+            Some(Completed::Instruction { pc, opcode, sp, cycles }) => {
+                self.retire(pc, opcode, sp, cycles);
+                if !decode::is_documented(opcode) {
+                    self.set_clock(CLOCK_RATES[0]);
+                }
+            },
+            Some(Completed::Interrupt { pc, sp, handler }) => self.record_interrupt(pc, sp, handler),
+            None => {},
+        }
+        self.irq = self.poll_devices(1);
+        access
+    }
+
+    /// Let the call stack, profiler and coverage know an instruction ran
+    fn retire(&mut self, pc: u16, opcode: u8, sp: u8, cycles: u64) {
+        self.call_stack.record(pc, opcode, sp, self.processor.pc, self.processor.sp);
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(pc, opcode, sp, cycles, self.processor.pc, self.processor.sp);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark_instruction(pc, opcode);
+        }
+    }
+
+    /// Cycles the instruction at PC is going to take: the count from the
+    /// table, a cycle more for a taken branch and another when it or an
+    /// indexed read crosses a page, and one for decimal ADC and SBC on the
+    /// 65C02
+    fn instruction_cycles(&self) -> u64 {
+        let pc = self.processor.pc;
+        let byte = |addr: u16| self.data[addr as usize];
+        let opcode = byte(pc);
+        let name = decode::get_opcode_name(opcode);
+        let mut cycles = decode::cycles(opcode);
+        if !decode::is_documented(opcode) {
+            return cycles;
+        }
+        if opcode & 0x1f == 0x10 {
+            // Bits 6 and 7 pick the flag, bit 5 the value it branches on
+            let flag = [FLAG_N, FLAG_O, FLAG_C, FLAG_Z][(opcode >> 6) as usize];
+            if (self.processor.flags & flag != 0) == (opcode & 0x20 != 0) {
+                let next = pc.wrapping_add(2);
+                let target = next.wrapping_add(byte(pc.wrapping_add(1)) as i8 as u16);
+                cycles += if next & 0xff00 == target & 0xff00 { 1 } else { 2 };
+            }
+        } else if decode::PAGE_PENALTY.contains(&name) {
+            let operand = byte(pc.wrapping_add(1));
+            let (base, index) = match decode::get_adressing_mode(opcode) {
+                ADRESSING_MODE::ABSOLUTE_X => (operand, self.processor.rx),
+                ADRESSING_MODE::ABSOLUTE_Y => (operand, self.processor.ry),
+                ADRESSING_MODE::INDIRECT_Y => (byte(operand as u16), self.processor.ry),
+                _ => (0, 0),
+            };
+            // Only the low byte of the base matters
+            if base as u16 + index as u16 > 0xff {
+                cycles += 1;
+            }
+        }
+        if (name == "ADC" || name == "SBC") && self.variant == Variant::Cmos && self.processor.flags & FLAG_D != 0 {
+            cycles += 1;
+        }
+        cycles
+    }
 
     fn run_instruction(&mut self) {
         let inst = &self.data[(self.processor.pc) as usize];
@@ -247,6 +816,56 @@ impl Computer {
         };
     }
 
+    fn tick_devices(&mut self, cycles: u64) {
+        if self.poll_devices(cycles) && self.processor.flags & FLAG_I == 0 {
+            self.interrupt(0xfffe);
+        }
+    }
+
+    /// Advance the devices by `cycles`, returning whether any of them
+    /// pulls the IRQ line low
+    fn poll_devices(&mut self, cycles: u64) -> bool {
+        let mut irq = false;
+        for device in self.devices.iter_mut() {
+            device.tick(cycles);
+            irq |= device.irq();
+        }
+        irq
+    }
+
+    /// Service a hardware interrupt through the vector at `vector`
+    fn interrupt(&mut self, vector: u16) {
+        let sp = 0x100 + self.processor.sp as u16;
+        let sp1 = 0x100 + self.processor.sp.wrapping_sub(1) as u16;
+        let sp2 = 0x100 + self.processor.sp.wrapping_sub(2) as u16;
+        let this_pc = self.processor.pc;
+        self.write_byte(sp, ((this_pc>>8) & 0xff) as u8);
+        self.write_byte(sp1, (this_pc & 0xff) as u8);
+        // Hardware interrupts push the flags with B clear
+        self.write_byte(sp2, self.processor.flags | FLAG_UNUSED);
+
+        self.processor.flags |= FLAG_I;
+        self.processor.sp = self.processor.sp.wrapping_sub(3);
+
+        self.cover_word(vector);
+        let new_addr: u16 = self.get_word(vector);
+        if LOG_LEVEL > 0 {
+            self.add_info(format!("{:#x} - Interrupt to: {:#x} flags: {:#b}", self.processor.pc, new_addr, self.processor.flags));
+        }
+        self.record_interrupt(this_pc, self.processor.sp.wrapping_add(3), new_addr);
+        self.processor.pc = new_addr;
+        self.processor.clock += 7;
+    }
+
+    /// Let the call stack and profiler know an interrupt left `pc` for
+    /// `handler`, with the stack at `sp`
+    fn record_interrupt(&mut self, pc: u16, sp: u8, handler: u16) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.interrupt(pc, sp, handler);
+        }
+        self.call_stack.interrupt(pc, sp, handler);
+    }
+
     fn add_info(&mut self, info: String) {
 
         let len = self.processor.info.len();
@@ -264,17 +883,15 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction cld: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
         self.processor.flags = self.processor.flags & !FLAG_D;
-        self.processor.clock += 2;
     }
 
     fn txs(&mut self) {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction txs: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
         self.processor.sp = self.processor.rx;
     }
 
@@ -283,8 +900,7 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction tsx: {:#x} val: {:#x} flags:{:#x} ", self.processor.pc, self.data[(self.processor.pc) as usize], self.processor.sp, self.processor.flags));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
         self.processor.rx = self.processor.sp;
     }
 
@@ -292,8 +908,7 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction tya: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
         self.processor.acc = self.processor.ry;
         self.processor.flags = Self::set_flags(self.processor.flags, self.processor.acc);
     }
@@ -302,8 +917,7 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction tay: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
         self.processor.ry = self.processor.acc;
         self.processor.flags = Self::set_flags(self.processor.flags, self.processor.ry);
     }
@@ -312,8 +926,7 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction tax: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
         self.processor.rx = self.processor.acc;
         self.processor.flags = Self::set_flags(self.processor.flags, self.processor.rx);
     }
@@ -323,8 +936,7 @@ impl Computer {
             self.add_info(format!("{:#x} - Running instruction txa: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
         self.processor.flags = Self::set_flags(self.processor.flags, self.processor.rx);
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
         self.processor.acc = self.processor.rx;
     }
 
@@ -333,13 +945,11 @@ impl Computer {
         // Place current address on stack
         let sp: u16 = (self.processor.sp as u16 + 0x100 as u16).into();
         let sp1: u16 = (self.processor.sp.wrapping_sub(1) as u16 + 0x100 as u16).into();
-        let mut _addr = self.data.to_vec();
-        let this_pc = self.processor.pc + 2;
-        _addr[sp as usize] = ((this_pc>>8) & 0xff) as u8;
-        _addr[sp1 as usize] = (this_pc & 0xff) as u8;
-        self.data = _addr;
+        let this_pc = self.processor.pc.wrapping_add(2);
+        self.write_byte(sp, ((this_pc>>8) & 0xff) as u8);
+        self.write_byte(sp1, (this_pc & 0xff) as u8);
         // Send to new address
-        let addr = self.get_word(self.processor.pc + 1);
+        let addr = self.get_word(self.processor.pc.wrapping_add(1));
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction jsr to: {:#x}", self.processor.pc, addr));
         }
@@ -351,25 +961,22 @@ impl Computer {
         let sp: u16 = (self.processor.sp as u16 + 0x100 as u16).into();
         let sp1: u16 = (self.processor.sp.wrapping_sub(1) as u16 + 0x100 as u16).into();
         let sp2: u16 = (self.processor.sp.wrapping_sub(2) as u16 + 0x100 as u16).into();
-        let mut _addr = self.data.to_vec();
-        let this_pc = self.processor.pc + 2;
-        _addr[sp as usize] = ((this_pc>>8) & 0xff) as u8;
-        _addr[sp1 as usize] = (this_pc & 0xff) as u8;
-        
-        _addr[sp2 as usize] = (self.processor.flags) | 0x30;
+        let this_pc = self.processor.pc.wrapping_add(2);
+        self.write_byte(sp, ((this_pc>>8) & 0xff) as u8);
+        self.write_byte(sp1, (this_pc & 0xff) as u8);
+        // BRK pushes the flags with B set, so the handler can tell it from an IRQ
+        self.write_byte(sp2, self.processor.flags | FLAG_B | FLAG_UNUSED);
 
         self.processor.flags |= FLAG_I;
         self.processor.sp = self.processor.sp.wrapping_sub(3);
-        
-        self.data = _addr;
 
+        self.cover_word(0xfffe);
         let new_addr: u16 = self.get_word(0xfffe);
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction brk ({:#x}) to: {:#x} flags: {:#b}", self.processor.pc, self.processor.inst, new_addr, self.processor.flags));
         }
         self.processor.pc = new_addr;
 
-        self.processor.clock += 7;
     }
 
     fn rti(&mut self) {
@@ -377,11 +984,11 @@ impl Computer {
         let sp1: u16 = (self.processor.sp.wrapping_add(1) as u16 + 0x100 as u16).into();
         let sp2: u16 = (self.processor.sp.wrapping_add(2) as u16 + 0x100 as u16).into();
         let sp3: u16 = (self.processor.sp.wrapping_add(3) as u16 + 0x100 as u16).into();
-        let high_byte = self.data[sp3 as usize];
-        let low_byte = self.data[sp2 as usize];
-        let flags = self.data[sp1 as usize];
-        // Unset interrupt disabled flag
-        self.processor.flags = flags;
+        let flags = self.read_byte(sp1);
+        let low_byte = self.read_byte(sp2);
+        let high_byte = self.read_byte(sp3);
+        // B and bit 5 exist only on the stack
+        self.processor.flags = flags & !(FLAG_B | FLAG_UNUSED);
         let addr: u16 = low_byte as u16 | ((high_byte as u16) << 8) as u16;
         // Send to new address
         if LOG_LEVEL > 0 {
@@ -389,23 +996,21 @@ impl Computer {
         }
         self.processor.sp = self.processor.sp.wrapping_add(3);
         self.processor.pc = addr;
-        self.processor.clock += 6;
     }
 
     fn rts(&mut self) {
         // Place current address on stack
         let sp1: u16 = (self.processor.sp.wrapping_add(1) as u16 + 0x100 as u16).into();
         let sp2: u16 = (self.processor.sp.wrapping_add(2) as u16 + 0x100 as u16).into();
-        let low_byte = self.data[sp1 as usize];
-        let high_byte = self.data[sp2 as usize];
+        let low_byte = self.read_byte(sp1);
+        let high_byte = self.read_byte(sp2);
         let addr: u16 = low_byte as u16 | ((high_byte as u16) << 8) as u16;
         // Send to new address
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction rts to: {:#x}", self.processor.pc, addr));
         }
         self.processor.sp = self.processor.sp.wrapping_add(2);
-        self.processor.pc = addr + 1;
-        self.processor.clock += 6;
+        self.processor.pc = addr.wrapping_add(1);
     }
 
     /// Clear carry flag
@@ -414,8 +1019,7 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction clc: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     /// Set carry flag
@@ -424,8 +1028,7 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction sec: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     /// Set decimal flag
@@ -434,8 +1037,7 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction sed: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     /// Clear interrupt disabled flag
@@ -444,8 +1046,7 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction cli: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     /// Set interrupt disabled flag
@@ -454,8 +1055,7 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction sei: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     /// clear overflow flag
@@ -464,44 +1064,31 @@ impl Computer {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction clv: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     /// Push accumulator to stack
     fn pha(&mut self) {
         let addr: u16 = (self.processor.sp as u16 + 0x100 as u16).into();
         
-        let mut _addr = self.data.to_vec();
-        _addr[addr as usize] = self.processor.acc;
-        self.tx.send(
-            ControllerMessage::UpdatedDataAvailable(addr,  self.processor.acc)
-        );
-        self.data = _addr;
+        self.write_byte(addr, self.processor.acc);
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction pha at: {:#x} val: {:#x}", self.processor.pc, addr, self.processor.acc));
         }
         self.processor.sp = self.processor.sp.wrapping_sub(1);
-        self.processor.pc += 1;
-        self.processor.clock += 3;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     /// Push flags to stack
     fn php(&mut self) {
         let addr: u16 = (self.processor.sp as u16 + 0x100 as u16).into();
         
-        let mut _addr = self.data.to_vec();
-        _addr[addr as usize] = self.processor.flags | 0x30;
-        self.tx.send(
-            ControllerMessage::UpdatedDataAvailable(addr,  self.processor.flags | 0x30)
-        );
-        self.data = _addr;
+        self.write_byte(addr, self.processor.flags | FLAG_B | FLAG_UNUSED);
         if LOG_LEVEL > 0 {
-            self.add_info(format!("{:#x} - Running instruction php at: {:#x} flags: {:#x}", self.processor.pc, addr, self.processor.flags | 0x30));
+            self.add_info(format!("{:#x} - Running instruction php at: {:#x} flags: {:#x}", self.processor.pc, addr, self.processor.flags | FLAG_B | FLAG_UNUSED));
         }
         self.processor.sp = self.processor.sp.wrapping_sub(1);
-        self.processor.pc += 1;
-        self.processor.clock += 3;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     /// Pull stack to accumulator
@@ -509,14 +1096,13 @@ impl Computer {
         self.processor.sp = self.processor.sp.wrapping_add(1);
         let addr: u16 = (self.processor.sp as u16 + 0x100 as u16).into();
         
-        self.processor.acc = self.data[addr as usize];
+        self.processor.acc = self.read_byte(addr);
         let flags = self.processor.flags;
         self.processor.flags = Self::set_flags(flags, self.processor.acc);
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction pla at: {:#x} val: {:#x}", self.processor.pc, addr, self.processor.acc));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 4;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     // 0X28 Pull value from the stack into the processor registers
@@ -524,12 +1110,11 @@ impl Computer {
         self.processor.sp = self.processor.sp.wrapping_add(1);
         let addr: u16 = (self.processor.sp as u16 + 0x100 as u16).into();
         
-        self.processor.flags = self.data[addr as usize];
+        self.processor.flags = self.read_byte(addr) & !(FLAG_B | FLAG_UNUSED);
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction plp at: {:#x} flags: {:#x}", self.processor.pc, addr, self.processor.flags));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 4;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
 
@@ -540,35 +1125,35 @@ impl Computer {
         }
 
         if addressing_mode == ADRESSING_MODE::IMMEDIATE {
-            return self.processor.pc + 1;
+            return self.processor.pc.wrapping_add(1);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE {
             //Absolute adressing
-            let start = self.processor.pc + 1;
+            let start = self.processor.pc.wrapping_add(1);
             let addr = self.get_word(start);
             return addr;
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE_X {
             //Absolute adressing
-            let start = self.processor.pc + 1;
+            let start = self.processor.pc.wrapping_add(1);
             let start_addr = self.get_word(start);
             let rx = self.processor.rx;
-            let addr: u16 = start_addr + (rx as u16);
+            let addr: u16 = start_addr.wrapping_add(rx as u16);
             if LOG_LEVEL > 2 {
                 self.add_info(format!("{:#x} - Getting absolute_x address from: {:#x} ry: {:#x} gives: {:#x}", self.processor.pc, start_addr, rx, addr));
             }
             return addr;
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE_Y {
             //Absolute adressing
-            let start = self.processor.pc + 1;
+            let start = self.processor.pc.wrapping_add(1);
             let start_addr = self.get_word(start);
             let ry = self.processor.ry;
-            let addr: u16 = start_addr + (ry as u16);
+            let addr: u16 = start_addr.wrapping_add(ry as u16);
             if LOG_LEVEL > 2 {
                 self.add_info(format!("{:#x} - Getting absolute_y address from: {:#x} ry: {:#x} gives: {:#x}", self.processor.pc, start_addr, ry, addr));
             }
             return addr;
         } else if addressing_mode == ADRESSING_MODE::ZERO_PAGE {
             //Absolute adressing
-            let start = self.processor.pc + 1;
+            let start = self.processor.pc.wrapping_add(1);
             let addr: u16 = self.data[start as usize].into();
             if LOG_LEVEL > 2 {
                 self.add_info(format!("{:#x} - Getting ZERO_PAGE address from: {:#x} gives: {:#x}", self.processor.pc, start, addr));
@@ -576,7 +1161,7 @@ impl Computer {
             return addr;
         } else if addressing_mode == ADRESSING_MODE::ZERO_PAGE_Y {
             //Absolute adressing
-            let start = self.processor.pc + 1;
+            let start = self.processor.pc.wrapping_add(1);
             let start_addr = self.data[start as usize].wrapping_add(self.processor.ry);
             let addr: u16 = start_addr.into();
             if LOG_LEVEL > 2 {
@@ -585,7 +1170,7 @@ impl Computer {
             return addr;
         } else if addressing_mode == ADRESSING_MODE::ZERO_PAGE_X {
             //Absolute adressing
-            let start = self.processor.pc + 1;
+            let start = self.processor.pc.wrapping_add(1);
             let start_addr = self.data[start as usize].wrapping_add(self.processor.rx);
             let addr: u16 = start_addr.into();
             if LOG_LEVEL > 2 {
@@ -594,19 +1179,19 @@ impl Computer {
             return addr;
         } else if addressing_mode == ADRESSING_MODE::INDIRECT_Y {
             //Absolute adressing
-            let start = self.processor.pc + 1;
+            let start = self.processor.pc.wrapping_add(1);
             let zp_addr = self.data[start as usize];
-            let base_addr = self.get_word(zp_addr.into());
-            let addr: u16 = base_addr + self.processor.ry as u16;
+            let base_addr = self.zero_page_word(zp_addr);
+            let addr: u16 = base_addr.wrapping_add(self.processor.ry as u16);
             if LOG_LEVEL > 2 {
                 self.add_info(format!("{:#x} - Getting INDIRECT_Y address from: {:#x} with ry: {:#x} gives: {:#x}", self.processor.pc, start, self.processor.ry, addr));
             }
             return addr;
         } else if addressing_mode == ADRESSING_MODE::INDIRECT_X {
             //Absolute adressing
-            let start = self.processor.pc + 1;
+            let start = self.processor.pc.wrapping_add(1);
             let zp_addr = self.data[start as usize].wrapping_add(self.processor.rx);
-            let addr: u16 = self.get_word(zp_addr.into());
+            let addr: u16 = self.zero_page_word(zp_addr);
             
             if LOG_LEVEL > 2 {
                 self.add_info(format!("{:#x} - Getting INDIRECT_X address from: {:#x} with ry: {:#x} gives: {:#x}", self.processor.pc, start, self.processor.ry, addr));
@@ -624,29 +1209,22 @@ impl Computer {
 
         let addr = self.get_ld_adddr(mode);
         if addressing_mode == ADRESSING_MODE::ZERO_PAGE || addressing_mode == ADRESSING_MODE::ZERO_PAGE_X {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction inc ZP with effective addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 2;
-            self.processor.clock += 5;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE || addressing_mode == ADRESSING_MODE::ABSOLUTE_X {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction inc ABS with effective addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 3;
-            self.processor.clock += 6;
+            self.processor.pc = self.processor.pc.wrapping_add(3);
         }
 
         let result = value.wrapping_add(1);
 
-        let mut _addr = self.data.to_vec();
-        _addr[addr as usize] = result;
-        self.data = _addr;
-        self.tx.send(
-            ControllerMessage::UpdatedDataAvailable(addr, result)
-        );
+        self.write_byte(addr, result);
 
         self.processor.flags = Self::set_flags(self.processor.flags, result);
     }
@@ -658,30 +1236,23 @@ impl Computer {
 
         let addr = self.get_ld_adddr(mode);
         if addressing_mode == ADRESSING_MODE::ZERO_PAGE || addressing_mode == ADRESSING_MODE::ZERO_PAGE_X {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction dec ZP with effective addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 2;
-            self.processor.clock += 5;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE || addressing_mode == ADRESSING_MODE::ABSOLUTE_X {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction dec ABS with effective addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 3;
-            self.processor.clock += 6;
+            self.processor.pc = self.processor.pc.wrapping_add(3);
         }
 
         let result = value.wrapping_sub(1);
 
-        self.tx.send(
-            ControllerMessage::UpdatedDataAvailable(addr, result)
-        );
 
-        let mut _addr = self.data.to_vec();
-        _addr[addr as usize] = result;
-        self.data = _addr;
+        self.write_byte(addr, result);
 
         self.processor.flags = Self::set_flags(self.processor.flags, result);
     }
@@ -694,26 +1265,23 @@ impl Computer {
         let addr = self.get_ld_adddr(mode);
 
         if addressing_mode == ADRESSING_MODE::IMMEDIATE {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction ldx val: {:#x}", self.processor.pc, value));
             }
-            self.processor.pc += 2;
-            self.processor.clock += 2;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE || addressing_mode == ADRESSING_MODE::ABSOLUTE_X || addressing_mode == ADRESSING_MODE::ABSOLUTE_Y {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction ldx absolute with addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 3;
-            self.processor.clock += 4;
+            self.processor.pc = self.processor.pc.wrapping_add(3);
         }else if addressing_mode == ADRESSING_MODE::ZERO_PAGE || addressing_mode == ADRESSING_MODE::ZERO_PAGE_Y {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction ldx ZP with effective addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 2;
-            self.processor.clock += 3;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         }
         self.processor.rx = value;
         self.processor.flags = Self::set_flags(self.processor.flags, self.processor.rx);
@@ -726,26 +1294,23 @@ impl Computer {
         let addr = self.get_ld_adddr(mode);
 
         if addressing_mode == ADRESSING_MODE::IMMEDIATE {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction ldy val: {:#x}", self.processor.pc, value));
             }
-            self.processor.pc += 2;
-            self.processor.clock += 2;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE || addressing_mode == ADRESSING_MODE::ABSOLUTE_X || addressing_mode == ADRESSING_MODE::ABSOLUTE_Y {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction ldy absolute with addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 3;
-            self.processor.clock += 4;
+            self.processor.pc = self.processor.pc.wrapping_add(3);
         } else if addressing_mode == ADRESSING_MODE::ZERO_PAGE || addressing_mode == ADRESSING_MODE::ZERO_PAGE_X {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction ldy ZP with effective addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 2;
-            self.processor.clock += 3;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         }
 
         self.processor.ry = value;
@@ -759,33 +1324,29 @@ impl Computer {
         let mode = addressing_mode;
         let addr = self.get_ld_adddr(mode);
         if addressing_mode == ADRESSING_MODE::IMMEDIATE {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction lda val: {:#x}", self.processor.pc, value));
             }
-            self.processor.pc += 2;
-            self.processor.clock += 2;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE || addressing_mode == ADRESSING_MODE::ABSOLUTE_X|| addressing_mode == ADRESSING_MODE::ABSOLUTE_Y {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction lda absolute with addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 3;
-            self.processor.clock += 4;
+            self.processor.pc = self.processor.pc.wrapping_add(3);
         } else if addressing_mode == ADRESSING_MODE::ZERO_PAGE || addressing_mode == ADRESSING_MODE::ZERO_PAGE_X {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction lda ZP with effective addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 2;
-            self.processor.clock += 3;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::INDIRECT_Y || addressing_mode == ADRESSING_MODE::INDIRECT_X {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - Running instruction lda INDIRECT with effective addr: {:#x} and val: {:#x}", self.processor.pc, addr, value));
             }
-            self.processor.pc += 2;
-            self.processor.clock += 5;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else {
             panic!("This adressing mode is not implemented yet, sorry");
         }
@@ -802,29 +1363,25 @@ impl Computer {
         let addr = self.get_ld_adddr(mode);
         if (mode == ADRESSING_MODE::ACCUMULATOR) {
             value = self.processor.acc;
-            self.processor.pc += 1;
-            self.processor.clock += 2;
+            self.processor.pc = self.processor.pc.wrapping_add(1);
         } else if mode == ADRESSING_MODE::ABSOLUTE || mode == ADRESSING_MODE::ABSOLUTE_X {
-            self.processor.pc += 3;
-            self.processor.clock += 6;
-            value = self.data[addr as usize];
+            self.processor.pc = self.processor.pc.wrapping_add(3);
+            value = self.read_byte(addr);
         } else {
-            self.processor.pc += 2;
-            self.processor.clock += 6;
-            value = self.data[addr as usize];
+            self.processor.pc = self.processor.pc.wrapping_add(2);
+            value = self.read_byte(addr);
         }
         if value >> 7 & 1 == 1 {
             self.processor.flags |= FLAG_C;
         } else {
             self.processor.flags &= !FLAG_C;
         }
-        if value == 0 {
+        let result = value << 1;
+        if result == 0 {
             self.processor.flags |= FLAG_Z;
         } else {
             self.processor.flags &= !FLAG_Z;
         }
-
-        let result = value << 1;
         if result >> 7 & 1 == 1 {
             self.processor.flags |= FLAG_N;
         } else {
@@ -833,12 +1390,7 @@ impl Computer {
         if (mode == ADRESSING_MODE::ACCUMULATOR) {
             self.processor.acc = result;
         } else {
-            self.tx.send(
-                ControllerMessage::UpdatedDataAvailable(addr, result)
-            );
-            let mut _addr = self.data.to_vec();
-            _addr[addr as usize] = result;
-            self.data = _addr;
+            self.write_byte(addr, result);
         }
     }
 
@@ -851,7 +1403,7 @@ impl Computer {
         if mode == ADRESSING_MODE::ACCUMULATOR {
             value = self.processor.acc;
         } else {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
         }
         let old_flags = self.processor.flags;
         if value & 1 == 1 {
@@ -859,13 +1411,12 @@ impl Computer {
         } else {
             self.processor.flags &= !FLAG_C;
         }
-        if value == 0 {
+        let result = value >> 1;
+        if result == 0 {
             self.processor.flags |= FLAG_Z;
         } else {
             self.processor.flags &= !FLAG_Z;
         }
-
-        let result = value >> 1;
         if result >> 7 & 1 == 1 {
             self.processor.flags |= FLAG_N;
         } else {
@@ -875,27 +1426,14 @@ impl Computer {
             self.add_info(format!("{:#x} - Running instruction lsr val: {:#x} result: {:#x} flags: {:#x} old flags: {:#x}", self.processor.pc, value, result, self.processor.flags, old_flags));
         }
         if mode == ADRESSING_MODE::ACCUMULATOR {
-            self.processor.pc += 1;
-            self.processor.clock += 2;
+            self.processor.pc = self.processor.pc.wrapping_add(1);
             self.processor.acc = result;
         } else if mode == ADRESSING_MODE::ABSOLUTE || mode == ADRESSING_MODE::ABSOLUTE_X {
-            self.processor.pc += 3;
-            self.processor.clock += 6;
-            self.tx.send(
-                ControllerMessage::UpdatedDataAvailable(addr, result)
-            );
-            let mut _addr = self.data.to_vec();
-            _addr[addr as usize] = result;
-            self.data = _addr;
+            self.processor.pc = self.processor.pc.wrapping_add(3);
+            self.write_byte(addr, result);
         } else {
-            self.processor.pc += 2;
-            self.processor.clock += 5;
-            let mut _addr = self.data.to_vec();
-            self.tx.send(
-                ControllerMessage::UpdatedDataAvailable(addr, result)
-            );
-            _addr[addr as usize] = result;
-            self.data = _addr;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
+            self.write_byte(addr, result);
         }
 
     }
@@ -908,16 +1446,13 @@ impl Computer {
         let addr = self.get_ld_adddr(mode);
         if mode == ADRESSING_MODE::ACCUMULATOR {
             value = self.processor.acc;
-            self.processor.pc += 1;
-            self.processor.clock += 2;
+            self.processor.pc = self.processor.pc.wrapping_add(1);
         } else if mode == ADRESSING_MODE::ABSOLUTE || mode == ADRESSING_MODE::ABSOLUTE_X {
-            value = self.processor.acc;
-            self.processor.pc += 3;
-            self.processor.clock += 6;
+            value = self.read_byte(addr);
+            self.processor.pc = self.processor.pc.wrapping_add(3);
         } else {
-            self.processor.pc += 2;
-            self.processor.clock += 6;
-            value = self.data[addr as usize];
+            self.processor.pc = self.processor.pc.wrapping_add(2);
+            value = self.read_byte(addr);
         }
         
         let old_flags = self.processor.flags;
@@ -943,12 +1478,7 @@ impl Computer {
         if (mode == ADRESSING_MODE::ACCUMULATOR) {
             self.processor.acc = result;
         } else {
-            let mut _addr = self.data.to_vec();
-            self.tx.send(
-                ControllerMessage::UpdatedDataAvailable(addr, result)
-            );
-            _addr[addr as usize] = result;
-            self.data = _addr;
+            self.write_byte(addr, result);
         }
     }
 
@@ -960,16 +1490,13 @@ impl Computer {
         let addr = self.get_ld_adddr(mode);
         if mode == ADRESSING_MODE::ACCUMULATOR {
             value = self.processor.acc;
-            self.processor.pc += 1;
-            self.processor.clock += 2;
+            self.processor.pc = self.processor.pc.wrapping_add(1);
         } else if mode == ADRESSING_MODE::ABSOLUTE || mode == ADRESSING_MODE::ABSOLUTE_X {
-            value = self.processor.acc;
-            self.processor.pc += 3;
-            self.processor.clock += 6;
+            value = self.read_byte(addr);
+            self.processor.pc = self.processor.pc.wrapping_add(3);
         } else {
-            self.processor.pc += 2;
-            self.processor.clock += 6;
-            value = self.data[addr as usize];
+            self.processor.pc = self.processor.pc.wrapping_add(2);
+            value = self.read_byte(addr);
         }
         
         let old_flags = self.processor.flags;
@@ -995,12 +1522,7 @@ impl Computer {
         if mode == ADRESSING_MODE::ACCUMULATOR {
             self.processor.acc = result;
         } else {
-            let mut _addr = self.data.to_vec();
-            self.tx.send(
-                ControllerMessage::UpdatedDataAvailable(addr, result)
-            );
-            _addr[addr as usize] = result;
-            self.data = _addr;
+            self.write_byte(addr, result);
         }
     }
 
@@ -1009,7 +1531,7 @@ impl Computer {
         let mode = addressing_mode;
 
         let addr = self.get_ld_adddr(mode);
-        let value = self.data[addr as usize];
+        let value = self.read_byte(addr);
 
         let result = self.processor.acc & value;
 
@@ -1017,11 +1539,9 @@ impl Computer {
 self.add_info(format!("{:#x} - Running instruction bit val: {:#x} result: {:#x}", self.processor.pc, value, result));
 }
         if addressing_mode == ADRESSING_MODE::ZERO_PAGE {
-            self.processor.pc += 2;
-            self.processor.clock += 2;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE {
-            self.processor.pc += 3;
-            self.processor.clock += 4;
+            self.processor.pc = self.processor.pc.wrapping_add(3);
         } else {
             panic!("Sorry, this adressing mode does not exist for this instruction")
         }
@@ -1049,8 +1569,7 @@ self.add_info(format!("{:#x} - Running instruction bit val: {:#x} result: {:#x}"
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction inx: new val: {:#x} flags: {:#x}", self.processor.pc, self.processor.rx, self.processor.flags));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     fn iny(&mut self) {
@@ -1059,8 +1578,7 @@ self.add_info(format!("{:#x} - Running instruction bit val: {:#x} result: {:#x}"
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction iny: new val: {:#x} flags: {:#x}", self.processor.pc, self.processor.ry, self.processor.flags));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     fn dex(&mut self) {
@@ -1069,8 +1587,7 @@ self.add_info(format!("{:#x} - Running instruction bit val: {:#x} result: {:#x}"
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction dex: new val: {:#x} flags: {:#x}", self.processor.pc, self.processor.rx, self.processor.flags));
         }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     fn dey(&mut self) {
@@ -1079,44 +1596,27 @@ self.add_info(format!("{:#x} - Running instruction bit val: {:#x} result: {:#x}"
         if LOG_LEVEL > 0 {
 self.add_info(format!("{:#x} - Running instruction dey: {:#x} new val: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize], self.processor.ry));
 }
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
     }
 
     fn cmp(&mut self) {
         let addressing_mode = decode::get_adressing_mode(self.processor.inst);
         let acc = self.processor.acc;
         let mut value: u8 = 0;
-        let mut pc = self.processor.pc + 2;
+        let mut pc = self.processor.pc.wrapping_add(2);
         let addr = self.get_ld_adddr(addressing_mode);
-        value = self.data[addr as usize];
+        value = self.read_byte(addr);
         if addressing_mode == ADRESSING_MODE::ABSOLUTE || addressing_mode == ADRESSING_MODE::ABSOLUTE_Y || addressing_mode == ADRESSING_MODE::ABSOLUTE_X {
-            pc += 1;
+            pc = pc.wrapping_add(1);
         }
         
-        let mut flags = self.processor.flags;
-        
-        //If equal, all flags are zero
-        // if a > cmp carry flag is set
-        //if cmp > a neg flag is set
-        
-        if acc == value {
-            flags |= FLAG_Z | FLAG_C;
-            flags &= !FLAG_N;
-        } else if (acc > value) {
-            flags |= FLAG_C;
-            flags &= !(FLAG_N | FLAG_Z);
-        } else {
-            flags |= FLAG_N;
-            flags &= !(FLAG_C | FLAG_Z);
-        }
+        let flags = alu::compare(self.processor.flags, acc, value);
         if LOG_LEVEL > 0 {
 self.add_info(format!("{:#x} - Running instruction cmp: {:#x} with acc: {:#x} val: {:#x} flags: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize], acc, value, flags));
 }
 
         self.processor.flags = flags;
         self.processor.pc = pc;
-        self.processor.clock += 4;
         
     }
 
@@ -1124,76 +1624,52 @@ self.add_info(format!("{:#x} - Running instruction cmp: {:#x} with acc: {:#x} va
         let addressing_mode = decode::get_adressing_mode(self.processor.inst);
         let ry = self.processor.ry;
         let mut value: u8 = 0;
-        let mut pc = self.processor.pc + 2;
+        let mut pc = self.processor.pc.wrapping_add(2);
         let addr = self.get_ld_adddr(addressing_mode);
         if addressing_mode == ADRESSING_MODE::IMMEDIATE {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE {
-            pc += 1;
-            value = self.data[addr as usize];
+            pc = pc.wrapping_add(1);
+            value = self.read_byte(addr);
         } else if addressing_mode == ADRESSING_MODE::ZERO_PAGE {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
         } else {
             panic!("Unknown address type {:?} {:#b}, {:#x}", addressing_mode, self.processor.inst, self.processor.inst);
         }
         
-        let mut flags = self.processor.flags;
-
-        if ry == value {
-            flags |= FLAG_Z | FLAG_C;
-            flags &= !FLAG_N;
-        } else if (ry > value) {
-            flags |= FLAG_C;
-            flags &= !(FLAG_N | FLAG_Z);
-        } else {
-            flags |= FLAG_N;
-            flags &= !(FLAG_C | FLAG_Z);
-        }
+        let flags = alu::compare(self.processor.flags, ry, value);
         if LOG_LEVEL > 0 {
 self.add_info(format!("{:#x} - Running instruction cpy ry: {:#x} with val: {:#x} flags: {:#x}", self.processor.pc, ry, value, flags));
 }
 
         self.processor.flags = flags;
         self.processor.pc = pc;
-        self.processor.clock += 4;
     }
 
     fn cpx(&mut self) {
         let addressing_mode = decode::get_adressing_mode(self.processor.inst);
         let rx = self.processor.rx;
         let mut value: u8 = 0;
-        let mut pc = self.processor.pc + 2;
+        let mut pc = self.processor.pc.wrapping_add(2);
         let addr = self.get_ld_adddr(addressing_mode);
         if addressing_mode == ADRESSING_MODE::IMMEDIATE {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE {
-            pc += 1;
-            value = self.data[addr as usize];
+            pc = pc.wrapping_add(1);
+            value = self.read_byte(addr);
         } else if addressing_mode == ADRESSING_MODE::ZERO_PAGE {
-            value = self.data[addr as usize];
+            value = self.read_byte(addr);
         } else {
             panic!("Unknown address type");
         }
         
-        let mut flags = self.processor.flags;
-
-        if rx == value {
-            flags |= FLAG_Z | FLAG_C;
-            flags &= !FLAG_N;
-        } else if (rx > value) {
-            flags |= FLAG_C;
-            flags &= !(FLAG_N | FLAG_Z);
-        } else {
-            flags |= FLAG_N;
-            flags &= !(FLAG_C | FLAG_Z);
-        }
+        let flags = alu::compare(self.processor.flags, rx, value);
         if LOG_LEVEL > 0 {
 self.add_info(format!("{:#x} - Running instruction cpx rx: {:#x} with val: {:#x} flags: {:#x}", self.processor.pc, rx, value, flags));
 }
 
         self.processor.flags = flags;
         self.processor.pc = pc;
-        self.processor.clock += 4;
     }
 
     fn sta(&mut self) {
@@ -1207,32 +1683,26 @@ self.add_info(format!("{:#x} - Running instruction cpx rx: {:#x} with val: {:#x}
 self.add_info(format!("{:#x} - Running instruction sta ABS at: {:#x} val: {:#x}", self.processor.pc, addr, self.processor.acc));
 }
 
-            pc += 3;
+            pc = pc.wrapping_add(3);
         } else if addressing_mode == ADRESSING_MODE::ZERO_PAGE || addressing_mode == ADRESSING_MODE::ZERO_PAGE_X || addressing_mode == ADRESSING_MODE::ZERO_PAGE_Y {
             if LOG_LEVEL > 0 {
 self.add_info(format!("{:#x} - Running instruction sta ZP at: {:#x} val: {:#x}", self.processor.pc, addr, self.processor.acc));
 }
 
-            pc += 2;
+            pc = pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::INDIRECT_Y || addressing_mode == ADRESSING_MODE::INDIRECT_X {
             if LOG_LEVEL > 0 {
 self.add_info(format!("{:#x} - Running instruction sta INDIRECT at: {:#x} val: {:#x}", self.processor.pc, addr, self.processor.acc));
 }
 
-            pc += 2;
+            pc = pc.wrapping_add(2);
         } else {
             panic!("This adressing mode is not implemented yet, sorry");
         }
 
-        self.tx.send(
-            ControllerMessage::UpdatedDataAvailable(addr, self.processor.acc)
-        );
-        let mut _addr = self.data.to_vec();
-        _addr[addr as usize] = self.processor.acc;
-        self.data = _addr;
+        self.write_byte(addr, self.processor.acc);
 
         self.processor.pc = pc;
-        self.processor.clock += 5;
     }
 
     fn stx(&mut self) {
@@ -1254,15 +1724,9 @@ self.add_info(format!("{:#x} - Running instruction stx ZP at: {:#x} val: {:#x}",
         if addr == 0x200 {
             //self.paused = true;
         }
-        self.tx.send(
-            ControllerMessage::UpdatedDataAvailable(addr, self.processor.rx)
-        );
-        let mut _addr = self.data.to_vec();
-        _addr[addr as usize] = self.processor.rx;
-        self.data = _addr;
+        self.write_byte(addr, self.processor.rx);
 
-        self.processor.pc += pc;
-        self.processor.clock += 5;
+        self.processor.pc = self.processor.pc.wrapping_add(pc);
     }
 
     fn sty(&mut self) {
@@ -1284,31 +1748,25 @@ self.add_info(format!("{:#x} - Running instruction sty ZP at: {:#x} val: {:#x}",
         if addr == 0x200 {
             //self.paused = true;
         }
-        self.tx.send(
-            ControllerMessage::UpdatedDataAvailable(addr, self.processor.ry)
-        );
-        let mut _addr = self.data.to_vec();
-        _addr[addr as usize] = self.processor.ry;
-        self.data = _addr;
+        self.write_byte(addr, self.processor.ry);
 
-        self.processor.pc += pc;
-        self.processor.clock += 5;
+        self.processor.pc = self.processor.pc.wrapping_add(pc);
     }
 
     fn jmp(&mut self) {
         let addressing_mode = decode::get_adressing_mode(self.processor.inst);
-        let mut value: u16 = 0;
-        let mut pc = self.processor.pc + 2;
-        if addressing_mode == ADRESSING_MODE::ABSOLUTE {
-            value = self.get_word(self.processor.pc + 1);
+        let value = if addressing_mode == ADRESSING_MODE::ABSOLUTE {
+            self.get_word(self.processor.pc.wrapping_add(1))
         } else if addressing_mode == ADRESSING_MODE::INDIRECT {
-            let start = self.processor.pc + 1;
-            pc += 1;
+            let start = self.processor.pc.wrapping_add(1);
             let addr = self.get_word(start);
-            value = self.get_word(addr);
+            // The pointer's high byte comes from the start of its page
+            // when the low byte is the last one
+            let high = (addr & 0xff00) | (addr.wrapping_add(1) & 0xff);
+            self.read_byte(addr) as u16 | (self.read_byte(high) as u16) << 8
         } else {
             panic!("Adressing mode not implmented yet");
-        }
+        };
 
         if LOG_LEVEL > 0 {
 self.add_info(format!("{:#x} - Running instruction jmp: {:#x} to: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize], value));
@@ -1318,11 +1776,11 @@ self.add_info(format!("{:#x} - Running instruction jmp: {:#x} to: {:#x}", self.p
     }
 
     fn bne(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
+        let offset = self.data[(self.processor.pc.wrapping_add(1)) as usize];
 
         let should_jump = (self.processor.flags >> 1) & 1 == 0;
         let mut new_addr :u16;
-        new_addr = self.processor.pc + 2;
+        new_addr = self.processor.pc.wrapping_add(2);
         
         if (should_jump) {
             let rel_address = offset as i8;
@@ -1337,7 +1795,6 @@ self.add_info(format!("{:#x} - Running instruction jmp: {:#x} to: {:#x}", self.p
             }
         }
 
-        self.processor.clock += 3;
         self.processor.pc = new_addr;
 
         
@@ -1346,10 +1803,10 @@ self.add_info(format!("{:#x} - Running instruction jmp: {:#x} to: {:#x}", self.p
 
     /// Branch if not equal
     fn beq(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
+        let offset = self.data[(self.processor.pc.wrapping_add(1)) as usize];
         // // println!("Jumping RAW offset is {:?} or 0x{:x?}", offset, offset);
         let should_jump = self.processor.flags & FLAG_Z != 0;
-        let mut new_addr :u16 = self.processor.pc + 2;
+        let mut new_addr :u16 = self.processor.pc.wrapping_add(2);
         
 
         if (should_jump) {
@@ -1364,17 +1821,16 @@ self.add_info(format!("{:#x} - Running instruction jmp: {:#x} to: {:#x}", self.p
                 self.add_info(format!("{:#x} - Running instruction beq not jumping to: {:#x} flags: {:#x}", self.processor.pc, new_addr, self.processor.flags));
             }
         }
-        self.processor.clock += 3;
         self.processor.pc = new_addr;
         
     }
 
     /// Branch if carry clear
     fn bcc(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
+        let offset = self.data[(self.processor.pc.wrapping_add(1)) as usize];
         // // println!("Jumping RAW offset is {:?} or 0x{:x?}", offset, offset);
         let should_jump = self.processor.flags & FLAG_C == 0;
-        let mut new_addr = self.processor.pc + 2;
+        let mut new_addr = self.processor.pc.wrapping_add(2);
         
         if (should_jump) {
             let rel_address = offset as i8;
@@ -1388,16 +1844,15 @@ self.add_info(format!("{:#x} - Running instruction jmp: {:#x} to: {:#x}", self.p
                 self.add_info(format!("{:#x} - Running instruction bcc NOT jumping to: {:#x} flags: {:#x} offset: {}", self.processor.pc, new_addr, self.processor.flags, offset as i8));
             }
         }
-        self.processor.clock += 3;
         self.processor.pc = new_addr;
     }
 
     /// Branch if carry set
     fn bcs(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
+        let offset = self.data[(self.processor.pc.wrapping_add(1)) as usize];
         // // println!("Jumping RAW offset is {:?} or 0x{:x?}", offset, offset);
         let should_jump = (self.processor.flags) & FLAG_C == 1;
-        let mut new_addr :u16 = self.processor.pc + 2;
+        let mut new_addr :u16 = self.processor.pc.wrapping_add(2);
 
         if (should_jump) {
             let rel_address = offset as i8;
@@ -1411,17 +1866,16 @@ self.add_info(format!("{:#x} - Running instruction jmp: {:#x} to: {:#x}", self.p
                 }
             }
         }
-        self.processor.clock += 3;
         self.processor.pc = new_addr;
         
     }
 
     /// Branch if overflow clear
     fn bvc(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
+        let offset = self.data[(self.processor.pc.wrapping_add(1)) as usize];
         // // println!("Jumping RAW offset is {:?} or 0x{:x?}", offset, offset);
         let should_jump = self.processor.flags & FLAG_O == 0;
-        let mut new_addr = self.processor.pc + 2;
+        let mut new_addr = self.processor.pc.wrapping_add(2);
         
         if (should_jump) {
             let rel_address = offset as i8;
@@ -1436,16 +1890,15 @@ self.add_info(format!("{:#x} - Running instruction jmp: {:#x} to: {:#x}", self.p
             }
         }
         
-        self.processor.clock += 3;
         self.processor.pc = new_addr;
     }
 
     /// Branch if overflow set
     fn bvs(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
+        let offset = self.data[(self.processor.pc.wrapping_add(1)) as usize];
         // // println!("Jumping RAW offset is {:?} or 0x{:x?}", offset, offset);
         let should_jump = self.processor.flags & FLAG_O != 0;
-        let mut new_addr = self.processor.pc + 2;
+        let mut new_addr = self.processor.pc.wrapping_add(2);
            
         if (should_jump) {
             let rel_address = offset as i8;
@@ -1459,63 +1912,56 @@ self.add_info(format!("{:#x} - Running instruction jmp: {:#x} to: {:#x}", self.p
                 self.add_info(format!("{:#x} - Running instruction bvs {:#x} NOT jumping to: {:#x} flags: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize], new_addr, self.processor.flags));
             }
         }
-        self.processor.clock += 3;
         self.processor.pc = new_addr;
     }
 
     fn bpl(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
+        let offset = self.data[(self.processor.pc.wrapping_add(1)) as usize];
         // println!("Jumping RAW offset is {:?} or 0x{:x?}", offset, offset);
         let should_jump = (self.processor.flags >> 7) & 1 == 0;
         let mut new_addr :u16;
-        new_addr = self.processor.pc + 2;
+        new_addr = self.processor.pc.wrapping_add(2);
         if (should_jump) {
             let rel_address = offset as i8;
             // println!("BPL Jumping offset {:?}", rel_address);
             new_addr = ((new_addr as i32) + (rel_address as i32)) as u16;
         }
         self.processor.pc = new_addr;
-        self.processor.clock += 3;
         
     }
 
     /// Branch if negative flag is set
     fn bmi(&mut self) {
-        let offset = self.data[(self.processor.pc + 1) as usize];
+        let offset = self.data[(self.processor.pc.wrapping_add(1)) as usize];
         // println!("Jumping RAW offset is {:?} or 0x{:x?}", offset, offset);
         let should_jump = (self.processor.flags >> 7) & 1 == 1;
         let mut new_addr :u16;
-        new_addr = self.processor.pc + 2;
+        new_addr = self.processor.pc.wrapping_add(2);
         if (should_jump) {
             let rel_address = offset as i8;
             // println!("BPL Jumping offset {:?}", rel_address);
             new_addr = ((new_addr as i32) + (rel_address as i32)) as u16;
         }
         self.processor.pc = new_addr;
-        self.processor.clock += 3;
         
     }
 
     fn get_logical_op_value(&mut self) -> u8 {
         let addressing_mode = decode::get_adressing_mode(self.processor.inst);
         let addr = self.get_ld_adddr(addressing_mode);
-        return self.data[addr as usize];
+        self.read_byte(addr)
     }
 
     fn after_logical_op(&mut self) {
         let addressing_mode = decode::get_adressing_mode(self.processor.inst);
         if addressing_mode == ADRESSING_MODE::IMMEDIATE {
-            self.processor.pc += 2;
-            self.processor.clock += 2;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::ZERO_PAGE || addressing_mode == ADRESSING_MODE::ZERO_PAGE_X {
-            self.processor.pc += 2;
-            self.processor.clock += 3;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::INDIRECT_X || addressing_mode == ADRESSING_MODE::INDIRECT_Y {
-            self.processor.pc += 2;
-            self.processor.clock += 6;
+            self.processor.pc = self.processor.pc.wrapping_add(2);
         } else if addressing_mode == ADRESSING_MODE::ABSOLUTE || addressing_mode == ADRESSING_MODE::ABSOLUTE_X || addressing_mode == ADRESSING_MODE::ABSOLUTE_Y {
-            self.processor.pc += 3;
-            self.processor.clock += 4;
+            self.processor.pc = self.processor.pc.wrapping_add(3);
         } else {
             if LOG_LEVEL > 0 {
                 self.add_info(format!("{:#x} - this addressing mode not implemented for instruction {:?}", self.processor.pc, addressing_mode));
@@ -1563,45 +2009,15 @@ self.add_info(format!("{:#x} - Running instruction ora {:#x} with acc: {:#x} val
     }
 
     fn adc(&mut self) {
-        
         let addressing_mode = decode::get_adressing_mode(self.processor.inst);
         let addr = self.get_ld_adddr(addressing_mode);
-        let val = self.data[addr as usize];
-        let acc = self.processor.acc;
-        let mut carry = self.processor.flags & FLAG_C != 0;
-        let decimal = self.processor.flags & FLAG_D != 0;
-
-        let mut sum = 0;
-
-        if (decimal) {
-            let mut s: u16 = 0;
-            let mut ln = (acc & 0xF) + (val &0xF) + (self.processor.flags & FLAG_C);
-            if (ln > 9) {
-                ln = 0x10 | ((ln + 6) & 0xf);
-            }
-            let mut hn: u16 = (acc & 0xf0) as u16 + (val & 0xf0) as u16;
-            s = hn + ln as u16;
-
-            
-
-            if (s >= 160) {
-                self.processor.flags |= FLAG_C;
-                if ((self.processor.flags & FLAG_O) != 0 && s >= 0x180) { self.processor.flags &= !FLAG_O; }
-                s += 0x60;
-            } else {
-                self.processor.flags &= !FLAG_C;
-                if ((self.processor.flags & FLAG_O) != 0 && s < 0x80) { self.processor.flags &= !FLAG_O; }
-            }
-            sum  = (s & 0xff) as u8;
-            self.processor.flags = Self::set_flags(self.processor.flags, sum);
-        } else {
-            sum = self.do_add(val);
-        }
-        
+        let val = self.read_byte(addr);
+        let (sum, flags) = alu::adc(self.variant, self.processor.flags, self.processor.acc, val);
 
         if LOG_LEVEL > 0 {
-self.add_info(format!("{:#x} - Running instruction adc with acc: {:#x} memval: {:#x} flags: {:#x} carry: {} result: {:#x}", self.processor.pc, self.processor.acc, val, self.processor.flags, carry, sum));
+self.add_info(format!("{:#x} - Running instruction adc with acc: {:#x} memval: {:#x} flags: {:#x} result: {:#x}", self.processor.pc, self.processor.acc, val, flags, sum));
 }
+        self.processor.flags = flags;
         self.processor.acc = sum;
         self.after_logical_op();
     }
@@ -1609,110 +2025,26 @@ self.add_info(format!("{:#x} - Running instruction adc with acc: {:#x} memval: {
     fn sbc(&mut self) {
         let addressing_mode = decode::get_adressing_mode(self.processor.inst);
         let addr = self.get_ld_adddr(addressing_mode);
-        let val = self.data[addr as usize];
-        let decimal = self.processor.flags & FLAG_D != 0;
-        let acc= self.processor.acc;
-
-        let mut carry: bool = false;
-        let mut sum = 0;
-
-        if (decimal) {
-
-            // tmp = 0xf + (regA & 0xf) - (value & 0xf) + carrySet();
-            // if (tmp < 0x10) {
-            // w = 0;
-            // tmp -= 6;
-            // } else {
-            // w = 0x10;
-            // tmp -= 0x10;
-            // }
-            // w += 0xf0 + (regA & 0xf0) - (value & 0xf0);
-            // if (w < 0x100) {
-            // CLC();
-            // if (overflowSet() && w < 0x80) { CLV(); }
-            // w -= 0x60;
-            // } else {
-            // SEC();
-            // if (overflowSet() && w >= 0x180) { CLV(); }
-            // }
-            // w += tmp;
-
-
-            let mut w: u16;
-            let mut tmp = 0xf + (acc & 0xf) - (val & 0xf) + (self.processor.flags & FLAG_C);
-            if (tmp < 0x10) {
-                w = 0;
-              tmp -= 6;
-            } else {
-              w = 0x10;
-              tmp -= 0x10;
-            }
-            w += 0xf0 + ((acc as u16) & 0xf0) - ((val as u16) & 0xf0);
-            if (w < 0x100) {
-              self.processor.flags &= !FLAG_C;
-              if ((self.processor.flags & FLAG_O) != 0 && w < 0x80) { self.processor.flags &= !FLAG_O; }
-              w -= 0x60;
-            } else {
-                self.processor.flags |= FLAG_C;
-              if ((self.processor.flags & FLAG_O) != 0  && w >= 0x180) { self.processor.flags &= !FLAG_O; }
-            }
-            w += tmp as u16;
-            sum = w as u8
-        } else {
-            sum = self.do_add(!val);
-        }
+        let val = self.read_byte(addr);
+        let (sum, flags) = alu::sbc(self.variant, self.processor.flags, self.processor.acc, val);
 
         if LOG_LEVEL > 0 {
-            self.add_info(format!("{:#x} - Running instruction sbc with acc: {:#x} memval: {:#x} flags: {:#x}", self.processor.pc, self.processor.acc, val, self.processor.flags));
+            self.add_info(format!("{:#x} - Running instruction sbc with acc: {:#x} memval: {:#x} flags: {:#x}", self.processor.pc, self.processor.acc, val, flags));
         }
-        self.processor.acc = sum as u8;
+        self.processor.flags = flags;
+        self.processor.acc = sum;
         self.after_logical_op();
     }
 
-    fn do_add(&mut self, val: u8) -> u8 {
-        let mut acc = self.processor.acc;
-        let mut carry = false;
-        let bit7 = acc >> 7;
-        let s = acc as u16 + val as u16 + (self.processor.flags & FLAG_C) as u16;
-        
-        if s > 255 {
-            carry = true;
-        }
-        let mut sum = 0;
-    
-        sum = acc.wrapping_add(val);
-        //Add carry bit
-        sum = sum.wrapping_add(self.processor.flags & FLAG_C);
-        self.processor.flags = Self::set_flags(self.processor.flags, sum);
-
-        if carry {
-            self.processor.flags |= FLAG_C;
-        } else {
-            self.processor.flags &= !FLAG_C;
-        }
-
-        if (acc ^ sum) & (val ^ sum) & 0x80 != 0 {
-            self.processor.flags |= FLAG_O;
-        } else {
-            self.processor.flags &= !FLAG_O;
-        }
-
-
-        
-
-        return sum;
-    }
-
     fn nop(&mut self) {
         if LOG_LEVEL > 0 {
             self.add_info(format!("{:#x} - Running instruction nop: {:#x}", self.processor.pc, self.data[(self.processor.pc) as usize]));
         }
         if (self.processor.inst != 0xea) {
-            self.speed = 1000;
+            self.set_clock(CLOCK_RATES[0]);
         }
         
-        self.processor.pc += 1;
-        self.processor.clock += 2;
+        self.processor.pc = self.processor.pc.wrapping_add(1);
         
     }
 
@@ -1730,15 +2062,78 @@ self.add_info(format!("{:#x} - Running instruction adc with acc: {:#x} memval: {
             _flags &= !FLAG_N;
         }
 
-        _flags |= 0x30;
 
         // // println!("Setting flags to {:#b}", _flags);
         return _flags;
     }
 
+    /// Read a byte from the bus, letting devices claim their addresses
+    pub fn read_byte(&mut self, address: u16) -> u8 {
+        self.cover(address, coverage::READ);
+        self.bus_read(address)
+    }
+
+    /// Read a byte from the bus without marking it as read
+    fn bus_read(&mut self, address: u16) -> u8 {
+        for device in self.devices.iter_mut() {
+            if device.handles(address) {
+                return device.read(address);
+            }
+        }
+        self.data[address as usize]
+    }
+
+    /// Write a byte to the bus, letting devices claim their addresses
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        self.cover(address, coverage::WRITTEN);
+        if self.watched[address as usize] {
+            self.writes.push((address, value));
+        }
+        for device in self.devices.iter_mut() {
+            if device.handles(address) {
+                device.write(address, value);
+                device.map(&mut self.data);
+                return;
+            }
+        }
+        self.data.write(address, value);
+    }
+
+    /// Read a pointer from the zero page, whose high byte wraps round to
+    /// $00 from $ff
+    fn zero_page_word(&mut self, zp: u8) -> u16 {
+        self.read_byte(zp as u16) as u16 | (self.read_byte(zp.wrapping_add(1) as u16) as u16) << 8
+    }
+
+    /// Read a little endian word from the bus without marking it as read
     pub fn get_word(&mut self, address: u16) -> u16 {
-        let low_byte :u16 = self.data[(address) as usize].into();
-        let high_byte :u16 = self.data[(address + 1) as usize].into();
+        let low_byte :u16 = self.bus_read(address).into();
+        let high_byte :u16 = self.bus_read(address.wrapping_add(1)).into();
         return low_byte + (high_byte << 8);
     }
-}
\ No newline at end of file
+}
+
+/// The cycle stepped core goes through the same devices as the
+/// instruction core. Its opcode and operand fetches are covered as
+/// executed code once the instruction completes, not as reads.
+impl Bus for Computer {
+    fn registers(&mut self) -> &mut Processor {
+        &mut self.processor
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        self.read_byte(address)
+    }
+
+    fn fetch(&mut self, address: u16) -> u8 {
+        self.bus_read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.write_byte(address, value);
+    }
+
+    fn irq(&mut self) -> bool {
+        self.irq
+    }
+}
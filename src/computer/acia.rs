@@ -0,0 +1,526 @@
+#[cfg(unix)]
+use std::ffi::CStr;
+use std::fmt;
+#[cfg(unix)]
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::raw::{c_char, c_int};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time;
+
+use crate::computer::device::Device;
+
+/// Nominal processor clock used to turn baud rates into cycle counts
+const CLOCK_HZ: u64 = 1_000_000;
+
+const STATUS_OVERRUN: u8 = 0x04;
+const STATUS_RDRF: u8 = 0x08;
+const STATUS_TDRE: u8 = 0x10;
+const STATUS_IRQ: u8 = 0x80;
+
+const COMMAND_DTR: u8 = 0x01;
+const COMMAND_RX_IRQ_DISABLE: u8 = 0x02;
+const COMMAND_TX_MASK: u8 = 0x0c;
+const COMMAND_TX_IRQ: u8 = 0x04;
+const COMMAND_ECHO: u8 = 0x10;
+const COMMAND_PARITY: u8 = 0x20;
+
+const CONTROL_STOP_BITS: u8 = 0x80;
+
+/// Baud rates selected by the low nibble of the control register, for the
+/// usual 1.8432 MHz crystal. Entry 0 is the external 16x clock, which we
+/// treat as "as fast as the host can go".
+const BAUD_RATES: [u64; 16] = [
+    0, 50, 75, 110, 135, 150, 300, 600,
+    1200, 1800, 2400, 3600, 4800, 7200, 9600, 19200,
+];
+
+#[cfg(unix)]
+extern "C" {
+    fn grantpt(fd: c_int) -> c_int;
+    fn unlockpt(fd: c_int) -> c_int;
+    fn ptsname(fd: c_int) -> *const c_char;
+}
+
+/// Where the host side of the serial line is connected
+#[derive(Clone, Debug, PartialEq)]
+pub enum AciaHost {
+    Stdio,
+    Pty,
+    Tcp(u16),
+}
+
+impl AciaHost {
+    /// Parse `stdio`, `pty` or `tcp:<port>`
+    pub fn parse(spec: &str) -> Result<AciaHost, String> {
+        match spec {
+            "stdio" => Ok(AciaHost::Stdio),
+            "pty" => Ok(AciaHost::Pty),
+            _ => {
+                if let Some(port) = spec.strip_prefix("tcp:") {
+                    let port = port.parse::<u16>().map_err(|e| format!("invalid tcp port {}: {}", port, e))?;
+                    return Ok(AciaHost::Tcp(port));
+                }
+                Err(format!("unknown serial host {}, expected stdio, pty or tcp:<port>", spec))
+            }
+        }
+    }
+}
+
+type HostWriter = Arc<Mutex<Option<Box<dyn Write + Send>>>>;
+
+/// The far end of a serial line driven by a program rather than a
+/// terminal, like a test script. Bytes sent here arrive at the ACIA's
+/// receiver, and what the ACIA transmits is kept until taken.
+pub struct SerialPort {
+    tx: mpsc::Sender<u8>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SerialPort {
+    /// Queue bytes for the emulated program to receive. Fails once the
+    /// ACIA has been dropped.
+    pub fn send(&self, bytes: &[u8]) -> Result<(), String> {
+        for byte in bytes {
+            self.tx.send(*byte).map_err(|_| "the serial port is no longer connected".to_string())?;
+        }
+        Ok(())
+    }
+
+    /// What the emulated program transmitted since the last call
+    pub fn take_output(&self) -> Vec<u8> {
+        let mut output = self.output.lock().unwrap();
+        output.drain(..).collect()
+    }
+}
+
+/// Collects transmitted bytes for `SerialPort::take_output`
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The host end of the serial line. Incoming bytes are read by a
+/// background thread and queued on `rx`, outgoing bytes go to `tx`
+/// when something is connected.
+struct HostLink {
+    rx: mpsc::Receiver<u8>,
+    tx: HostWriter,
+    description: String,
+}
+
+impl HostLink {
+    fn connect(host: &AciaHost) -> Result<HostLink, String> {
+        match host {
+            AciaHost::Stdio => Ok(Self::stdio()),
+            AciaHost::Pty => Self::pty(),
+            AciaHost::Tcp(port) => Self::tcp(*port),
+        }
+    }
+
+    /// A link whose other end is a `SerialPort`
+    fn port() -> (HostLink, SerialPort) {
+        let (tx, rx) = mpsc::channel::<u8>();
+        let output = Arc::new(Mutex::new(vec![]));
+        let writer: Box<dyn Write + Send> = Box::new(Captured(output.clone()));
+        let link = HostLink {
+            rx,
+            tx: Arc::new(Mutex::new(Some(writer))),
+            description: "serial port".to_string(),
+        };
+        (link, SerialPort { tx, output })
+    }
+
+    fn stdio() -> HostLink {
+        let (tx, rx) = mpsc::channel::<u8>();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                match byte {
+                    Ok(b) => if tx.send(b).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+        let writer: Box<dyn Write + Send> = Box::new(io::stdout());
+        HostLink {
+            rx,
+            tx: Arc::new(Mutex::new(Some(writer))),
+            description: "stdin/stdout".to_string(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn pty() -> Result<HostLink, String> {
+        Err("pty serial hosts need a unix host".to_string())
+    }
+
+    #[cfg(unix)]
+    fn pty() -> Result<HostLink, String> {
+        let master = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/ptmx")
+            .map_err(|e| format!("could not open /dev/ptmx: {}", e))?;
+        let fd = master.as_raw_fd();
+        let name = unsafe {
+            if grantpt(fd) != 0 || unlockpt(fd) != 0 {
+                return Err(format!("could not unlock pty: {}", io::Error::last_os_error()));
+            }
+            let name = ptsname(fd);
+            if name.is_null() {
+                return Err(format!("could not get pty name: {}", io::Error::last_os_error()));
+            }
+            CStr::from_ptr(name).to_string_lossy().into_owned()
+        };
+
+        let mut reader = master.try_clone().map_err(|e| e.to_string())?;
+        let (tx, rx) = mpsc::channel::<u8>();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => thread::sleep(time::Duration::from_millis(50)),
+                    Ok(n) => {
+                        for b in &buf[..n] {
+                            if tx.send(*b).is_err() {
+                                return;
+                            }
+                        }
+                    },
+                    // Reads fail with EIO until a terminal opens the slave side
+                    Err(_) => thread::sleep(time::Duration::from_millis(100)),
+                }
+            }
+        });
+
+        let writer: Box<dyn Write + Send> = Box::new(master);
+        Ok(HostLink {
+            rx,
+            tx: Arc::new(Mutex::new(Some(writer))),
+            description: format!("pty {}", name),
+        })
+    }
+
+    fn tcp(port: u16) -> Result<HostLink, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| format!("could not listen on port {}: {}", port, e))?;
+        let writer: HostWriter = Arc::new(Mutex::new(None));
+        let (tx, rx) = mpsc::channel::<u8>();
+        let client = writer.clone();
+        thread::spawn(move || {
+            // Serve one client at a time, accepting the next one when it hangs up
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                if let Ok(w) = stream.try_clone() {
+                    *client.lock().unwrap() = Some(Box::new(w));
+                }
+                let mut buf = [0u8; 64];
+                loop {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            for b in &buf[..n] {
+                                if tx.send(*b).is_err() {
+                                    return;
+                                }
+                            }
+                        },
+                    }
+                }
+                *client.lock().unwrap() = None;
+            }
+        });
+
+        Ok(HostLink {
+            rx,
+            tx: writer,
+            description: format!("tcp 127.0.0.1:{}", port),
+        })
+    }
+
+    fn send(&self, byte: u8) {
+        let mut writer = self.tx.lock().unwrap();
+        let failed = match writer.as_mut() {
+            Some(w) => w.write_all(&[byte]).and_then(|_| w.flush()).is_err(),
+            None => false,
+        };
+        if failed {
+            *writer = None;
+        }
+    }
+}
+
+/// 6551 / 65C51 asynchronous communications interface adapter.
+///
+/// Registers, relative to `base`:
+/// 0 - transmit (write) / receive (read) data
+/// 1 - status (read) / programmed reset (write)
+/// 2 - command
+/// 3 - control
+pub struct Acia {
+    base: u16,
+    status: u8,
+    command: u8,
+    control: u8,
+    rx_data: u8,
+    tx_data: Option<u8>,
+    tx_countdown: u64,
+    rx_countdown: u64,
+    link: HostLink,
+}
+
+impl fmt::Debug for Acia {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Acia")
+            .field("base", &self.base)
+            .field("status", &self.status)
+            .field("command", &self.command)
+            .field("control", &self.control)
+            .field("host", &self.link.description)
+            .finish()
+    }
+}
+
+impl Acia {
+    /// Create an ACIA at `base` and connect its host side
+    pub fn new(base: u16, host: &AciaHost) -> Result<Acia, String> {
+        Ok(Self::with_link(base, HostLink::connect(host)?))
+    }
+
+    /// Create an ACIA at `base` whose host side is the returned port
+    pub fn with_port(base: u16) -> (Acia, SerialPort) {
+        let (link, port) = HostLink::port();
+        (Self::with_link(base, link), port)
+    }
+
+    fn with_link(base: u16, link: HostLink) -> Acia {
+        Acia {
+            base,
+            status: STATUS_TDRE,
+            command: COMMAND_RX_IRQ_DISABLE,
+            control: 0,
+            rx_data: 0,
+            tx_data: None,
+            tx_countdown: 0,
+            rx_countdown: 0,
+            link,
+        }
+    }
+
+    /// Number of processor cycles it takes to shift one character,
+    /// including start, parity and stop bits
+    fn cycles_per_char(&self) -> u64 {
+        let baud = BAUD_RATES[(self.control & 0xf) as usize];
+        if baud == 0 {
+            return 0;
+        }
+        let data_bits = 8 - ((self.control >> 5) & 3) as u64;
+        let parity_bits = if self.command & COMMAND_PARITY != 0 { 1 } else { 0 };
+        let stop_bits = if self.control & CONTROL_STOP_BITS != 0 { 2 } else { 1 };
+        CLOCK_HZ * (1 + data_bits + parity_bits + stop_bits) / baud
+    }
+
+    fn interrupts_enabled(&self) -> bool {
+        self.command & COMMAND_DTR != 0
+    }
+}
+
+impl Device for Acia {
+    fn name(&self) -> String {
+        format!("6551 ACIA at {:#06x} on {}", self.base, self.link.description)
+    }
+
+    fn handles(&self, addr: u16) -> bool {
+        addr >= self.base && addr - self.base < 4
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr - self.base {
+            0 => {
+                self.status &= !(STATUS_RDRF | STATUS_OVERRUN);
+                self.rx_data
+            },
+            1 => {
+                // Reading the status register acknowledges the interrupt
+                let status = self.status;
+                self.status &= !STATUS_IRQ;
+                status
+            },
+            2 => self.command,
+            _ => self.control,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr - self.base {
+            0 => {
+                self.tx_data = Some(value);
+                self.tx_countdown = self.cycles_per_char();
+                self.status &= !STATUS_TDRE;
+            },
+            1 => {
+                self.status &= !STATUS_OVERRUN;
+                self.command &= 0xe0;
+            },
+            2 => self.command = value,
+            _ => self.control = value,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if let Some(byte) = self.tx_data {
+            self.tx_countdown = self.tx_countdown.saturating_sub(cycles);
+            if self.tx_countdown == 0 {
+                self.link.send(byte);
+                self.tx_data = None;
+                self.status |= STATUS_TDRE;
+                if self.interrupts_enabled() && self.command & COMMAND_TX_MASK == COMMAND_TX_IRQ {
+                    self.status |= STATUS_IRQ;
+                }
+            }
+        }
+
+        self.rx_countdown = self.rx_countdown.saturating_sub(cycles);
+        // Host bytes stay queued while the receive register is full, which
+        // behaves like hardware flow control instead of dropping characters
+        if self.rx_countdown == 0 && self.status & STATUS_RDRF == 0 && self.interrupts_enabled() {
+            if let Ok(byte) = self.link.rx.try_recv() {
+                self.rx_data = byte;
+                self.status |= STATUS_RDRF;
+                if self.command & COMMAND_ECHO != 0 {
+                    self.link.send(byte);
+                }
+                if self.command & COMMAND_RX_IRQ_DISABLE == 0 {
+                    self.status |= STATUS_IRQ;
+                }
+                self.rx_countdown = self.cycles_per_char();
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.status & STATUS_IRQ != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u16 = 0xa000;
+    const DATA: u16 = BASE;
+    const STATUS: u16 = BASE + 1;
+    const COMMAND: u16 = BASE + 2;
+    const CONTROL: u16 = BASE + 3;
+
+    /// 9600 baud, 8 data bits and 1 stop bit: 10 bits of 104 cycles
+    const CONTROL_9600: u8 = 0x1e;
+    const CHAR_9600: u64 = 1041;
+
+    #[test]
+    fn registers() {
+        let (mut acia, _port) = Acia::with_port(BASE);
+        assert!(acia.handles(BASE) && acia.handles(BASE + 3));
+        assert!(!acia.handles(BASE - 1) && !acia.handles(BASE + 4));
+        assert_eq!(acia.read(STATUS), STATUS_TDRE);
+        assert_eq!(acia.read(COMMAND), COMMAND_RX_IRQ_DISABLE);
+
+        acia.write(COMMAND, 0xeb);
+        acia.write(CONTROL, CONTROL_9600);
+        assert_eq!((acia.read(COMMAND), acia.read(CONTROL)), (0xeb, CONTROL_9600));
+        // A programmed reset clears the low command bits and leaves control alone
+        acia.write(STATUS, 0);
+        assert_eq!((acia.read(COMMAND), acia.read(CONTROL)), (0xe0, CONTROL_9600));
+    }
+
+    #[test]
+    fn baud_rate_paces_transmission() {
+        let (mut acia, port) = Acia::with_port(BASE);
+        acia.write(CONTROL, CONTROL_9600);
+        assert_eq!(acia.cycles_per_char(), CHAR_9600);
+        acia.write(DATA, b'A');
+        assert_eq!(acia.read(STATUS) & STATUS_TDRE, 0);
+        acia.tick(CHAR_9600 - 1);
+        assert!(port.take_output().is_empty());
+        acia.tick(1);
+        assert_eq!(port.take_output(), b"A");
+        assert_eq!(acia.read(STATUS) & STATUS_TDRE, STATUS_TDRE);
+
+        // Two stop bits and parity make the character two bits longer
+        acia.write(CONTROL, CONTROL_9600 | CONTROL_STOP_BITS);
+        acia.write(COMMAND, COMMAND_PARITY);
+        assert_eq!(acia.cycles_per_char(), 1_000_000 * 12 / 9600);
+        // The external clock sends at once
+        acia.write(CONTROL, 0);
+        acia.write(DATA, b'B');
+        acia.tick(1);
+        assert_eq!(port.take_output(), b"B");
+    }
+
+    #[test]
+    fn receives_at_the_baud_rate() {
+        let (mut acia, port) = Acia::with_port(BASE);
+        acia.write(CONTROL, CONTROL_9600);
+        port.send(b"hi").unwrap();
+        // Nothing arrives until the program sets DTR
+        acia.tick(1);
+        assert_eq!(acia.read(STATUS) & STATUS_RDRF, 0);
+
+        acia.write(COMMAND, COMMAND_DTR | COMMAND_RX_IRQ_DISABLE);
+        acia.tick(1);
+        assert_eq!(acia.read(STATUS) & STATUS_RDRF, STATUS_RDRF);
+        assert_eq!(acia.read(DATA), b'h');
+        assert_eq!(acia.read(STATUS) & STATUS_RDRF, 0);
+        acia.tick(CHAR_9600 - 1);
+        assert_eq!(acia.read(STATUS) & STATUS_RDRF, 0);
+        acia.tick(1);
+        assert_eq!(acia.read(DATA), b'i');
+        assert!(!acia.irq());
+    }
+
+    #[test]
+    fn reading_status_acknowledges_the_irq() {
+        let (mut acia, port) = Acia::with_port(BASE);
+        acia.write(COMMAND, COMMAND_DTR | COMMAND_TX_IRQ);
+        acia.write(DATA, b'x');
+        acia.tick(1);
+        assert!(acia.irq());
+        assert_eq!(acia.read(STATUS), STATUS_IRQ | STATUS_TDRE);
+        assert!(!acia.irq());
+        assert_eq!(acia.read(STATUS), STATUS_TDRE);
+
+        port.send(b"y").unwrap();
+        acia.tick(1);
+        assert!(acia.irq());
+        // Reading the data leaves the interrupt for the status read
+        assert_eq!(acia.read(DATA), b'y');
+        assert!(acia.irq());
+        acia.read(STATUS);
+        assert!(!acia.irq());
+    }
+
+    #[test]
+    fn port_fails_once_the_acia_is_gone() {
+        let (acia, port) = Acia::with_port(BASE);
+        assert!(port.send(b"a").is_ok());
+        drop(acia);
+        assert!(port.send(b"a").is_err());
+        assert!(port.take_output().is_empty());
+    }
+}
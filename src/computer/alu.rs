@@ -0,0 +1,122 @@
+use crate::computer::{Computer, Variant};
+
+const FLAG_C: u8 = 1;
+const FLAG_Z: u8 = 2;
+const FLAG_D: u8 = 8;
+const FLAG_O: u8 = 0x40;
+const FLAG_N: u8 = 0x80;
+
+// The arithmetic of the cycle stepped core, whose ADC and SBC the
+// instruction core uses too. Each takes the flags and operands and
+// returns the result and the new flags.
+
+fn set(flags: u8, flag: u8, on: bool) -> u8 {
+    if on { flags | flag } else { flags & !flag }
+}
+
+/// Add with carry, in decimal when the D flag is set. Decimal results
+/// follow Bruce Clark's description of the chips, for valid and invalid
+/// BCD alike: the 6502 takes N and V from the sum of the high digits
+/// before it is adjusted and Z from the binary sum, while the 65C02 takes
+/// N and Z from the result.
+pub fn adc(variant: Variant, flags: u8, acc: u8, value: u8) -> (u8, u8) {
+    if flags & FLAG_D == 0 {
+        return add(flags, acc, value);
+    }
+    let carry = flags & FLAG_C;
+    let mut low = (acc & 0xf) + (value & 0xf) + carry;
+    if low >= 0xa {
+        low = ((low + 6) & 0xf) + 0x10;
+    }
+    let mut sum = (acc & 0xf0) as u16 + (value & 0xf0) as u16 + low as u16;
+    // The same sum with the high digits signed gives N and V
+    let signed = (acc & 0xf0) as i8 as i16 + (value & 0xf0) as i8 as i16 + low as i16;
+    if sum >= 0xa0 {
+        sum += 0x60;
+    }
+    let result = sum as u8;
+    let flags = set(flags, FLAG_C, sum >= 0x100);
+    let flags = set(flags, FLAG_O, !(-128..=127).contains(&signed));
+    match variant {
+        Variant::Nmos => {
+            let flags = set(flags, FLAG_N, signed & 0x80 != 0);
+            (result, set(flags, FLAG_Z, acc.wrapping_add(value).wrapping_add(carry) == 0))
+        },
+        Variant::Cmos => (result, Computer::set_flags(flags, result)),
+    }
+}
+
+/// Subtract with borrow, in decimal when the D flag is set. C and V
+/// always come from the binary difference, and on the 6502 N and Z too,
+/// while the 65C02 takes N and Z from the result.
+pub fn sbc(variant: Variant, flags: u8, acc: u8, value: u8) -> (u8, u8) {
+    let (difference, binary) = add(flags, acc, !value);
+    if flags & FLAG_D == 0 {
+        return (difference, binary);
+    }
+    let borrow = 1 - (flags & FLAG_C) as i16;
+    let low = (acc & 0xf) as i16 - (value & 0xf) as i16 - borrow;
+    let result = match variant {
+        Variant::Nmos => {
+            let low = if low < 0 { ((low - 6) & 0xf) - 0x10 } else { low };
+            let high = (acc & 0xf0) as i16 - (value & 0xf0) as i16 + low;
+            if high < 0 { high - 0x60 } else { high }
+        },
+        Variant::Cmos => {
+            let mut result = acc as i16 - value as i16 - borrow;
+            if result < 0 {
+                result -= 0x60;
+            }
+            if low < 0 {
+                result -= 6;
+            }
+            result
+        },
+    } as u8;
+    match variant {
+        Variant::Nmos => (result, binary),
+        Variant::Cmos => (result, Computer::set_flags(binary, result)),
+    }
+}
+
+/// Binary add with carry
+fn add(flags: u8, acc: u8, value: u8) -> (u8, u8) {
+    let sum = acc as u16 + value as u16 + (flags & FLAG_C) as u16;
+    let result = sum as u8;
+    let flags = set(Computer::set_flags(flags, result), FLAG_C, sum > 0xff);
+    (result, set(flags, FLAG_O, (acc ^ result) & (value ^ result) & 0x80 != 0))
+}
+
+/// Flags after CMP, CPX or CPY of `register` with `value`
+pub fn compare(flags: u8, register: u8, value: u8) -> u8 {
+    let flags = set(flags, FLAG_C, register >= value);
+    let flags = set(flags, FLAG_Z, register == value);
+    set(flags, FLAG_N, register.wrapping_sub(value) & 0x80 != 0)
+}
+
+/// Flags after BIT of `acc` with `value`
+pub fn bit(flags: u8, acc: u8, value: u8) -> u8 {
+    let flags = set(flags, FLAG_Z, acc & value == 0);
+    let flags = set(flags, FLAG_N, value & 0x80 != 0);
+    set(flags, FLAG_O, value & 0x40 != 0)
+}
+
+pub fn asl(flags: u8, value: u8) -> (u8, u8) {
+    let result = value << 1;
+    (result, set(Computer::set_flags(flags, result), FLAG_C, value & 0x80 != 0))
+}
+
+pub fn lsr(flags: u8, value: u8) -> (u8, u8) {
+    let result = value >> 1;
+    (result, set(Computer::set_flags(flags, result), FLAG_C, value & 1 != 0))
+}
+
+pub fn rol(flags: u8, value: u8) -> (u8, u8) {
+    let result = value << 1 | (flags & FLAG_C);
+    (result, set(Computer::set_flags(flags, result), FLAG_C, value & 0x80 != 0))
+}
+
+pub fn ror(flags: u8, value: u8) -> (u8, u8) {
+    let result = value >> 1 | (flags & FLAG_C) << 7;
+    (result, set(Computer::set_flags(flags, result), FLAG_C, value & 1 != 0))
+}
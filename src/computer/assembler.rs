@@ -0,0 +1,762 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::computer::ADRESSING_MODE;
+use crate::computer::decode;
+
+/// Code assembled from source, as runs of bytes at their addresses
+#[derive(Clone, Debug, Default)]
+pub struct Assembly {
+    /// Start address and bytes of each run, in source order
+    pub segments: Vec<(u16, Vec<u8>)>,
+    /// Labels and `=` definitions
+    pub symbols: BTreeMap<String, u16>,
+}
+
+impl Assembly {
+    /// Number of bytes assembled
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|(_, bytes)| bytes.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lay the segments out in a 64 KiB image, with `fill` elsewhere
+    pub fn image(&self, fill: u8) -> Vec<u8> {
+        let mut image = vec![fill; 0x10000];
+        for (start, bytes) in &self.segments {
+            for (i, byte) in bytes.iter().enumerate() {
+                image[(*start as usize + i) & 0xffff] = *byte;
+            }
+        }
+        image
+    }
+
+    fn emit(&mut self, addr: u16, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        match self.segments.last_mut() {
+            Some((start, run)) if start.wrapping_add(run.len() as u16) == addr => run.extend_from_slice(bytes),
+            _ => self.segments.push((addr, bytes.to_vec())),
+        }
+    }
+}
+
+/// Assemble `source`, starting at `origin` until the first `.org`
+pub fn assemble(source: &str, origin: u16) -> Result<Assembly, String> {
+    assemble_with(source, origin, &|_| None)
+}
+
+/// Like `assemble`, asking `symbol` for the names the source uses but does
+/// not define, for example from the debug info of the program being patched
+pub fn assemble_with(source: &str, origin: u16, symbol: &dyn Fn(&str) -> Option<u16>) -> Result<Assembly, String> {
+    let lines = source.lines()
+        .enumerate()
+        .map(|(number, text)| parse_line(text).map_err(|e| format!("line {}: {}", number + 1, e)))
+        .collect::<Result<Vec<Line>, String>>()?;
+
+    // The first pass places every line, choosing zero page operands only
+    // for values it already knows, like ca65 does
+    let mut symbols: HashMap<String, i64> = HashMap::new();
+    // Address of each line, and the opcode and mode of instructions
+    let mut placed = Vec::with_capacity(lines.len());
+    let mut pc = origin as i64;
+    for (number, line) in lines.iter().enumerate() {
+        let error = |e: String| format!("line {}: {}", number + 1, e);
+        if let Some(label) = &line.label {
+            define(&mut symbols, label, pc).map_err(error)?;
+        }
+        let context = Context { symbols: &symbols, symbol, pc };
+        let mut choice = None;
+        let mut assigned = None;
+        let size = match &line.statement {
+            Statement::Empty => 0,
+            Statement::Assign(_, expr) => {
+                assigned = context.eval(expr).map_err(error)?;
+                0
+            },
+            Statement::Org(expr) => {
+                pc = context.known(expr, "the .org address").map_err(error)?;
+                if !(0..=0xffff).contains(&pc) {
+                    return Err(error(format!("address {} out of range", pc)));
+                }
+                0
+            },
+            Statement::Data(width, items) => items.iter().map(|item| match item {
+                Item::Text(text) => text.len() as i64,
+                Item::Expr(_) => *width as i64,
+            }).sum(),
+            Statement::Reserve(count, _) => {
+                let count = context.known(count, "the .res size").map_err(error)?;
+                if count < 0 {
+                    return Err(error(format!("negative .res size {}", count)));
+                }
+                count
+            },
+            Statement::Instruction(name, operand) => {
+                let (op, mode) = select(name, operand, &context).map_err(error)?;
+                choice = Some((op, mode));
+                decode::instruction_length(op) as i64
+            },
+        };
+        if let (Statement::Assign(name, _), Some(value)) = (&line.statement, assigned) {
+            define(&mut symbols, name, value).map_err(error)?;
+        }
+        placed.push((pc, choice));
+        pc = match pc.checked_add(size) {
+            Some(end) if end <= 0x10000 => end,
+            _ => return Err(error("code runs past the end of memory".to_string())),
+        };
+    }
+
+    // Definitions that refer to later labels can only be resolved now
+    let mut progress = true;
+    while progress {
+        progress = false;
+        for line in &lines {
+            if let Statement::Assign(name, expr) = &line.statement {
+                if symbols.contains_key(name) {
+                    continue;
+                }
+                let context = Context { symbols: &symbols, symbol, pc: 0 };
+                if let Ok(Some(value)) = context.eval(expr) {
+                    symbols.insert(name.clone(), value);
+                    progress = true;
+                }
+            }
+        }
+    }
+
+    let mut assembly = Assembly::default();
+    for (number, (line, &(pc, choice))) in lines.iter().zip(placed.iter()).enumerate() {
+        let error = |e: String| format!("line {}: {}", number + 1, e);
+        let context = Context { symbols: &symbols, symbol, pc };
+        let addr = pc as u16;
+        match &line.statement {
+            Statement::Empty | Statement::Org(_) => {},
+            Statement::Assign(_, expr) => {
+                context.value(expr).map_err(error)?;
+            },
+            Statement::Data(width, items) => {
+                let mut bytes = vec![];
+                for item in items {
+                    match item {
+                        Item::Text(text) => bytes.extend_from_slice(text.as_bytes()),
+                        Item::Expr(expr) if *width == 1 => bytes.push(byte(context.value(expr).map_err(error)?).map_err(error)?),
+                        Item::Expr(expr) => {
+                            let word = word(context.value(expr).map_err(error)?).map_err(error)?;
+                            bytes.push(word as u8);
+                            bytes.push((word >> 8) as u8);
+                        },
+                    }
+                }
+                assembly.emit(addr, &bytes);
+            },
+            Statement::Reserve(count, fill) => {
+                let count = context.value(count).map_err(error)? as usize;
+                let fill = match fill {
+                    Some(fill) => byte(context.value(fill).map_err(error)?).map_err(error)?,
+                    None => 0,
+                };
+                assembly.emit(addr, &vec![fill; count]);
+            },
+            Statement::Instruction(name, operand) => {
+                let (op, mode) = choice.unwrap();
+                let bytes = encode(name, operand, op, mode, &context).map_err(error)?;
+                assembly.emit(addr, &bytes);
+            },
+        }
+    }
+    assembly.symbols = symbols.into_iter().map(|(name, value)| (name, value as u16)).collect();
+    Ok(assembly)
+}
+
+fn define(symbols: &mut HashMap<String, i64>, name: &str, value: i64) -> Result<(), String> {
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err(format!("{} is defined twice", name));
+    }
+    Ok(())
+}
+
+/// One source line, split into its parts
+struct Line {
+    label: Option<String>,
+    statement: Statement,
+}
+
+enum Statement {
+    Empty,
+    /// `name = expr`
+    Assign(String, String),
+    Org(String),
+    /// `.byte` or `.word` items, with the item width
+    Data(usize, Vec<Item>),
+    /// `.res count[, fill]`
+    Reserve(String, Option<String>),
+    Instruction(String, Operand),
+}
+
+enum Item {
+    Expr(String),
+    Text(String),
+}
+
+/// An operand as written, before its value is known. `Direct` and the
+/// indexed forms are zero page or absolute depending on the value, unless
+/// forced with ca65's `z:` or `a:` prefix.
+enum Operand {
+    None,
+    Accumulator,
+    Immediate(String),
+    Direct(Size, String),
+    IndexedX(Size, String),
+    IndexedY(Size, String),
+    IndirectX(String),
+    IndirectY(String),
+    Indirect(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Size {
+    Any,
+    ZeroPage,
+    Absolute,
+}
+
+/// `text` up to a `;` comment, leaving `;` in strings alone
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (';', None) => return &text[..i],
+            _ => {},
+        }
+    }
+    text
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '@' || c == '.'
+}
+
+fn parse_line(text: &str) -> Result<Line, String> {
+    let mut rest = strip_comment(text).trim();
+    let mut label = None;
+    let end = rest.find(|c: char| !is_symbol_char(c)).unwrap_or(rest.len());
+    if end > 0 && rest[end..].starts_with(':') && !rest[end..].starts_with(":=") {
+        label = Some(rest[..end].to_string());
+        rest = rest[end + 1..].trim();
+    }
+    if rest.is_empty() {
+        return Ok(Line { label, statement: Statement::Empty });
+    }
+
+    let end = rest.find(|c: char| !is_symbol_char(c)).unwrap_or(rest.len());
+    let (word, operand) = (&rest[..end], rest[end..].trim());
+    if operand.starts_with(":=") || operand.starts_with('=') {
+        let expr = operand.trim_start_matches(':').trim_start_matches('=').trim();
+        return Ok(Line { label, statement: Statement::Assign(word.to_string(), expr.to_string()) });
+    }
+    let statement = match word.to_lowercase().as_str() {
+        ".org" => Statement::Org(operand.to_string()),
+        ".byte" | ".byt" => Statement::Data(1, split_items(operand)?),
+        ".word" | ".addr" => Statement::Data(2, split_items(operand)?),
+        ".res" => {
+            let mut parts = split_list(operand).into_iter();
+            let count = parts.next().filter(|c| !c.is_empty()).ok_or(".res needs a size")?;
+            Statement::Reserve(count, parts.next())
+        },
+        directive if directive.starts_with('.') => return Err(format!("unsupported directive {}", word)),
+        _ => {
+            let name = word.to_uppercase();
+            if !(0..=255u8).any(|op| decode::get_opcode_name(op) == name) {
+                return Err(format!("unknown instruction {}", word));
+            }
+            Statement::Instruction(name, parse_operand(operand)?)
+        },
+    };
+    Ok(Line { label, statement })
+}
+
+/// Split a comma separated list, leaving commas in strings alone
+fn split_list(text: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut item = String::new();
+    let mut quote = None;
+    for c in text.chars() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (',', None) => {
+                items.push(item.trim().to_string());
+                item.clear();
+                continue;
+            },
+            _ => {},
+        }
+        item.push(c);
+    }
+    items.push(item.trim().to_string());
+    items
+}
+
+fn split_items(text: &str) -> Result<Vec<Item>, String> {
+    split_list(text).into_iter().map(|item| {
+        if item.is_empty() {
+            Err("missing value".to_string())
+        } else if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+            Ok(Item::Text(item[1..item.len() - 1].to_string()))
+        } else {
+            Ok(Item::Expr(item))
+        }
+    }).collect()
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_uppercase();
+    if text.is_empty() {
+        return Ok(Operand::None);
+    }
+    if compact == "A" {
+        return Ok(Operand::Accumulator);
+    }
+    if let Some(expr) = text.strip_prefix('#') {
+        return Ok(Operand::Immediate(expr.trim().to_string()));
+    }
+    // Everything before the last comma, and the inside of the parentheses
+    let before_index = || text[..text.rfind(',').unwrap()].trim();
+    if text.starts_with('(') && compact.ends_with(",X)") {
+        let inner = before_index();
+        return Ok(Operand::IndirectX(inner[1..].to_string()));
+    }
+    if text.starts_with('(') && compact.ends_with("),Y") {
+        let inner = before_index();
+        return Ok(Operand::IndirectY(inner[1..inner.len() - 1].to_string()));
+    }
+    if text.starts_with('(') && text.ends_with(')') && closing_paren(text) == Some(text.len() - 1) {
+        return Ok(Operand::Indirect(text[1..text.len() - 1].to_string()));
+    }
+    let (size, expr) = if compact.ends_with(",X") || compact.ends_with(",Y") {
+        size_prefix(before_index())
+    } else {
+        size_prefix(text)
+    };
+    Ok(if compact.ends_with(",X") {
+        Operand::IndexedX(size, expr)
+    } else if compact.ends_with(",Y") {
+        Operand::IndexedY(size, expr)
+    } else {
+        Operand::Direct(size, expr)
+    })
+}
+
+/// Position of the parenthesis closing the one `text` starts with
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Split off ca65's `z:` and `a:` address size prefixes
+fn size_prefix(text: &str) -> (Size, String) {
+    let lower = text.to_lowercase();
+    if lower.starts_with("z:") {
+        (Size::ZeroPage, text[2..].trim().to_string())
+    } else if lower.starts_with("a:") {
+        (Size::Absolute, text[2..].trim().to_string())
+    } else {
+        (Size::Any, text.to_string())
+    }
+}
+
+/// Addressing mode of a documented opcode, as the assembler sees it
+fn mode_of(opcode: u8) -> Option<ADRESSING_MODE> {
+    if !decode::is_documented(opcode) {
+        return None;
+    }
+    let name = decode::get_opcode_name(opcode);
+    Some(if decode::IMPLIED.contains(&name) || is_branch(name) {
+        ADRESSING_MODE::NONE
+    } else if opcode == 0x20 {
+        ADRESSING_MODE::ABSOLUTE
+    } else {
+        decode::get_adressing_mode(opcode)
+    })
+}
+
+fn opcode(name: &str, mode: ADRESSING_MODE) -> Option<u8> {
+    (0..=255u8).find(|&op| decode::get_opcode_name(op) == name && mode_of(op) == Some(mode))
+}
+
+fn is_branch(name: &str) -> bool {
+    ["BPL", "BMI", "BVC", "BVS", "BCC", "BCS", "BNE", "BEQ"].contains(&name)
+}
+
+/// The opcode and mode for an instruction, zero page when the value is
+/// known to fit and the instruction has a zero page form
+fn select(name: &str, operand: &Operand, context: &Context) -> Result<(u8, ADRESSING_MODE), String> {
+    use ADRESSING_MODE::*;
+    let sized = |size: Size, expr: &str, zero_page: ADRESSING_MODE, absolute: ADRESSING_MODE| -> Result<ADRESSING_MODE, String> {
+        let fits = match size {
+            Size::ZeroPage => true,
+            Size::Absolute => false,
+            Size::Any => opcode(name, zero_page).is_some()
+                && (opcode(name, absolute).is_none() || context.eval(expr)?.is_some_and(|v| (0..=0xff).contains(&v))),
+        };
+        Ok(if fits { zero_page } else { absolute })
+    };
+    let mode = match operand {
+        Operand::None if opcode(name, ACCUMULATOR).is_some() => ACCUMULATOR,
+        Operand::None => NONE,
+        Operand::Accumulator => ACCUMULATOR,
+        Operand::Immediate(_) => IMMEDIATE,
+        Operand::Direct(_, _) if is_branch(name) => NONE,
+        Operand::Direct(size, expr) => sized(*size, expr, ZERO_PAGE, ABSOLUTE)?,
+        Operand::IndexedX(size, expr) => sized(*size, expr, ZERO_PAGE_X, ABSOLUTE_X)?,
+        Operand::IndexedY(size, expr) => sized(*size, expr, ZERO_PAGE_Y, ABSOLUTE_Y)?,
+        Operand::IndirectX(_) => INDIRECT_X,
+        Operand::IndirectY(_) => INDIRECT_Y,
+        Operand::Indirect(_) => INDIRECT,
+    };
+    // Branches need a target and implied instructions take nothing
+    let missing = match operand {
+        Operand::None => is_branch(name),
+        _ => mode == NONE && !is_branch(name),
+    };
+    match opcode(name, mode) {
+        Some(op) if !missing => Ok((op, mode)),
+        _ => Err(format!("{} does not take this operand", name)),
+    }
+}
+
+/// The bytes of an instruction, with the opcode and mode picked for it
+/// in the first pass
+fn encode(name: &str, operand: &Operand, op: u8, mode: ADRESSING_MODE, context: &Context) -> Result<Vec<u8>, String> {
+    let expr = match operand {
+        Operand::None | Operand::Accumulator => return Ok(vec![op]),
+        Operand::Immediate(expr) | Operand::Direct(_, expr) | Operand::IndexedX(_, expr) | Operand::IndexedY(_, expr)
+        | Operand::IndirectX(expr) | Operand::IndirectY(expr) | Operand::Indirect(expr) => expr,
+    };
+    let value = context.value(expr)?;
+    if is_branch(name) {
+        let offset = value.checked_sub(context.pc + 2).unwrap_or(i64::MAX);
+        if !(-128..=127).contains(&offset) {
+            return Err(format!("branch to {:#06x} is {} bytes away, out of range", value, offset));
+        }
+        return Ok(vec![op, offset as u8]);
+    }
+    match decode::instruction_length(op) {
+        2 if mode == ADRESSING_MODE::IMMEDIATE => Ok(vec![op, byte(value)?]),
+        2 => match value {
+            0..=0xff => Ok(vec![op, value as u8]),
+            _ => Err(format!("{:#x} does not fit in zero page", value)),
+        },
+        _ => {
+            let word = word(value)?;
+            Ok(vec![op, word as u8, (word >> 8) as u8])
+        },
+    }
+}
+
+fn byte(value: i64) -> Result<u8, String> {
+    if !(-128..=0xff).contains(&value) {
+        return Err(format!("{} does not fit in a byte", value));
+    }
+    Ok(value as u8)
+}
+
+fn word(value: i64) -> Result<u16, String> {
+    if !(-0x8000..=0xffff).contains(&value) {
+        return Err(format!("{} does not fit in a word", value));
+    }
+    Ok(value as u16)
+}
+
+/// What expressions can refer to: the symbols defined so far, outside
+/// symbols and `*` for the address of the current line
+struct Context<'a> {
+    symbols: &'a HashMap<String, i64>,
+    symbol: &'a dyn Fn(&str) -> Option<u16>,
+    pc: i64,
+}
+
+impl<'a> Context<'a> {
+    /// The value of `expr`, or `None` while it uses undefined symbols
+    fn eval(&self, expr: &str) -> Result<Option<i64>, String> {
+        let mut parser = Parser { context: self, chars: expr.chars().collect(), pos: 0 };
+        let value = parser.binary(0)?;
+        parser.skip_spaces();
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unexpected {} in {}", parser.chars[parser.pos], expr));
+        }
+        Ok(value)
+    }
+
+    /// The value of `expr`, which must be defined by now
+    fn value(&self, expr: &str) -> Result<i64, String> {
+        self.eval(expr)?.ok_or_else(|| format!("undefined symbol in {}", expr))
+    }
+
+    /// The value of `expr`, which must not refer to later lines
+    fn known(&self, expr: &str, what: &str) -> Result<i64, String> {
+        self.eval(expr)?.ok_or_else(|| format!("{} must not depend on later lines", what))
+    }
+}
+
+/// Binary operators from the loosest binding, like C
+const OPERATORS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+struct Parser<'a> {
+    context: &'a Context<'a>,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_spaces(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Consume `token` if it comes next
+    fn accept(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        let end = self.pos + token.chars().count();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(token.chars()) {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Option<i64>, String> {
+        if level == OPERATORS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        'outer: loop {
+            for &operator in OPERATORS[level] {
+                if self.accept(operator) {
+                    let right = self.binary(level + 1)?;
+                    left = match (left, right) {
+                        (Some(l), Some(r)) => Some(apply(operator, l, r)?),
+                        _ => None,
+                    };
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Option<i64>, String> {
+        for &operator in ["-", "~", "<", ">", "+"].iter() {
+            if self.accept(operator) {
+                let value = match self.unary()? {
+                    Some(v) => v,
+                    None => return Ok(None),
+                };
+                return Ok(Some(match operator {
+                    "-" => value.checked_neg().ok_or_else(|| overflow(operator))?,
+                    "~" => !value,
+                    "<" => value & 0xff,
+                    ">" => (value >> 8) & 0xff,
+                    _ => value,
+                }));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Option<i64>, String> {
+        self.skip_spaces();
+        let start = self.pos;
+        let take = |parser: &mut Parser, valid: &dyn Fn(char) -> bool| {
+            let from = parser.pos;
+            while parser.peek().is_some_and(valid) {
+                parser.pos += 1;
+            }
+            parser.chars[from..parser.pos].iter().collect::<String>()
+        };
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let value = self.binary(0)?;
+                if !self.accept(")") {
+                    return Err("missing )".to_string());
+                }
+                Ok(value)
+            },
+            Some('*') => {
+                self.pos += 1;
+                Ok(Some(self.context.pc))
+            },
+            Some('$') => {
+                self.pos += 1;
+                let digits = take(self, &|c| c.is_ascii_hexdigit());
+                i64::from_str_radix(&digits, 16).map(Some).map_err(|_| format!("invalid number ${}", digits))
+            },
+            Some('%') => {
+                self.pos += 1;
+                let digits = take(self, &|c| c == '0' || c == '1');
+                i64::from_str_radix(&digits, 2).map(Some).map_err(|_| format!("invalid number %{}", digits))
+            },
+            Some('\'') => {
+                let c = self.chars.get(self.pos + 1).copied().ok_or("missing character")?;
+                self.pos += 2;
+                self.accept("'");
+                Ok(Some(c as i64))
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let digits = take(self, &|c| c.is_ascii_digit());
+                digits.parse().map(Some).map_err(|_| format!("invalid number {}", digits))
+            },
+            Some(c) if is_symbol_char(c) => {
+                let name = take(self, &is_symbol_char);
+                match self.context.symbols.get(&name) {
+                    Some(&value) => Ok(Some(value)),
+                    None => Ok((self.context.symbol)(&name).map(i64::from)),
+                }
+            },
+            Some(c) => Err(format!("unexpected {}", c)),
+            None if start == 0 => Err("missing value".to_string()),
+            None => Err("expression ends early".to_string()),
+        }
+    }
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64, String> {
+    let value = match operator {
+        "|" => Some(left | right),
+        "^" => Some(left ^ right),
+        "&" => Some(left & right),
+        "<<" => Some(left << (right & 63)),
+        ">>" => Some(left >> (right & 63)),
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" | "%" if right == 0 => return Err("division by zero".to_string()),
+        "/" => left.checked_div(right),
+        _ => left.checked_rem(right),
+    };
+    value.ok_or_else(|| overflow(operator))
+}
+
+fn overflow(operator: &str) -> String {
+    format!("{} overflows", operator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bytes `source` assembles to at $0400, which must be one run
+    fn bytes(source: &str) -> Vec<u8> {
+        let assembly = assemble(source, 0x400).unwrap();
+        assert_eq!(assembly.segments.len(), 1, "{:?}", assembly.segments);
+        assembly.segments[0].1.clone()
+    }
+
+    fn error(source: &str) -> String {
+        assemble(source, 0x400).unwrap_err()
+    }
+
+    #[test]
+    fn picks_zero_page_for_known_small_values() {
+        assert_eq!(bytes("LDA $10"), vec![0xa5, 0x10]);
+        assert_eq!(bytes("LDA $1234"), vec![0xad, 0x34, 0x12]);
+        assert_eq!(bytes("LDA $10,X"), vec![0xb5, 0x10]);
+        assert_eq!(bytes("LDX $10,Y"), vec![0xb6, 0x10]);
+        // LDA has no zero page,Y form
+        assert_eq!(bytes("LDA $10,Y"), vec![0xb9, 0x10, 0x00]);
+    }
+
+    #[test]
+    fn forward_references_are_absolute_unless_forced() {
+        assert_eq!(bytes("LDA later\nlater = $20"), vec![0xad, 0x20, 0x00]);
+        assert_eq!(bytes("LDA z:later\nlater = $20"), vec![0xa5, 0x20]);
+        assert_eq!(bytes("LDA a:$10"), vec![0xad, 0x10, 0x00]);
+        assert_eq!(bytes("early = $20\nLDA early"), vec![0xa5, 0x20]);
+    }
+
+    #[test]
+    fn parses_indirect_operands() {
+        assert_eq!(bytes("LDA ($10),Y"), vec![0xb1, 0x10]);
+        assert_eq!(bytes("LDA ( $10 ) , y"), vec![0xb1, 0x10]);
+        assert_eq!(bytes("LDA ($10,X)"), vec![0xa1, 0x10]);
+        assert_eq!(bytes("JMP ($1234)"), vec![0x6c, 0x34, 0x12]);
+        // Parentheses around an expression are not indirection
+        assert_eq!(bytes("JMP ($1000)+$234"), vec![0x4c, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn checks_branch_range() {
+        assert_eq!(bytes("LOOP: BNE LOOP"), vec![0xd0, 0xfe]);
+        assert_eq!(bytes("BNE *+129"), vec![0xd0, 0x7f]);
+        assert!(error("BNE *+130").contains("out of range"));
+        assert!(error("BNE *-127").contains("out of range"));
+        assert!(error("BNE -$7fffffffffffffff").contains("out of range"));
+    }
+
+    #[test]
+    fn reserves_with_fill() {
+        assert_eq!(bytes(".res 3, $ea\n.byte 1"), vec![0xea, 0xea, 0xea, 1]);
+        assert_eq!(bytes(".res 2\n.byte 1"), vec![0, 0, 1]);
+        assert!(error(".res $7fffffffffffffff").contains("past the end"));
+        assert!(error(".res -1").contains("negative"));
+    }
+
+    #[test]
+    fn reads_characters_and_strings() {
+        assert_eq!(bytes("LDA #'A'"), vec![0xa9, 0x41]);
+        assert_eq!(bytes(".byte \"a;b\", ';' ; a comment"), vec![0x61, 0x3b, 0x62, 0x3b]);
+        assert_eq!(bytes(".byte \"a,b\""), vec![0x61, 0x2c, 0x62]);
+    }
+
+    #[test]
+    fn definitions_may_use_later_labels() {
+        let assembly = assemble("ptr = target + 1\n.word ptr\ntarget: NOP", 0x400).unwrap();
+        assert_eq!(assembly.segments[0].1, vec![0x03, 0x04, 0xea]);
+        assert_eq!(assembly.symbols["ptr"], 0x403);
+        assert_eq!(assembly.symbols["target"], 0x402);
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        assert_eq!(error("NOP\n\nFOO"), "line 3: unknown instruction FOO");
+        assert_eq!(error("NOP\nLDA #$100"), "line 2: 256 does not fit in a byte");
+        assert_eq!(error("x: NOP\nx: NOP"), "line 2: x is defined twice");
+        assert_eq!(error("LDA missing"), "line 1: undefined symbol in missing");
+    }
+
+    #[test]
+    fn arithmetic_overflow_is_an_error() {
+        assert!(error("LDA #$100000000 * $100000000").contains("overflows"));
+        assert!(error("LDA #$7fffffffffffffff + 1").contains("overflows"));
+        assert!(error("LDA #-$7fffffffffffffff - 2").contains("overflows"));
+        assert!(error("LDA #-(-$7fffffffffffffff - 1)").contains("overflows"));
+        assert!(error("LDA #1 / 0").contains("division by zero"));
+        assert_eq!(bytes("LDA #2 * 3 + 1"), vec![0xa9, 7]);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::computer::device::Device;
+use crate::computer::memory::Memory;
+
+/// Snapshot of a banked window, for display
+#[derive(Clone, Debug)]
+pub struct BankInfo {
+    pub register: u16,
+    pub window: u16,
+    pub size: usize,
+    pub count: usize,
+    pub active: usize,
+    pub rom: bool,
+}
+
+/// Bank switching memory controller.
+///
+/// `count` banks of `size` bytes share the window starting at `window`.
+/// Writing a bank number to `register` pages that bank in. The selected
+/// bank is copied into main memory so the processor can fetch code from
+/// it directly, and copied back out when another bank is selected.
+#[derive(Debug)]
+pub struct BankSwitch {
+    register: u16,
+    window: u16,
+    size: usize,
+    banks: Vec<Vec<u8>>,
+    active: usize,
+    mapped: Option<usize>,
+    rom: bool,
+}
+
+impl BankSwitch {
+    /// Create the banks, filled from `contents` in order and padded with zeroes
+    pub fn new(register: u16, window: u16, size: usize, count: usize, contents: &[u8], rom: bool) -> Result<BankSwitch, String> {
+        if count == 0 || size == 0 {
+            return Err("a banked window needs at least one bank of at least one byte".to_string());
+        }
+        if count > 256 {
+            return Err(format!("an 8-bit bank register selects at most 256 banks, not {}", count));
+        }
+        if window as usize + size > 0x10000 {
+            return Err(format!("{} byte banks do not fit in a window at {:#06x}", size, window));
+        }
+        if register >= window && ((register - window) as usize) < size {
+            return Err(format!("bank register {:#06x} is inside its own window", register));
+        }
+        let banks = (0..count).map(|i| {
+            let mut bank = vec![0; size];
+            let start = (i * size).min(contents.len());
+            let end = ((i + 1) * size).min(contents.len());
+            bank[..end - start].copy_from_slice(&contents[start..end]);
+            bank
+        }).collect();
+
+        Ok(BankSwitch {
+            register,
+            window,
+            size,
+            banks,
+            active: 0,
+            mapped: None,
+            rom,
+        })
+    }
+
+    fn in_window(&self, addr: u16) -> bool {
+        addr >= self.window && ((addr - self.window) as usize) < self.size
+    }
+}
+
+impl Device for BankSwitch {
+    fn name(&self) -> String {
+        let size = if self.size.is_multiple_of(1024) { format!("{} KiB", self.size / 1024) } else { format!("{} bytes", self.size) };
+        format!("{} banks of {} at {:#06x} selected by {:#06x}", self.banks.len(), size, self.window, self.register)
+    }
+
+    fn handles(&self, addr: u16) -> bool {
+        // RAM banks live in main memory while mapped, ROM banks are
+        // handled here so writes to them can be ignored
+        addr == self.register || (self.rom && self.in_window(addr))
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr == self.register {
+            return self.active as u8;
+        }
+        self.banks[self.active][(addr - self.window) as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if addr == self.register {
+            self.active = value as usize % self.banks.len();
+        }
+    }
+
+    fn map(&mut self, memory: &mut Memory) {
+        if self.mapped == Some(self.active) {
+            return;
+        }
+        let start = self.window as usize;
+        let end = start + self.size;
+        if let Some(old) = self.mapped {
+            if !self.rom {
+                self.banks[old].copy_from_slice(&memory.bytes()[start..end]);
+            }
+        }
+        memory.write_range(start, &self.banks[self.active]);
+        self.mapped = Some(self.active);
+    }
+
+    fn bank_info(&self) -> Option<BankInfo> {
+        Some(BankInfo {
+            register: self.register,
+            window: self.window,
+            size: self.size,
+            count: self.banks.len(),
+            active: self.active,
+            rom: self.rom,
+        })
+    }
+
+    fn read_bank(&self, bank: usize, memory: &[u8]) -> Option<Vec<u8>> {
+        if Some(bank) == self.mapped {
+            // The mapped bank may have been written to since it was paged in
+            let start = self.window as usize;
+            return Some(memory[start..start + self.size].to_vec());
+        }
+        self.banks.get(bank).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTER: u16 = 0xbfff;
+    const WINDOW: u16 = 0x8000;
+
+    /// Four 16 byte banks, each filled with its own number
+    fn banks(rom: bool) -> (BankSwitch, Memory) {
+        let contents: Vec<u8> = (0..64).map(|i| i / 16).collect();
+        let banks = BankSwitch::new(REGISTER, WINDOW, 16, 4, &contents, rom).unwrap();
+        (banks, Memory::new(vec![0xee; 0x10000]))
+    }
+
+    fn window(memory: &Memory) -> &[u8] {
+        &memory.bytes()[WINDOW as usize..WINDOW as usize + 16]
+    }
+
+    #[test]
+    fn rejects_bad_windows() {
+        assert!(BankSwitch::new(REGISTER, WINDOW, 0, 4, &[], false).is_err());
+        assert!(BankSwitch::new(REGISTER, WINDOW, 16, 0, &[], false).is_err());
+        assert!(BankSwitch::new(REGISTER, WINDOW, 16, 257, &[], false).is_err());
+        assert!(BankSwitch::new(REGISTER, WINDOW, 16, 256, &[], false).is_ok());
+        assert!(BankSwitch::new(REGISTER, 0xfff0, 17, 4, &[], false).is_err());
+        assert!(BankSwitch::new(WINDOW + 15, WINDOW, 16, 4, &[], false).is_err());
+        assert!(BankSwitch::new(WINDOW + 16, WINDOW, 16, 4, &[], false).is_ok());
+    }
+
+    #[test]
+    fn register_selects_the_bank() {
+        let (mut banks, mut memory) = banks(false);
+        assert!(banks.handles(REGISTER) && !banks.handles(WINDOW));
+        banks.map(&mut memory);
+        assert_eq!(window(&memory), &[0; 16]);
+        banks.write(REGISTER, 2);
+        assert_eq!(banks.read(REGISTER), 2);
+        banks.map(&mut memory);
+        assert_eq!(window(&memory), &[2; 16]);
+        // Numbers past the last bank wrap round
+        banks.write(REGISTER, 7);
+        assert_eq!(banks.read(REGISTER), 3);
+        assert_eq!(banks.bank_info().unwrap().active, 3);
+        assert_eq!(memory.bytes()[WINDOW as usize + 16], 0xee);
+    }
+
+    #[test]
+    fn ram_writes_survive_a_swap() {
+        let (mut banks, mut memory) = banks(false);
+        banks.map(&mut memory);
+        memory.write(WINDOW + 5, 0x42);
+        banks.write(REGISTER, 1);
+        banks.map(&mut memory);
+        assert_eq!(window(&memory)[5], 1);
+        banks.write(REGISTER, 0);
+        banks.map(&mut memory);
+        assert_eq!(window(&memory)[5], 0x42);
+    }
+
+    #[test]
+    fn rom_ignores_writes() {
+        let (mut banks, mut memory) = banks(true);
+        banks.write(REGISTER, 1);
+        banks.map(&mut memory);
+        assert!(banks.handles(WINDOW) && banks.handles(WINDOW + 15) && !banks.handles(WINDOW + 16));
+        banks.write(WINDOW + 5, 0x42);
+        assert_eq!(banks.read(WINDOW + 5), 1);
+        // Even if something wrote main memory under the window
+        memory.write(WINDOW + 5, 0x42);
+        banks.write(REGISTER, 0);
+        banks.map(&mut memory);
+        banks.write(REGISTER, 1);
+        banks.map(&mut memory);
+        assert_eq!(window(&memory)[5], 1);
+    }
+
+    #[test]
+    fn read_bank_sees_live_memory() {
+        let (mut banks, mut memory) = banks(false);
+        banks.write(REGISTER, 1);
+        banks.map(&mut memory);
+        memory.write(WINDOW, 0x42);
+        assert_eq!(banks.read_bank(1, memory.bytes()).unwrap()[0], 0x42);
+        assert_eq!(banks.read_bank(2, memory.bytes()), Some(vec![2; 16]));
+        assert_eq!(banks.read_bank(4, memory.bytes()), None);
+    }
+
+    #[test]
+    fn names_small_banks_in_bytes() {
+        assert_eq!(banks(false).0.name(), "4 banks of 16 bytes at 0x8000 selected by 0xbfff");
+        let big = BankSwitch::new(REGISTER, WINDOW, 0x2000, 2, &[], false).unwrap();
+        assert_eq!(big.name(), "2 banks of 8 KiB at 0x8000 selected by 0xbfff");
+    }
+}
@@ -0,0 +1,89 @@
+const OPCODE_BRK: u8 = 0x00;
+const OPCODE_JSR: u8 = 0x20;
+const OPCODE_RTI: u8 = 0x40;
+const OPCODE_RTS: u8 = 0x60;
+const OPCODE_TXS: u8 = 0x9a;
+
+/// Frames kept before the oldest are dropped, for code that calls
+/// without ever returning
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CallKind {
+    Subroutine,
+    Break,
+    Interrupt,
+}
+
+/// One active call, innermost last in `CallStack::frames`
+#[derive(Clone, Copy, Debug)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    /// Address that was called, or the interrupt handler
+    pub routine: u16,
+    /// Address of the JSR or BRK, or of the interrupted instruction
+    pub call_site: u16,
+    /// Where execution resumes once the call returns
+    pub return_addr: u16,
+    /// Stack pointer before the call, used to match the return
+    pub sp: u8,
+}
+
+/// The chain of calls reconstructed from JSR, BRK, interrupts and their
+/// returns. Like the profiler it matches returns by stack pointer, so
+/// code that drops return addresses or reloads SP still unwinds.
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack::default()
+    }
+
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    fn enter(&mut self, kind: CallKind, routine: u16, call_site: u16, return_addr: u16, sp: u8) {
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(CallFrame { kind, routine, call_site, return_addr, sp });
+    }
+
+    /// Drop every frame the stack pointer has moved back past
+    fn leave(&mut self, sp: u8) {
+        while self.frames.last().is_some_and(|frame| returned_past(sp, frame.sp)) {
+            self.frames.pop();
+        }
+    }
+
+    /// Follow one executed instruction. `sp` is the stack pointer before it
+    /// ran, `next_pc` and `next_sp` the state after.
+    pub fn record(&mut self, pc: u16, opcode: u8, sp: u8, next_pc: u16, next_sp: u8) {
+        match opcode {
+            OPCODE_JSR => self.enter(CallKind::Subroutine, next_pc, pc, pc.wrapping_add(3), sp),
+            OPCODE_BRK => self.enter(CallKind::Break, next_pc, pc, pc.wrapping_add(2), sp),
+            OPCODE_RTS | OPCODE_RTI | OPCODE_TXS => self.leave(next_sp),
+            _ => {},
+        }
+    }
+
+    /// Follow a hardware interrupt taken at `pc`
+    pub fn interrupt(&mut self, pc: u16, sp: u8, handler: u16) {
+        self.enter(CallKind::Interrupt, handler, pc, pc, sp);
+    }
+}
+
+/// Whether the stack pointer `sp` is back at or above `frame_sp`. The
+/// stack wraps within page one, so this looks at the distance between
+/// them rather than which is larger.
+pub fn returned_past(sp: u8, frame_sp: u8) -> bool {
+    sp.wrapping_sub(frame_sp) < 0x80
+}
@@ -0,0 +1,154 @@
+use std::io::{self, Write};
+
+use crate::computer::decode;
+
+/// The byte was fetched as an opcode
+pub const EXECUTED: u8 = 1;
+/// The byte was fetched as an instruction operand
+pub const OPERAND: u8 = 2;
+/// The byte was read as data
+pub const READ: u8 = 4;
+/// The byte was written
+pub const WRITTEN: u8 = 8;
+
+/// How every byte of memory has been accessed so far
+#[derive(Clone, Debug)]
+pub struct Coverage {
+    map: Vec<u8>,
+    /// Bumped whenever a byte gains a new kind of access
+    generation: u64,
+}
+
+/// Single character summary of the accesses to one byte
+pub fn marker(access: u8) -> char {
+    if access & EXECUTED != 0 {
+        'X'
+    } else if access & OPERAND != 0 {
+        'o'
+    } else if access & (READ | WRITTEN) == READ | WRITTEN {
+        'B'
+    } else if access & WRITTEN != 0 {
+        'W'
+    } else if access & READ != 0 {
+        'R'
+    } else {
+        '.'
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage { map: vec![0; 0x10000], generation: 0 }
+    }
+
+    pub fn mark(&mut self, addr: u16, access: u8) {
+        let byte = &mut self.map[addr as usize];
+        if *byte | access != *byte {
+            *byte |= access;
+            self.generation += 1;
+        }
+    }
+
+    /// Changes whenever the map does, so readers can tell when to fetch it
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Mark the opcode at `pc` as executed and its operands as fetched
+    pub fn mark_instruction(&mut self, pc: u16, opcode: u8) {
+        self.mark(pc, EXECUTED);
+        for i in 1..decode::instruction_length(opcode) {
+            self.mark(pc.wrapping_add(i), OPERAND);
+        }
+    }
+
+    pub fn map(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Write a summary of the address ranges covered by each kind of access
+    pub fn write_summary(&self, out: &mut dyn Write) -> io::Result<()> {
+        let kinds = [(EXECUTED, "Executed"), (OPERAND, "Operands"), (READ, "Read"), (WRITTEN, "Written")];
+        for &(kind, title) in kinds.iter() {
+            let total = self.map.iter().filter(|&&a| a & kind != 0).count();
+            writeln!(out, "{}: {} bytes", title, total)?;
+            let mut start: Option<usize> = None;
+            for addr in 0..=self.map.len() {
+                let covered = addr < self.map.len() && self.map[addr] & kind != 0;
+                match (covered, start) {
+                    (true, None) => start = Some(addr),
+                    (false, Some(first)) => {
+                        writeln!(out, "  {:#06x}-{:#06x}", first, addr - 1)?;
+                        start = None;
+                    },
+                    _ => {},
+                }
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// Copy a ca65 listing, prefixing every line that emitted bytes with
+    /// one marker per byte: X executed, o operand, R read, W written,
+    /// B read and written, . untouched
+    pub fn write_listing(&self, listing: &str, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "; coverage: X executed, o operand, R read, W written, B read and written, . untouched")?;
+        for line in listing.lines() {
+            let markers: String = match listing_bytes(line) {
+                Some((addr, count)) => (0..count)
+                    .map(|i| marker(self.map[(addr as usize + i) & 0xffff]))
+                    .collect(),
+                None => String::new(),
+            };
+            writeln!(out, "{:<4} {}", markers, line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Address and number of bytes emitted by a ca65 listing line, like
+/// `00057C  1  F0 03                beq range_end`. Relocatable lines,
+/// whose address ends in `r`, have no known address and are skipped.
+pub fn listing_bytes(line: &str) -> Option<(u16, usize)> {
+    if line.len() < 11 || !line.is_char_boundary(6) || &line[6..7] != " " {
+        return None;
+    }
+    let addr = u32::from_str_radix(&line[0..6], 16).ok()?;
+    if addr > 0xffff {
+        return None;
+    }
+    let end = line.len().min(23);
+    let field = line.get(11..end)?;
+    let count = field.split_whitespace()
+        .take_while(|b| b.len() == 2 && u8::from_str_radix(b, 16).is_ok())
+        .count();
+    if count == 0 {
+        return None;
+    }
+    Some((addr as u16, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generation_moves_only_with_new_accesses() {
+        let mut coverage = Coverage::new();
+        coverage.mark(0x1234, READ);
+        let after_read = coverage.generation();
+        assert!(after_read > 0);
+        coverage.mark(0x1234, READ);
+        assert_eq!(coverage.generation(), after_read);
+        coverage.mark(0x1234, WRITTEN);
+        assert!(coverage.generation() > after_read);
+        assert_eq!(marker(coverage.map()[0x1234]), 'B');
+    }
+}
@@ -0,0 +1,672 @@
+use crate::computer::{alu, decode, Computer, Processor, Variant, ADRESSING_MODE};
+
+const FLAG_C: u8 = 1;
+const FLAG_Z: u8 = 2;
+const FLAG_I: u8 = 4;
+const FLAG_D: u8 = 8;
+const FLAG_B: u8 = 0x10;
+const FLAG_UNUSED: u8 = 0x20;
+const FLAG_O: u8 = 0x40;
+const FLAG_N: u8 = 0x80;
+
+const IRQ_VECTOR: u16 = 0xfffe;
+
+/// One bus access, as it appears on the address and data pins
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BusCycle {
+    pub address: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+/// What the cycle stepped core is wired to: its registers, the bus and
+/// the IRQ line
+pub trait Bus {
+    /// The registers the core works on
+    fn registers(&mut self) -> &mut Processor;
+
+    /// Read data, a pointer, the stack or a vector. Dummy reads go
+    /// through here too, since they have the same side effects.
+    fn read(&mut self, address: u16) -> u8;
+
+    /// Fetch an opcode or an operand at the program counter
+    fn fetch(&mut self, address: u16) -> u8 {
+        self.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Whether a device is pulling the IRQ line low
+    fn irq(&mut self) -> bool {
+        false
+    }
+}
+
+/// What the core finished on the cycle it just ran
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Completed {
+    /// An instruction, with the address and stack pointer it started with
+    Instruction { pc: u16, opcode: u8, sp: u8, cycles: u64 },
+    /// An interrupt was taken at `pc`, with the stack at `sp`
+    Interrupt { pc: u16, sp: u8, handler: u16 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    Modify,
+}
+
+/// A 6502 run one bus cycle at a time, with the dummy reads and writes
+/// of the real chip: the extra read of an indexed address before
+/// its high byte is fixed, the double write of read-modify-write
+/// instructions, and the reads of the program counter and stack that
+/// pad out implied instructions, pushes, pulls and branches.
+#[derive(Clone, Copy, Debug)]
+pub struct CycleCpu {
+    variant: Variant,
+    /// Cycle of the current instruction to run next, 0 to fetch an opcode
+    step: u8,
+    opcode: u8,
+    name: &'static str,
+    mode: ADRESSING_MODE,
+    /// Effective address, or the target of a jump or branch
+    addr: u16,
+    /// Whether indexing `addr` carried into the high byte
+    crossed: bool,
+    /// Zero page pointer of an indirect address
+    pointer: u8,
+    /// Value read by a read-modify-write instruction
+    data: u8,
+    /// Whether the cycles belong to an interrupt rather than an opcode
+    interrupt: bool,
+    /// Whether a decimal result still needs the 65C02's extra cycle
+    fixup: bool,
+    start_pc: u16,
+    start_sp: u8,
+    cycles: u64,
+    last: BusCycle,
+    completed: Option<Completed>,
+}
+
+impl CycleCpu {
+    pub fn new(variant: Variant) -> CycleCpu {
+        CycleCpu {
+            variant,
+            step: 0,
+            opcode: 0xea,
+            name: "NOP",
+            mode: ADRESSING_MODE::NONE,
+            addr: 0,
+            crossed: false,
+            pointer: 0,
+            data: 0,
+            interrupt: false,
+            fixup: false,
+            start_pc: 0,
+            start_sp: 0,
+            cycles: 0,
+            last: BusCycle::default(),
+            completed: None,
+        }
+    }
+
+    /// Whether the next cycle starts a new instruction or interrupt
+    pub fn at_boundary(&self) -> bool {
+        self.step == 0
+    }
+
+    /// The instruction or interrupt the last cycle finished, if any
+    pub fn completed(&self) -> Option<Completed> {
+        self.completed
+    }
+
+    /// Run one clock cycle and return the bus access it made
+    pub fn tick(&mut self, bus: &mut dyn Bus) -> BusCycle {
+        self.completed = None;
+        if self.step == 0 {
+            let processor = bus.registers();
+            self.start_pc = processor.pc;
+            self.start_sp = processor.sp;
+            self.cycles = 0;
+            let masked = processor.flags & FLAG_I != 0;
+            self.interrupt = !masked && bus.irq();
+        }
+        self.cycles += 1;
+        let step = self.step;
+        self.step += 1;
+        if self.interrupt {
+            self.irq(bus, step);
+        } else if step == 0 {
+            self.decode(bus);
+        } else {
+            self.execute(bus, step);
+        }
+        bus.registers().clock += 1;
+        self.last
+    }
+
+    fn decode(&mut self, bus: &mut dyn Bus) {
+        self.opcode = self.fetch(bus);
+        bus.registers().inst = self.opcode;
+        if decode::is_documented(self.opcode) {
+            self.name = decode::get_opcode_name(self.opcode);
+            self.mode = decode::get_adressing_mode(self.opcode);
+        } else {
+            // Undocumented opcodes run as one byte NOPs, like in the
+            // instruction core
+            self.name = "";
+            self.mode = ADRESSING_MODE::NONE;
+        }
+    }
+
+    fn execute(&mut self, bus: &mut dyn Bus, step: u8) {
+        if self.fixup {
+            self.fixup = false;
+            self.dummy_fetch(bus);
+            self.finish();
+            return;
+        }
+        match self.name {
+            "BRK" => self.brk(bus, step),
+            "JSR" => self.jsr(bus, step),
+            "RTI" => self.rti(bus, step),
+            "RTS" => self.rts(bus, step),
+            "PHA" | "PHP" => self.push_register(bus, step),
+            "PLA" | "PLP" => self.pull_register(bus, step),
+            "JMP" => self.jmp(bus, step),
+            _ if self.opcode & 0x1f == 0x10 => self.branch(bus, step),
+            _ if self.mode == ADRESSING_MODE::NONE || self.mode == ADRESSING_MODE::ACCUMULATOR => {
+                self.dummy_fetch(bus);
+                self.implied(bus.registers());
+                self.finish();
+            },
+            _ => self.addressed(bus, step),
+        }
+    }
+
+    fn finish(&mut self) {
+        self.step = 0;
+        self.completed = Some(Completed::Instruction { pc: self.start_pc, opcode: self.opcode, sp: self.start_sp, cycles: self.cycles });
+    }
+
+    fn read(&mut self, bus: &mut dyn Bus, address: u16) -> u8 {
+        let value = bus.read(address);
+        self.last = BusCycle { address, value, write: false };
+        value
+    }
+
+    fn write(&mut self, bus: &mut dyn Bus, address: u16, value: u8) {
+        bus.write(address, value);
+        self.last = BusCycle { address, value, write: true };
+    }
+
+    /// Fetch the byte at the program counter and move past it
+    fn fetch(&mut self, bus: &mut dyn Bus) -> u8 {
+        let value = self.dummy_fetch(bus);
+        let processor = bus.registers();
+        processor.pc = processor.pc.wrapping_add(1);
+        value
+    }
+
+    /// Read the byte at the program counter without moving past it
+    fn dummy_fetch(&mut self, bus: &mut dyn Bus) -> u8 {
+        let address = bus.registers().pc;
+        let value = bus.fetch(address);
+        self.last = BusCycle { address, value, write: false };
+        value
+    }
+
+    fn push(&mut self, bus: &mut dyn Bus, value: u8) {
+        let sp = bus.registers().sp;
+        self.write(bus, 0x100 | sp as u16, value);
+        bus.registers().sp = sp.wrapping_sub(1);
+    }
+
+    /// Read the stack at the stack pointer, then move the pointer up
+    fn pop(&mut self, bus: &mut dyn Bus) -> u8 {
+        let sp = bus.registers().sp;
+        let value = self.read(bus, 0x100 | sp as u16);
+        bus.registers().sp = sp.wrapping_add(1);
+        value
+    }
+
+    /// Read the stack at the stack pointer
+    fn peek(&mut self, bus: &mut dyn Bus) -> u8 {
+        let sp = bus.registers().sp;
+        self.read(bus, 0x100 | sp as u16)
+    }
+
+    fn irq(&mut self, bus: &mut dyn Bus, step: u8) {
+        match step {
+            0 | 1 => {
+                self.dummy_fetch(bus);
+            },
+            2 => {
+                let pc = bus.registers().pc;
+                self.push(bus, (pc >> 8) as u8);
+            },
+            3 => {
+                let pc = bus.registers().pc;
+                self.push(bus, pc as u8);
+            },
+            4 => {
+                // Hardware interrupts push the flags with B clear
+                let flags = bus.registers().flags;
+                self.push(bus, flags | FLAG_UNUSED);
+                bus.registers().flags |= FLAG_I;
+            },
+            5 => self.addr = self.read(bus, IRQ_VECTOR) as u16,
+            _ => {
+                self.addr |= (self.read(bus, IRQ_VECTOR + 1) as u16) << 8;
+                bus.registers().pc = self.addr;
+                self.step = 0;
+                self.completed = Some(Completed::Interrupt { pc: self.start_pc, sp: self.start_sp, handler: self.addr });
+            },
+        }
+    }
+
+    fn brk(&mut self, bus: &mut dyn Bus, step: u8) {
+        match step {
+            // The byte after BRK is skipped
+            1 => {
+                self.fetch(bus);
+            },
+            2 => {
+                let pc = bus.registers().pc;
+                self.push(bus, (pc >> 8) as u8);
+            },
+            3 => {
+                let pc = bus.registers().pc;
+                self.push(bus, pc as u8);
+            },
+            4 => {
+                let flags = bus.registers().flags;
+                self.push(bus, flags | FLAG_B | FLAG_UNUSED);
+                bus.registers().flags |= FLAG_I;
+            },
+            5 => self.addr = self.read(bus, IRQ_VECTOR) as u16,
+            _ => {
+                self.addr |= (self.read(bus, IRQ_VECTOR + 1) as u16) << 8;
+                bus.registers().pc = self.addr;
+                self.finish();
+            },
+        }
+    }
+
+    fn jsr(&mut self, bus: &mut dyn Bus, step: u8) {
+        match step {
+            1 => self.addr = self.fetch(bus) as u16,
+            2 => {
+                self.peek(bus);
+            },
+            3 => {
+                // The return address pushed is that of the high byte
+                let pc = bus.registers().pc;
+                self.push(bus, (pc >> 8) as u8);
+            },
+            4 => {
+                let pc = bus.registers().pc;
+                self.push(bus, pc as u8);
+            },
+            _ => {
+                self.addr |= (self.dummy_fetch(bus) as u16) << 8;
+                bus.registers().pc = self.addr;
+                self.finish();
+            },
+        }
+    }
+
+    fn rti(&mut self, bus: &mut dyn Bus, step: u8) {
+        match step {
+            1 => {
+                self.dummy_fetch(bus);
+            },
+            2 => {
+                self.pop(bus);
+            },
+            3 => {
+                // B and bit 5 exist only on the stack
+                bus.registers().flags = self.pop(bus) & !(FLAG_B | FLAG_UNUSED);
+            },
+            4 => self.addr = self.pop(bus) as u16,
+            _ => {
+                self.addr |= (self.peek(bus) as u16) << 8;
+                bus.registers().pc = self.addr;
+                self.finish();
+            },
+        }
+    }
+
+    fn rts(&mut self, bus: &mut dyn Bus, step: u8) {
+        match step {
+            1 => {
+                self.dummy_fetch(bus);
+            },
+            2 => {
+                self.pop(bus);
+            },
+            3 => self.addr = self.pop(bus) as u16,
+            4 => {
+                self.addr |= (self.peek(bus) as u16) << 8;
+                bus.registers().pc = self.addr;
+            },
+            _ => {
+                // Reads the last byte of the JSR before moving past it
+                self.fetch(bus);
+                self.finish();
+            },
+        }
+    }
+
+    fn push_register(&mut self, bus: &mut dyn Bus, step: u8) {
+        if step == 1 {
+            self.dummy_fetch(bus);
+            return;
+        }
+        let processor = bus.registers();
+        let value = if self.name == "PHA" { processor.acc } else { processor.flags | FLAG_B | FLAG_UNUSED };
+        self.push(bus, value);
+        self.finish();
+    }
+
+    fn pull_register(&mut self, bus: &mut dyn Bus, step: u8) {
+        match step {
+            1 => {
+                self.dummy_fetch(bus);
+            },
+            2 => {
+                self.pop(bus);
+            },
+            _ => {
+                let value = self.peek(bus);
+                let processor = bus.registers();
+                if self.name == "PLA" {
+                    processor.acc = value;
+                    processor.flags = Computer::set_flags(processor.flags, value);
+                } else {
+                    processor.flags = value & !(FLAG_B | FLAG_UNUSED);
+                }
+                self.finish();
+            },
+        }
+    }
+
+    fn jmp(&mut self, bus: &mut dyn Bus, step: u8) {
+        match (self.mode, step) {
+            (_, 1) => self.addr = self.fetch(bus) as u16,
+            (ADRESSING_MODE::ABSOLUTE, _) => {
+                self.addr |= (self.fetch(bus) as u16) << 8;
+                bus.registers().pc = self.addr;
+                self.finish();
+            },
+            (_, 2) => self.addr |= (self.fetch(bus) as u16) << 8,
+            (_, 3) => self.data = self.read(bus, self.addr),
+            _ => {
+                // The pointer's high byte comes from the same page, even
+                // when its low byte is the last one of the page
+                let high = (self.addr & 0xff00) | (self.addr as u8).wrapping_add(1) as u16;
+                let target = self.data as u16 | (self.read(bus, high) as u16) << 8;
+                bus.registers().pc = target;
+                self.finish();
+            },
+        }
+    }
+
+    fn branch(&mut self, bus: &mut dyn Bus, step: u8) {
+        match step {
+            1 => {
+                let offset = self.fetch(bus);
+                let processor = bus.registers();
+                // Bits 6 and 7 pick N, V, C or Z, bit 5 the value that branches
+                let flag = [FLAG_N, FLAG_O, FLAG_C, FLAG_Z][(self.opcode >> 6) as usize];
+                if (processor.flags & flag != 0) != (self.opcode & 0x20 != 0) {
+                    self.finish();
+                    return;
+                }
+                self.addr = processor.pc.wrapping_add(offset as i8 as u16);
+            },
+            2 => {
+                self.dummy_fetch(bus);
+                let processor = bus.registers();
+                let unfixed = (processor.pc & 0xff00) | (self.addr & 0xff);
+                processor.pc = unfixed;
+                if unfixed == self.addr {
+                    self.finish();
+                }
+            },
+            _ => {
+                self.dummy_fetch(bus);
+                bus.registers().pc = self.addr;
+                self.finish();
+            },
+        }
+    }
+
+    fn access(&self) -> Access {
+        match self.name {
+            "STA" | "STX" | "STY" => Access::Write,
+            "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" => Access::Modify,
+            _ => Access::Read,
+        }
+    }
+
+    fn index(&self, processor: &Processor) -> u8 {
+        match self.mode {
+            ADRESSING_MODE::ZERO_PAGE_Y | ADRESSING_MODE::ABSOLUTE_Y | ADRESSING_MODE::INDIRECT_Y => processor.ry,
+            _ => processor.rx,
+        }
+    }
+
+    /// Add the index to the 16 bit base address in `addr`
+    fn add_index(&mut self, bus: &mut dyn Bus) {
+        let base = self.addr;
+        self.addr = base.wrapping_add(self.index(bus.registers()) as u16);
+        self.crossed = (base ^ self.addr) & 0xff00 != 0;
+    }
+
+    /// The read made before the high byte of an indexed address is fixed.
+    /// Reads that did not cross a page are done with it.
+    fn read_unfixed(&mut self, bus: &mut dyn Bus) {
+        let unfixed = if self.crossed { self.addr.wrapping_sub(0x100) } else { self.addr };
+        let value = self.read(bus, unfixed);
+        if self.access() == Access::Read && !self.crossed {
+            self.read_done(bus, value);
+        }
+    }
+
+    /// Work out the effective address, then access it. `step` counts the
+    /// cycles after the opcode fetch.
+    fn addressed(&mut self, bus: &mut dyn Bus, step: u8) {
+        match (self.mode, step) {
+            (ADRESSING_MODE::IMMEDIATE, _) => {
+                let value = self.fetch(bus);
+                self.read_done(bus, value);
+            },
+            (ADRESSING_MODE::ZERO_PAGE, 1) => self.addr = self.fetch(bus) as u16,
+            (ADRESSING_MODE::ZERO_PAGE, _) => self.operand(bus, step - 2),
+
+            (ADRESSING_MODE::ZERO_PAGE_X, 1) | (ADRESSING_MODE::ZERO_PAGE_Y, 1) => self.addr = self.fetch(bus) as u16,
+            (ADRESSING_MODE::ZERO_PAGE_X, 2) | (ADRESSING_MODE::ZERO_PAGE_Y, 2) => {
+                self.read(bus, self.addr);
+                let index = self.index(bus.registers());
+                self.addr = (self.addr as u8).wrapping_add(index) as u16;
+            },
+            (ADRESSING_MODE::ZERO_PAGE_X, _) | (ADRESSING_MODE::ZERO_PAGE_Y, _) => self.operand(bus, step - 3),
+
+            (ADRESSING_MODE::ABSOLUTE, 1) => self.addr = self.fetch(bus) as u16,
+            (ADRESSING_MODE::ABSOLUTE, 2) => self.addr |= (self.fetch(bus) as u16) << 8,
+            (ADRESSING_MODE::ABSOLUTE, _) => self.operand(bus, step - 3),
+
+            (ADRESSING_MODE::ABSOLUTE_X, 1) | (ADRESSING_MODE::ABSOLUTE_Y, 1) => self.addr = self.fetch(bus) as u16,
+            (ADRESSING_MODE::ABSOLUTE_X, 2) | (ADRESSING_MODE::ABSOLUTE_Y, 2) => {
+                self.addr |= (self.fetch(bus) as u16) << 8;
+                self.add_index(bus);
+            },
+            (ADRESSING_MODE::ABSOLUTE_X, 3) | (ADRESSING_MODE::ABSOLUTE_Y, 3) => self.read_unfixed(bus),
+            (ADRESSING_MODE::ABSOLUTE_X, _) | (ADRESSING_MODE::ABSOLUTE_Y, _) => self.operand(bus, step - 4),
+
+            (ADRESSING_MODE::INDIRECT_X, 1) => self.pointer = self.fetch(bus),
+            (ADRESSING_MODE::INDIRECT_X, 2) => {
+                self.read(bus, self.pointer as u16);
+                self.pointer = self.pointer.wrapping_add(bus.registers().rx);
+            },
+            (ADRESSING_MODE::INDIRECT_X, 3) => self.addr = self.read(bus, self.pointer as u16) as u16,
+            (ADRESSING_MODE::INDIRECT_X, 4) => {
+                self.addr |= (self.read(bus, self.pointer.wrapping_add(1) as u16) as u16) << 8;
+            },
+            (ADRESSING_MODE::INDIRECT_X, _) => self.operand(bus, step - 5),
+
+            (ADRESSING_MODE::INDIRECT_Y, 1) => self.pointer = self.fetch(bus),
+            (ADRESSING_MODE::INDIRECT_Y, 2) => self.addr = self.read(bus, self.pointer as u16) as u16,
+            (ADRESSING_MODE::INDIRECT_Y, 3) => {
+                self.addr |= (self.read(bus, self.pointer.wrapping_add(1) as u16) as u16) << 8;
+                self.add_index(bus);
+            },
+            (ADRESSING_MODE::INDIRECT_Y, 4) => self.read_unfixed(bus),
+            (ADRESSING_MODE::INDIRECT_Y, _) => self.operand(bus, step - 5),
+
+            _ => {
+                self.dummy_fetch(bus);
+                self.finish();
+            },
+        }
+    }
+
+    /// Access the effective address, `step` cycles into the access
+    fn operand(&mut self, bus: &mut dyn Bus, step: u8) {
+        match (self.access(), step) {
+            (Access::Read, _) => {
+                let value = self.read(bus, self.addr);
+                self.read_done(bus, value);
+            },
+            (Access::Write, _) => {
+                let processor = bus.registers();
+                let value = match self.name {
+                    "STX" => processor.rx,
+                    "STY" => processor.ry,
+                    _ => processor.acc,
+                };
+                self.write(bus, self.addr, value);
+                self.finish();
+            },
+            (Access::Modify, 0) => self.data = self.read(bus, self.addr),
+            // The unmodified value is written back while the ALU works
+            (Access::Modify, 1) => self.write(bus, self.addr, self.data),
+            (Access::Modify, _) => {
+                let value = self.modify(bus.registers(), self.data);
+                self.write(bus, self.addr, value);
+                self.finish();
+            },
+        }
+    }
+
+    /// Finish an instruction with the `value` it read. The 65C02 takes
+    /// another cycle, reading the next opcode, to fix up a decimal result.
+    fn read_done(&mut self, bus: &mut dyn Bus, value: u8) {
+        let processor = bus.registers();
+        self.operate(processor, value);
+        let arithmetic = self.name == "ADC" || self.name == "SBC";
+        if self.variant == Variant::Cmos && arithmetic && processor.flags & FLAG_D != 0 {
+            self.fixup = true;
+        } else {
+            self.finish();
+        }
+    }
+
+    /// Carry out an instruction that reads `value`
+    fn operate(&self, processor: &mut Processor, value: u8) {
+        let flags = processor.flags;
+        match self.name {
+            "LDA" => {
+                processor.acc = value;
+                processor.flags = Computer::set_flags(flags, value);
+            },
+            "LDX" => {
+                processor.rx = value;
+                processor.flags = Computer::set_flags(flags, value);
+            },
+            "LDY" => {
+                processor.ry = value;
+                processor.flags = Computer::set_flags(flags, value);
+            },
+            "AND" => {
+                processor.acc &= value;
+                processor.flags = Computer::set_flags(flags, processor.acc);
+            },
+            "ORA" => {
+                processor.acc |= value;
+                processor.flags = Computer::set_flags(flags, processor.acc);
+            },
+            "EOR" => {
+                processor.acc ^= value;
+                processor.flags = Computer::set_flags(flags, processor.acc);
+            },
+            "ADC" => {
+                let (acc, flags) = alu::adc(self.variant, flags, processor.acc, value);
+                processor.acc = acc;
+                processor.flags = flags;
+            },
+            "SBC" => {
+                let (acc, flags) = alu::sbc(self.variant, flags, processor.acc, value);
+                processor.acc = acc;
+                processor.flags = flags;
+            },
+            "CMP" => processor.flags = alu::compare(flags, processor.acc, value),
+            "CPX" => processor.flags = alu::compare(flags, processor.rx, value),
+            "CPY" => processor.flags = alu::compare(flags, processor.ry, value),
+            "BIT" => processor.flags = alu::bit(flags, processor.acc, value),
+            _ => {},
+        }
+    }
+
+    /// The value a read-modify-write instruction writes back
+    fn modify(&self, processor: &mut Processor, value: u8) -> u8 {
+        let flags = processor.flags;
+        let (result, flags) = match self.name {
+            "ASL" => alu::asl(flags, value),
+            "LSR" => alu::lsr(flags, value),
+            "ROL" => alu::rol(flags, value),
+            "ROR" => alu::ror(flags, value),
+            "INC" => (value.wrapping_add(1), Computer::set_flags(flags, value.wrapping_add(1))),
+            _ => (value.wrapping_sub(1), Computer::set_flags(flags, value.wrapping_sub(1))),
+        };
+        processor.flags = flags;
+        result
+    }
+
+    /// Carry out a one byte instruction
+    fn implied(&self, processor: &mut Processor) {
+        let flags = processor.flags;
+        match self.name {
+            "CLC" => processor.flags &= !FLAG_C,
+            "SEC" => processor.flags |= FLAG_C,
+            "CLI" => processor.flags &= !FLAG_I,
+            "SEI" => processor.flags |= FLAG_I,
+            "CLV" => processor.flags &= !FLAG_O,
+            "CLD" => processor.flags &= !FLAG_D,
+            "SED" => processor.flags |= FLAG_D,
+            "TXS" => processor.sp = processor.rx,
+            "ASL" | "LSR" | "ROL" | "ROR" => {
+                let acc = processor.acc;
+                processor.acc = self.modify(processor, acc);
+            },
+            "NOP" | "" => {},
+            name => {
+                let value = match name {
+                    "TAX" => { processor.rx = processor.acc; processor.rx },
+                    "TAY" => { processor.ry = processor.acc; processor.ry },
+                    "TXA" => { processor.acc = processor.rx; processor.acc },
+                    "TYA" => { processor.acc = processor.ry; processor.acc },
+                    "TSX" => { processor.rx = processor.sp; processor.rx },
+                    "INX" => { processor.rx = processor.rx.wrapping_add(1); processor.rx },
+                    "INY" => { processor.ry = processor.ry.wrapping_add(1); processor.ry },
+                    "DEX" => { processor.rx = processor.rx.wrapping_sub(1); processor.rx },
+                    _ => { processor.ry = processor.ry.wrapping_sub(1); processor.ry },
+                };
+                processor.flags = Computer::set_flags(flags, value);
+            },
+        }
+    }
+}
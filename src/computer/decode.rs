@@ -149,3 +149,114 @@ pub fn get_opcode_name<'a>(opcode: u8) -> &'a str {
 
     ""
 }
+
+/// Instructions that take no operand
+pub const IMPLIED: [&str; 25] = [
+    "BRK", "RTI", "RTS", "PHP", "PLP", "PHA", "PLA", "DEY", "TAY", "INY", "INX",
+    "CLC", "SEC", "CLI", "SEI", "TYA", "CLV", "CLD", "SED",
+    "TXA", "TXS", "TAX", "TSX", "DEX", "NOP",
+];
+
+/// Opcodes the decode tables give a name that no NMOS 6502 implements,
+/// like `STA #` or `JMP zp`, which must never be picked
+const UNDOCUMENTED: [u8; 37] = [
+    0x02, 0x12, 0x1a, 0x22, 0x32, 0x34, 0x3a, 0x3c, 0x42, 0x44, 0x52, 0x54, 0x5a,
+    0x5c, 0x62, 0x64, 0x72, 0x74, 0x7a, 0x7c, 0x80, 0x82, 0x89, 0x92, 0x9c, 0x9e,
+    0xb2, 0xc2, 0xd2, 0xd4, 0xda, 0xdc, 0xe2, 0xf2, 0xf4, 0xfa, 0xfc,
+];
+
+/// Whether `opcode` is one of the 151 opcodes every NMOS 6502 documents
+pub fn is_documented(opcode: u8) -> bool {
+    !get_opcode_name(opcode).is_empty() && !UNDOCUMENTED.contains(&opcode)
+}
+
+/// Cycles each opcode takes on the NMOS 6502, before the extra cycles of
+/// taken branches and page crossings. Undocumented opcodes count as two
+/// cycle NOPs, as the cycle stepped core runs them.
+const CYCLES: [u8; 256] = [
+    7, 6, 2, 2, 2, 3, 5, 2, 3, 2, 2, 2, 2, 4, 6, 2,
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2,
+    6, 6, 2, 2, 3, 3, 5, 2, 4, 2, 2, 2, 4, 4, 6, 2,
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2,
+    6, 6, 2, 2, 2, 3, 5, 2, 3, 2, 2, 2, 3, 4, 6, 2,
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2,
+    6, 6, 2, 2, 2, 3, 5, 2, 4, 2, 2, 2, 5, 4, 6, 2,
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2,
+    2, 6, 2, 2, 3, 3, 3, 2, 2, 2, 2, 2, 4, 4, 4, 2,
+    2, 6, 2, 2, 4, 4, 4, 2, 2, 5, 2, 2, 2, 5, 2, 2,
+    2, 6, 2, 2, 3, 3, 3, 2, 2, 2, 2, 2, 4, 4, 4, 2,
+    2, 5, 2, 2, 4, 4, 4, 2, 2, 4, 2, 2, 4, 4, 4, 2,
+    2, 6, 2, 2, 3, 3, 5, 2, 2, 2, 2, 2, 4, 4, 6, 2,
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2,
+    2, 6, 2, 2, 3, 3, 5, 2, 2, 2, 2, 2, 4, 4, 6, 2,
+    2, 5, 2, 2, 2, 4, 6, 2, 2, 4, 2, 2, 2, 4, 7, 2,
+];
+
+/// Instructions that take a cycle more when indexing crosses a page
+pub const PAGE_PENALTY: [&str; 9] = ["ADC", "AND", "CMP", "EOR", "LDA", "LDX", "LDY", "ORA", "SBC"];
+
+/// Cycles `opcode` takes, not counting taken branches and page crossings
+pub fn cycles(opcode: u8) -> u64 {
+    CYCLES[opcode as usize] as u64
+}
+
+/// Number of bytes taken by the instruction starting with `opcode`,
+/// operands included. Unknown opcodes count as one byte.
+pub fn instruction_length(opcode: u8) -> u16 {
+    let name = get_opcode_name(opcode);
+    if name.is_empty() || IMPLIED.contains(&name) {
+        return 1;
+    }
+    if opcode & 0x1f == 0x10 {
+        return 2;
+    }
+    if opcode == 0x20 {
+        return 3;
+    }
+    match get_adressing_mode(opcode) {
+        ADRESSING_MODE::IMMEDIATE | ADRESSING_MODE::ZERO_PAGE | ADRESSING_MODE::ZERO_PAGE_X
+        | ADRESSING_MODE::ZERO_PAGE_Y | ADRESSING_MODE::INDIRECT_X | ADRESSING_MODE::INDIRECT_Y => 2,
+        ADRESSING_MODE::ABSOLUTE | ADRESSING_MODE::ABSOLUTE_X | ADRESSING_MODE::ABSOLUTE_Y
+        | ADRESSING_MODE::INDIRECT => 3,
+        ADRESSING_MODE::ACCUMULATOR | ADRESSING_MODE::NONE => 1,
+    }
+}
+
+/// Disassemble the instruction at `addr`, returning its text and length.
+/// `label` is asked for a name for every address used as an operand.
+pub fn disassemble(memory: &[u8], addr: u16, label: &dyn Fn(u16) -> Option<String>) -> (String, u16) {
+    let byte = |offset: u16| memory[addr.wrapping_add(offset) as usize];
+    let word = || byte(1) as u16 | ((byte(2) as u16) << 8);
+    let name_or = |target: u16, text: String| label(target).unwrap_or(text);
+
+    let opcode = byte(0);
+    let name = get_opcode_name(opcode);
+    if name.is_empty() {
+        return (format!(".byte ${:02x}", opcode), 1);
+    }
+    if IMPLIED.contains(&name) {
+        return (name.to_string(), 1);
+    }
+    if opcode & 0x1f == 0x10 {
+        let target = addr.wrapping_add(2).wrapping_add(byte(1) as i8 as u16);
+        return (format!("{} {}", name, name_or(target, format!("${:04x}", target))), 2);
+    }
+    if opcode == 0x20 {
+        return (format!("{} {}", name, name_or(word(), format!("${:04x}", word()))), 3);
+    }
+
+    match get_adressing_mode(opcode) {
+        ADRESSING_MODE::IMMEDIATE => (format!("{} #${:02x}", name, byte(1)), 2),
+        ADRESSING_MODE::ZERO_PAGE => (format!("{} {}", name, name_or(byte(1) as u16, format!("${:02x}", byte(1)))), 2),
+        ADRESSING_MODE::ZERO_PAGE_X => (format!("{} {},X", name, name_or(byte(1) as u16, format!("${:02x}", byte(1)))), 2),
+        ADRESSING_MODE::ZERO_PAGE_Y => (format!("{} {},Y", name, name_or(byte(1) as u16, format!("${:02x}", byte(1)))), 2),
+        ADRESSING_MODE::INDIRECT_X => (format!("{} ({},X)", name, name_or(byte(1) as u16, format!("${:02x}", byte(1)))), 2),
+        ADRESSING_MODE::INDIRECT_Y => (format!("{} ({}),Y", name, name_or(byte(1) as u16, format!("${:02x}", byte(1)))), 2),
+        ADRESSING_MODE::ABSOLUTE => (format!("{} {}", name, name_or(word(), format!("${:04x}", word()))), 3),
+        ADRESSING_MODE::ABSOLUTE_X => (format!("{} {},X", name, name_or(word(), format!("${:04x}", word()))), 3),
+        ADRESSING_MODE::ABSOLUTE_Y => (format!("{} {},Y", name, name_or(word(), format!("${:04x}", word()))), 3),
+        ADRESSING_MODE::INDIRECT => (format!("{} ({})", name, name_or(word(), format!("${:04x}", word()))), 3),
+        ADRESSING_MODE::ACCUMULATOR => (format!("{} A", name), 1),
+        ADRESSING_MODE::NONE => (name.to_string(), 1),
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+
+use crate::computer::banking::BankInfo;
+use crate::computer::memory::Memory;
+
+/// A peripheral mapped onto the processor bus.
+///
+/// The computer asks every attached device whether it claims an address
+/// before falling back to plain memory, so a device only sees the accesses
+/// that fall inside its own register window.
+pub trait Device: Send + fmt::Debug {
+    /// Short human readable description, shown in the debug info panel
+    fn name(&self) -> String;
+
+    /// Whether this device responds to `addr`
+    fn handles(&self, addr: u16) -> bool;
+
+    /// Read a register. Takes `&mut self` because reads can have side
+    /// effects, like clearing a status flag.
+    fn read(&mut self, addr: u16) -> u8;
+
+    /// Write a register
+    fn write(&mut self, addr: u16, value: u8);
+
+    /// Advance the device by `cycles` processor clock cycles
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Whether the device is currently pulling the IRQ line low
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Copy whatever the device pages into main memory, if it changed.
+    /// Called when the device is attached and after every write to it.
+    fn map(&mut self, _memory: &mut Memory) {}
+
+    /// Description of the banked window, for bank switching devices
+    fn bank_info(&self) -> Option<BankInfo> {
+        None
+    }
+
+    /// Contents of `bank`, mapped or not. `memory` holds the mapped one.
+    fn read_bank(&self, _bank: usize, _memory: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
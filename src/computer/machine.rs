@@ -0,0 +1,222 @@
+//! Computers running on threads of their own, any number to a process.
+//!
+//! A `Machine` owns the thread a `Computer` runs on and the front end's
+//! ends of the channels to it, and speaks the `protocol` for the caller.
+//! `Machines` keeps many of them by id, for fuzzers and tests that run
+//! machines side by side. Dropping either shuts the computers down and
+//! joins their threads.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::computer::Computer;
+use crate::computer::protocol::{Command, Event, Reply, Request, State};
+
+pub struct Machine {
+    requests: mpsc::Sender<Request>,
+    events: mpsc::Receiver<Event>,
+    /// Events that arrived while waiting for a reply, oldest first
+    pending: VecDeque<Event>,
+    /// Id of the last request sent
+    next_id: u64,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Machine {
+    /// Start a paused computer with `data` as its memory on a new thread.
+    /// `setup` runs on that thread first, to attach devices or change
+    /// settings.
+    pub fn spawn<F>(data: Vec<u8>, setup: F) -> Machine
+        where F: FnOnce(&mut Computer) + Send + 'static
+    {
+        let (event_tx, events) = mpsc::channel::<Event>();
+        let (requests, request_rx) = mpsc::channel::<Request>();
+        let thread = thread::spawn(move || {
+            let mut computer = Computer::new(event_tx, request_rx, data);
+            setup(&mut computer);
+            while computer.step() {}
+        });
+        Machine { requests, events, pending: VecDeque::new(), next_id: 0, thread: Some(thread) }
+    }
+
+    /// Send a command without waiting, returning the id its reply will carry
+    pub fn send(&mut self, command: Command) -> Result<u64, String> {
+        self.next_id += 1;
+        self.requests.send(Request { id: self.next_id, command })
+            .map_err(|_| "the machine has stopped".to_string())?;
+        Ok(self.next_id)
+    }
+
+    /// Wait for the reply to the request with this id. `Reply::Error`
+    /// comes back as an error, and other events that arrive meanwhile are
+    /// kept for `try_event`.
+    pub fn wait(&mut self, id: u64) -> Result<Reply, String> {
+        let early = self.pending.iter().position(|event| match event {
+            Event::Reply(reply_id, _) => *reply_id == id,
+            _ => false,
+        });
+        let reply = match early.and_then(|i| self.pending.remove(i)) {
+            Some(Event::Reply(_, reply)) => reply,
+            _ => loop {
+                match self.events.recv() {
+                    Ok(Event::Reply(reply_id, reply)) if reply_id == id => break reply,
+                    Ok(event) => self.pending.push_back(event),
+                    Err(_) => return Err("the machine has stopped".to_string()),
+                }
+            },
+        };
+        match reply {
+            Reply::Error(e) => Err(e),
+            reply => Ok(reply),
+        }
+    }
+
+    /// Send a command and wait for its reply
+    pub fn request(&mut self, command: Command) -> Result<Reply, String> {
+        let id = self.send(command)?;
+        self.wait(id)
+    }
+
+    /// The oldest event not taken yet, without waiting
+    pub fn try_event(&mut self) -> Option<Event> {
+        self.pending.pop_front().or_else(|| self.events.try_recv().ok())
+    }
+
+    pub fn run(&mut self) -> Result<(), String> {
+        self.request(Command::Run).map(|_| ())
+    }
+
+    pub fn pause(&mut self) -> Result<(), String> {
+        self.request(Command::Pause).map(|_| ())
+    }
+
+    /// Execute `count` instructions and wait until they have run or a
+    /// breakpoint stopped them
+    pub fn step(&mut self, count: u32) -> Result<(), String> {
+        self.request(Command::Step(count)).map(|_| ())
+    }
+
+    pub fn state(&mut self) -> Result<State, String> {
+        match self.request(Command::GetState)? {
+            Reply::State(state) => Ok(state),
+            reply => Err(format!("expected the state, got {:?}", reply)),
+        }
+    }
+
+    /// Stop the computer and wait for its thread to end. Does nothing the
+    /// second time.
+    pub fn shutdown(&mut self) -> Result<(), String> {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+        // A computer that has already stopped has dropped its end
+        if let Ok(id) = self.send(Command::Shutdown) {
+            let _ = self.wait(id);
+        }
+        thread.join().map_err(|_| "the machine's thread panicked".to_string())
+    }
+}
+
+impl Drop for Machine {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+/// Any number of machines, by the id `create` gave them
+#[derive(Default)]
+pub struct Machines {
+    machines: BTreeMap<usize, Machine>,
+    next_id: usize,
+}
+
+impl Machines {
+    pub fn new() -> Machines {
+        Machines::default()
+    }
+
+    /// Start a machine as `Machine::spawn` does, returning its id
+    pub fn create<F>(&mut self, data: Vec<u8>, setup: F) -> usize
+        where F: FnOnce(&mut Computer) + Send + 'static
+    {
+        self.next_id += 1;
+        self.machines.insert(self.next_id, Machine::spawn(data, setup));
+        self.next_id
+    }
+
+    pub fn get(&mut self, id: usize) -> Option<&mut Machine> {
+        self.machines.get_mut(&id)
+    }
+
+    /// Ids of the machines, oldest first
+    pub fn ids(&self) -> Vec<usize> {
+        self.machines.keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    /// Shut a machine down and forget it
+    pub fn destroy(&mut self, id: usize) -> Result<(), String> {
+        match self.machines.remove(&id) {
+            Some(mut machine) => machine.shutdown(),
+            None => Err(format!("no machine {}", id)),
+        }
+    }
+
+    /// Send `command` to every machine before waiting for any reply, so
+    /// they carry it out side by side. Returns the replies by machine id.
+    pub fn broadcast(&mut self, command: Command) -> Vec<(usize, Result<Reply, String>)> {
+        let sent: Vec<_> = self.machines.iter_mut()
+            .map(|(&id, machine)| (id, machine.send(command.clone())))
+            .collect();
+        sent.into_iter().map(|(id, sent)| {
+            let machine = self.machines.get_mut(&id).unwrap();
+            (id, sent.and_then(|request| machine.wait(request)))
+        }).collect()
+    }
+
+    pub fn run_all(&mut self) -> Result<(), String> {
+        Self::all_done(self.broadcast(Command::Run))
+    }
+
+    pub fn pause_all(&mut self) -> Result<(), String> {
+        Self::all_done(self.broadcast(Command::Pause))
+    }
+
+    /// Step every machine `count` instructions at once
+    pub fn step_all(&mut self, count: u32) -> Result<(), String> {
+        Self::all_done(self.broadcast(Command::Step(count)))
+    }
+
+    /// Shut every machine down, asking them all before waiting for any
+    pub fn destroy_all(&mut self) -> Result<(), String> {
+        let result = Self::all_done(self.broadcast(Command::Shutdown));
+        for (_, mut machine) in mem::take(&mut self.machines) {
+            machine.shutdown()?;
+        }
+        result
+    }
+
+    /// The first error among `replies`, naming its machine
+    fn all_done(replies: Vec<(usize, Result<Reply, String>)>) -> Result<(), String> {
+        for (id, reply) in replies {
+            reply.map_err(|e| format!("machine {}: {}", id, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Machines {
+    fn drop(&mut self) {
+        let _ = self.destroy_all();
+    }
+}
@@ -0,0 +1,93 @@
+use std::ops::Index;
+
+/// Bytes per page, the granularity of change tracking
+pub const PAGE_SIZE: usize = 256;
+const PAGES: usize = 0x10000 / PAGE_SIZE;
+
+/// The 64 KiB address space. Writes happen in place and stamp their page
+/// with the current generation, so a reader can fetch just the pages that
+/// changed since its last snapshot instead of copying everything.
+#[derive(Debug)]
+pub struct Memory {
+    bytes: Vec<u8>,
+    /// Generation of the last write to each page, 0 if never written
+    pages: Vec<u64>,
+    generation: u64,
+}
+
+/// Pages written since an earlier generation
+#[derive(Clone, Debug)]
+pub struct MemoryUpdate {
+    pub generation: u64,
+    /// Page number and its contents
+    pub pages: Vec<(usize, Vec<u8>)>,
+}
+
+impl MemoryUpdate {
+    /// Copy the changed pages into a mirror of memory
+    pub fn apply(&self, memory: &mut Vec<u8>) {
+        if memory.len() < 0x10000 {
+            memory.resize(0x10000, 0);
+        }
+        for (page, contents) in self.pages.iter() {
+            let start = page * PAGE_SIZE;
+            memory[start..start + PAGE_SIZE].copy_from_slice(contents);
+        }
+    }
+
+    /// Whether any byte in `start..end` may have changed
+    pub fn touches(&self, start: usize, end: usize) -> bool {
+        self.pages.iter().any(|(page, _)| page * PAGE_SIZE < end && (page + 1) * PAGE_SIZE > start)
+    }
+}
+
+impl Memory {
+    /// Create memory holding `contents`, zero filled up to 64 KiB
+    pub fn new(mut contents: Vec<u8>) -> Memory {
+        contents.resize(0x10000, 0);
+        Memory {
+            bytes: contents,
+            pages: vec![0; PAGES],
+            generation: 1,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        self.bytes[addr as usize] = value;
+        self.pages[addr as usize / PAGE_SIZE] = self.generation;
+    }
+
+    /// Copy `data` in starting at `start`
+    pub fn write_range(&mut self, start: usize, data: &[u8]) {
+        let end = start + data.len();
+        self.bytes[start..end].copy_from_slice(data);
+        for page in start / PAGE_SIZE..end.div_ceil(PAGE_SIZE) {
+            self.pages[page] = self.generation;
+        }
+    }
+
+    /// Pages written after generation `since`. Starts a new generation so
+    /// later writes show up in the next update.
+    pub fn changes(&mut self, since: u64) -> MemoryUpdate {
+        let pages = self.pages.iter()
+            .enumerate()
+            .filter(|&(_, &written)| written > since)
+            .map(|(page, _)| (page, self.bytes[page * PAGE_SIZE..(page + 1) * PAGE_SIZE].to_vec()))
+            .collect();
+        let update = MemoryUpdate { generation: self.generation, pages };
+        self.generation += 1;
+        update
+    }
+}
+
+impl Index<usize> for Memory {
+    type Output = u8;
+
+    fn index(&self, addr: usize) -> &u8 {
+        &self.bytes[addr]
+    }
+}
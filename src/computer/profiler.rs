@@ -0,0 +1,315 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+const OPCODE_BRK: u8 = 0x00;
+const OPCODE_JSR: u8 = 0x20;
+const OPCODE_RTI: u8 = 0x40;
+const OPCODE_RTS: u8 = 0x60;
+
+/// Number of addresses listed in the hot spot section of the report
+const HOT_SPOTS: usize = 40;
+
+/// Executions and cycles spent on one instruction
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cost {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+/// Calls made from one call site to one routine, with the inclusive
+/// cost of those calls
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CallCost {
+    pub calls: u64,
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+#[derive(Clone, Debug)]
+struct Frame {
+    routine: u16,
+    caller: u16,
+    call_site: u16,
+    /// Stack pointer before the call, used to match the return
+    sp: u8,
+    cycles: u64,
+    instructions: u64,
+}
+
+/// Counts executions and cycles per address and attributes them to the
+/// routine that was entered through JSR, BRK or an interrupt.
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    root: Option<u16>,
+    cycles: u64,
+    instructions: u64,
+    stack: Vec<Frame>,
+    /// Exclusive cost per (routine, pc)
+    costs: HashMap<(u16, u16), Cost>,
+    /// Inclusive cost per (caller routine, call site, called routine)
+    calls: HashMap<(u16, u16, u16), CallCost>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    fn routine(&self) -> u16 {
+        match self.stack.last() {
+            Some(frame) => frame.routine,
+            None => self.root.unwrap_or(0),
+        }
+    }
+
+    fn enter(&mut self, routine: u16, call_site: u16, sp: u8) {
+        let caller = self.routine();
+        self.stack.push(Frame {
+            routine,
+            caller,
+            call_site,
+            sp,
+            cycles: self.cycles,
+            instructions: self.instructions,
+        });
+    }
+
+    /// Close every frame the stack pointer has returned past. More than one
+    /// frame ends when code drops return addresses instead of returning.
+    fn leave(&mut self, sp: u8) {
+        while let Some(frame) = self.stack.last() {
+            if frame.sp > sp {
+                break;
+            }
+            let frame = self.stack.pop().unwrap();
+            let call = self.calls.entry((frame.caller, frame.call_site, frame.routine)).or_default();
+            call.calls += 1;
+            call.cycles += self.cycles - frame.cycles;
+            call.instructions += self.instructions - frame.instructions;
+        }
+    }
+
+    /// Account for one executed instruction. `sp` is the stack pointer
+    /// before it ran and `next_pc` where execution continues.
+    pub fn record(&mut self, pc: u16, opcode: u8, sp: u8, cycles: u64, next_pc: u16, next_sp: u8) {
+        if self.root.is_none() {
+            self.root = Some(pc);
+        }
+        let routine = self.routine();
+        let cost = self.costs.entry((routine, pc)).or_default();
+        cost.count += 1;
+        cost.cycles += cycles;
+        self.cycles += cycles;
+        self.instructions += 1;
+
+        match opcode {
+            OPCODE_JSR | OPCODE_BRK => self.enter(next_pc, pc, sp),
+            OPCODE_RTS | OPCODE_RTI => self.leave(next_sp),
+            _ => {},
+        }
+    }
+
+    /// Account for a hardware interrupt taken at `pc`
+    pub fn interrupt(&mut self, pc: u16, sp: u8, handler: u16) {
+        self.enter(handler, pc, sp);
+    }
+
+    /// Total cost per address, over all routines
+    fn per_address(&self) -> HashMap<u16, Cost> {
+        let mut totals: HashMap<u16, Cost> = HashMap::new();
+        for (&(_, pc), cost) in self.costs.iter() {
+            let total = totals.entry(pc).or_default();
+            total.count += cost.count;
+            total.cycles += cost.cycles;
+        }
+        totals
+    }
+
+    /// Calls, exclusive and inclusive cycles per routine. Recursive
+    /// routines count their inclusive cycles once per active call.
+    fn per_routine(&self) -> HashMap<u16, (u64, u64, u64)> {
+        let mut routines: HashMap<u16, (u64, u64, u64)> = HashMap::new();
+        for (&(routine, _), cost) in self.costs.iter() {
+            routines.entry(routine).or_default().1 += cost.cycles;
+        }
+        for (&(_, _, routine), call) in self.calls.iter() {
+            let entry = routines.entry(routine).or_default();
+            entry.0 += call.calls;
+            entry.2 += call.cycles;
+        }
+        if let Some(root) = self.root {
+            let entry = routines.entry(root).or_default();
+            entry.0 = entry.0.max(1);
+            entry.2 = entry.2.max(self.cycles);
+        }
+        routines
+    }
+
+    fn percent(&self, cycles: u64) -> f64 {
+        if self.cycles == 0 {
+            return 0.0;
+        }
+        cycles as f64 * 100.0 / self.cycles as f64
+    }
+
+    /// Write a plain text report. `name` gives a display name for an address.
+    pub fn write_report(&self, out: &mut dyn Write, name: &dyn Fn(u16) -> String) -> io::Result<()> {
+        writeln!(out, "{} cycles, {} instructions", self.cycles, self.instructions)?;
+
+        let mut routines: Vec<(u16, (u64, u64, u64))> = self.per_routine().into_iter().collect();
+        routines.sort_by_key(|&(_, (_, _, inclusive))| Reverse(inclusive));
+        writeln!(out, "\nRoutines by inclusive cycles\n")?;
+        writeln!(out, "{:>8} {:>12} {:>7} {:>12} {:>7}  routine", "calls", "exclusive", "%", "inclusive", "%")?;
+        for (routine, (calls, exclusive, inclusive)) in routines {
+            writeln!(
+                out,
+                "{:>8} {:>12} {:>6.2}% {:>12} {:>6.2}%  {} ({:#06x})",
+                calls, exclusive, self.percent(exclusive), inclusive, self.percent(inclusive), name(routine), routine
+            )?;
+        }
+
+        let mut addresses: Vec<(u16, Cost)> = self.per_address().into_iter().collect();
+        addresses.sort_by_key(|&(_, cost)| Reverse(cost.cycles));
+        writeln!(out, "\nHottest addresses\n")?;
+        writeln!(out, "{:>12} {:>12} {:>7}  address", "executions", "cycles", "%")?;
+        for (pc, cost) in addresses.into_iter().take(HOT_SPOTS) {
+            writeln!(out, "{:>12} {:>12} {:>6.2}%  {:#06x} {}", cost.count, cost.cycles, self.percent(cost.cycles), pc, name(pc))?;
+        }
+        Ok(())
+    }
+
+    /// Write the profile in callgrind format, for KCachegrind and friends
+    pub fn write_callgrind(&self, out: &mut dyn Write, name: &dyn Fn(u16) -> String) -> io::Result<()> {
+        writeln!(out, "version: 1")?;
+        writeln!(out, "creator: rust-6502")?;
+        writeln!(out, "positions: instr")?;
+        writeln!(out, "events: Cycles Instructions")?;
+        writeln!(out, "summary: {} {}", self.cycles, self.instructions)?;
+
+        let mut routines: Vec<u16> = self.costs.keys().map(|&(routine, _)| routine)
+            .chain(self.calls.keys().map(|&(_, _, routine)| routine))
+            .collect();
+        routines.sort();
+        routines.dedup();
+
+        for routine in routines {
+            writeln!(out, "\nfn={}", name(routine))?;
+            let mut costs: Vec<(u16, Cost)> = self.costs.iter()
+                .filter(|(&(r, _), _)| r == routine)
+                .map(|(&(_, pc), &cost)| (pc, cost))
+                .collect();
+            costs.sort_by_key(|&(pc, _)| pc);
+            for (pc, cost) in costs {
+                writeln!(out, "{:#06x} {} {}", pc, cost.cycles, cost.count)?;
+            }
+
+            let mut calls: Vec<(u16, u16, CallCost)> = self.calls.iter()
+                .filter(|(&(caller, _, _), _)| caller == routine)
+                .map(|(&(_, site, callee), &call)| (site, callee, call))
+                .collect();
+            calls.sort_by_key(|&(site, callee, _)| (site, callee));
+            for (site, callee, call) in calls {
+                writeln!(out, "cfn={}", name(callee))?;
+                writeln!(out, "calls={} {:#06x}", call.calls, callee)?;
+                writeln!(out, "{:#06x} {} {}", site, call.cycles, call.instructions)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOP: u8 = 0xea;
+    const PLA: u8 = 0x68;
+
+    /// Record `(pc, opcode, cycles, next_pc)` in order, tracking the stack
+    /// pointer from $ff the way the instructions move it
+    fn trace(steps: &[(u16, u8, u64, u16)]) -> Profiler {
+        let mut profiler = Profiler::new();
+        let mut sp: u8 = 0xff;
+        for &(pc, opcode, cycles, next_pc) in steps {
+            let next_sp = match opcode {
+                OPCODE_JSR => sp.wrapping_sub(2),
+                OPCODE_RTS => sp.wrapping_add(2),
+                PLA => sp.wrapping_add(1),
+                _ => sp,
+            };
+            profiler.record(pc, opcode, sp, cycles, next_pc, next_sp);
+            sp = next_sp;
+        }
+        profiler
+    }
+
+    #[test]
+    fn inclusive_and_exclusive_cycles() {
+        let mut profiler = trace(&[
+            (0x0400, OPCODE_JSR, 6, 0x0500),
+            (0x0500, OPCODE_JSR, 6, 0x0600),
+            (0x0600, NOP, 2, 0x0601),
+            (0x0601, OPCODE_RTS, 6, 0x0503),
+        ]);
+        // Only the inner call has returned
+        assert_eq!(profiler.stack.len(), 1);
+        profiler.record(0x0503, OPCODE_RTS, 0xfd, 6, 0x0403, 0xff);
+        profiler.record(0x0403, NOP, 0xff, 2, 0x0404, 0xff);
+        assert!(profiler.stack.is_empty());
+
+        let routines = profiler.per_routine();
+        assert_eq!(routines[&0x0400], (1, 8, 28));
+        assert_eq!(routines[&0x0500], (1, 12, 20));
+        assert_eq!(routines[&0x0600], (1, 8, 8));
+    }
+
+    #[test]
+    fn dropped_return_address() {
+        // The inner routine pulls its return address and returns straight
+        // to the outer routine's caller, ending both calls
+        let profiler = trace(&[
+            (0x0400, OPCODE_JSR, 6, 0x0500),
+            (0x0500, OPCODE_JSR, 6, 0x0600),
+            (0x0600, PLA, 4, 0x0601),
+            (0x0601, PLA, 4, 0x0602),
+            (0x0602, OPCODE_RTS, 6, 0x0403),
+            (0x0403, NOP, 2, 0x0404),
+        ]);
+        assert!(profiler.stack.is_empty());
+        let routines = profiler.per_routine();
+        assert_eq!(routines[&0x0400], (1, 8, 28));
+        assert_eq!(routines[&0x0500], (1, 6, 20));
+        assert_eq!(routines[&0x0600], (1, 14, 14));
+    }
+
+    #[test]
+    fn callgrind() {
+        let profiler = trace(&[
+            (0x0400, OPCODE_JSR, 6, 0x0500),
+            (0x0500, NOP, 2, 0x0501),
+            (0x0501, OPCODE_RTS, 6, 0x0403),
+            (0x0403, NOP, 2, 0x0404),
+        ]);
+        let mut out = vec![];
+        profiler.write_callgrind(&mut out, &|addr| format!("r{:04x}", addr)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+version: 1
+creator: rust-6502
+positions: instr
+events: Cycles Instructions
+summary: 16 4
+
+fn=r0400
+0x0400 6 1
+0x0403 2 1
+cfn=r0500
+calls=1 0x0500
+0x0400 8 2
+
+fn=r0500
+0x0500 2 1
+0x0501 6 1
+");
+    }
+}
@@ -0,0 +1,115 @@
+//! Typed messages between the emulator core and a front end.
+//!
+//! A front end owns the two ends of a pair of channels: it sends
+//! `Request`s to the `Computer` and receives `Event`s back. Every request
+//! carries an id chosen by the front end, and the `Event::Reply` answering
+//! it echoes that id, so several requests can be in flight at once.
+//! Nothing here depends on the terminal UI.
+//!
+//! Commands that change registers or memory are refused unless the
+//! emulator is paused.
+
+use std::ops::Range;
+
+use crate::computer::Processor;
+use crate::computer::banking::BankInfo;
+use crate::computer::profiler::Profiler;
+use crate::computer::coverage::Coverage;
+use crate::computer::memory::MemoryUpdate;
+use crate::computer::callstack::CallFrame;
+
+/// Something for the core to do
+#[derive(Clone, Debug)]
+pub enum Command {
+    /// Run until paused or a breakpoint is reached
+    Run,
+    Pause,
+    /// Execute this many instructions, then pause. Replied to once they
+    /// have run, or when a breakpoint or another command cuts them short.
+    Step(u32),
+    /// Step, running a JSR at PC through to its return. Replied to when
+    /// execution comes back or a breakpoint stops it first.
+    StepOver,
+    /// Run until the innermost call on the call stack returns
+    StepOut,
+    /// Target clock rate in Hz, 0 to run as fast as possible
+    SetSpeed(u64),
+    /// Set, or clear when false, a breakpoint at an address
+    SetBreakpoint(u16, bool),
+    /// Read memory without side effects on devices
+    ReadMemory(Range<usize>),
+    /// Store bytes starting at an address, bypassing devices
+    WriteMemory(u16, Vec<u8>),
+    /// Store one value over a whole range
+    FillMemory(Range<usize>, u8),
+    /// Copy a range to another start address; the two may overlap
+    CopyMemory(Range<usize>, u16),
+    /// Load a register. 8 bit registers refuse larger values.
+    SetRegister(Register, u16),
+    /// Return the processor to its power on state, leaving memory alone
+    Reset,
+    /// Processor state and the memory changed since the previous `GetState`
+    GetState,
+    /// Contents of a bank, by window and bank number
+    GetBank(usize, usize),
+    GetProfile,
+    GetCoverage,
+    /// Stop for good. Replied to before `Computer::step` returns false, so
+    /// the thread running it can end.
+    Shutdown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    SP,
+    PC,
+    Flags,
+}
+
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub id: u64,
+    pub command: Command,
+}
+
+/// Snapshot answering `Command::GetState`
+#[derive(Clone, Debug)]
+pub struct State {
+    pub processor: Processor,
+    /// Pages written since the previous snapshot
+    pub memory: MemoryUpdate,
+    pub paused: bool,
+    /// Target clock rate in Hz, 0 when unthrottled
+    pub target_hz: u64,
+    /// Banked windows, in the order their devices were attached
+    pub banks: Vec<BankInfo>,
+    /// Coverage, when it changed since the previous snapshot
+    pub coverage: Option<Coverage>,
+    /// Active calls, innermost last
+    pub call_stack: Vec<CallFrame>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Reply {
+    /// The command was carried out and has nothing to return
+    Done,
+    State(State),
+    /// Start address and the bytes read
+    Memory(usize, Vec<u8>),
+    /// Window, bank number and contents
+    Bank(usize, usize, Vec<u8>),
+    Profile(Profiler),
+    Coverage(Coverage),
+    Error(String),
+}
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// Answer to the request with this id
+    Reply(u64, Reply),
+    /// Execution paused at a breakpoint at this address
+    Breakpoint(u16),
+}
@@ -0,0 +1,309 @@
+use crate::computer::device::Device;
+
+const RAM_SIZE: usize = 128;
+const IO_SIZE: u16 = 0x20;
+
+const FLAG_TIMER: u8 = 0x80;
+const FLAG_PA7: u8 = 0x40;
+
+/// Timer divide ratios selected by A1-A0 when writing the timer
+const PRESCALERS: [u64; 4] = [1, 8, 64, 1024];
+
+/// 6532 RAM-I/O-Timer.
+///
+/// The full chip maps its 128 bytes of RAM at `base` and its registers at
+/// `base + 0x80`. The timer only variant maps just the registers at `base`.
+///
+/// Registers are decoded from A4-A0 like on the real chip:
+/// A2 = 0: A1-A0 select port A data, port A direction, port B data, port B direction
+/// A2 = 1, write: A4 = 1 loads the timer with the prescaler in A1-A0 and
+///                the timer interrupt enable in A3, A4 = 0 sets the PA7
+///                edge detection (A0 positive edge, A1 interrupt enable)
+/// A2 = 1, read:  A0 = 0 reads the timer (A3 sets the interrupt enable),
+///                A0 = 1 reads the interrupt flags
+#[derive(Debug)]
+pub struct Riot {
+    base: u16,
+    io_base: u16,
+    ram: Option<Vec<u8>>,
+    port_a: u8,
+    ddr_a: u8,
+    port_b: u8,
+    ddr_b: u8,
+    /// Level of the pins not driven by the chip. Nothing is wired to the
+    /// ports yet so they float high.
+    input_a: u8,
+    input_b: u8,
+    timer: u8,
+    prescaler: u64,
+    prescale_count: u64,
+    timer_irq: bool,
+    pa7_irq: bool,
+    pa7_positive_edge: bool,
+    flags: u8,
+}
+
+impl Riot {
+    /// Full RIOT with RAM at `base` and registers at `base + 0x80`
+    pub fn new(base: u16) -> Riot {
+        Self::build(base, base.wrapping_add(RAM_SIZE as u16), Some(vec![0; RAM_SIZE]))
+    }
+
+    /// Registers only, at `base`
+    pub fn timer_only(base: u16) -> Riot {
+        Self::build(base, base, None)
+    }
+
+    fn build(base: u16, io_base: u16, ram: Option<Vec<u8>>) -> Riot {
+        Riot {
+            base,
+            io_base,
+            ram,
+            port_a: 0,
+            ddr_a: 0,
+            port_b: 0,
+            ddr_b: 0,
+            input_a: 0xff,
+            input_b: 0xff,
+            timer: 0,
+            prescaler: 1024,
+            prescale_count: 1024,
+            timer_irq: false,
+            pa7_irq: false,
+            pa7_positive_edge: false,
+            flags: 0,
+        }
+    }
+
+    fn pins_a(&self) -> u8 {
+        (self.port_a & self.ddr_a) | (self.input_a & !self.ddr_a)
+    }
+
+    fn pins_b(&self) -> u8 {
+        (self.port_b & self.ddr_b) | (self.input_b & !self.ddr_b)
+    }
+
+    /// Latch the PA7 interrupt flag if the pin moved in the selected direction
+    fn check_pa7(&mut self, before: u8) {
+        let before = before & 0x80 != 0;
+        let after = self.pins_a() & 0x80 != 0;
+        if before != after && after == self.pa7_positive_edge {
+            self.flags |= FLAG_PA7;
+        }
+    }
+
+    fn in_ram(&self, addr: u16) -> bool {
+        self.ram.is_some() && addr >= self.base && addr - self.base < RAM_SIZE as u16
+    }
+}
+
+impl Device for Riot {
+    fn name(&self) -> String {
+        if self.ram.is_some() {
+            format!("6532 RIOT at {:#06x}", self.base)
+        } else {
+            format!("6532 timer at {:#06x}", self.base)
+        }
+    }
+
+    fn handles(&self, addr: u16) -> bool {
+        self.in_ram(addr) || (addr >= self.io_base && addr - self.io_base < IO_SIZE)
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        if self.in_ram(addr) {
+            return self.ram.as_ref().unwrap()[(addr - self.base) as usize];
+        }
+        let reg = addr - self.io_base;
+        if reg & 0x04 == 0 {
+            return match reg & 3 {
+                0 => self.pins_a(),
+                1 => self.ddr_a,
+                2 => self.pins_b(),
+                _ => self.ddr_b,
+            };
+        }
+        if reg & 0x01 == 0 {
+            self.timer_irq = reg & 0x08 != 0;
+            self.flags &= !FLAG_TIMER;
+            self.timer
+        } else {
+            let flags = self.flags;
+            self.flags &= !FLAG_PA7;
+            flags
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if self.in_ram(addr) {
+            self.ram.as_mut().unwrap()[(addr - self.base) as usize] = value;
+            return;
+        }
+        let reg = addr - self.io_base;
+        if reg & 0x04 == 0 {
+            let before = self.pins_a();
+            match reg & 3 {
+                0 => self.port_a = value,
+                1 => self.ddr_a = value,
+                2 => self.port_b = value,
+                _ => self.ddr_b = value,
+            }
+            self.check_pa7(before);
+        } else if reg & 0x10 != 0 {
+            self.timer = value;
+            self.prescaler = PRESCALERS[(reg & 3) as usize];
+            self.prescale_count = self.prescaler;
+            self.timer_irq = reg & 0x08 != 0;
+            self.flags &= !FLAG_TIMER;
+        } else {
+            self.pa7_positive_edge = reg & 0x01 != 0;
+            self.pa7_irq = reg & 0x02 != 0;
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        let mut remaining = cycles;
+        while remaining > 0 {
+            if self.prescale_count > remaining {
+                self.prescale_count -= remaining;
+                break;
+            }
+            remaining -= self.prescale_count;
+            if self.timer == 0 {
+                // After running out the timer keeps counting down once per
+                // cycle so the program can tell how long ago it expired
+                self.timer = 0xff;
+                self.prescaler = 1;
+                self.flags |= FLAG_TIMER;
+            } else {
+                self.timer -= 1;
+            }
+            self.prescale_count = self.prescaler;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.timer_irq && self.flags & FLAG_TIMER != 0) || (self.pa7_irq && self.flags & FLAG_PA7 != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAM: u16 = 0x1000;
+    const IO: u16 = 0x1080;
+    const PORT_A: u16 = IO;
+    const DDR_A: u16 = IO + 1;
+    const PORT_B: u16 = IO + 2;
+    const DDR_B: u16 = IO + 3;
+    /// Timer writes, plus the prescaler index and `TIMER_IRQ`
+    const WRITE_TIMER: u16 = IO + 0x14;
+    const TIMER_IRQ: u16 = 0x08;
+    const READ_TIMER: u16 = IO + 0x04;
+    const READ_FLAGS: u16 = IO + 0x05;
+    /// PA7 edge detection, plus 1 for a positive edge and 2 for an interrupt
+    const PA7_EDGE: u16 = IO + 0x04;
+
+    #[test]
+    fn every_prescaler_divides_the_clock() {
+        for (index, &prescaler) in PRESCALERS.iter().enumerate() {
+            let mut riot = Riot::new(RAM);
+            riot.write(WRITE_TIMER + index as u16, 3);
+            riot.tick(prescaler - 1);
+            assert_eq!(riot.read(READ_TIMER), 3, "prescaler {}", prescaler);
+            riot.tick(1);
+            assert_eq!(riot.read(READ_TIMER), 2, "prescaler {}", prescaler);
+            riot.tick(3 * prescaler - 1);
+            assert_eq!(riot.read(READ_TIMER), 0, "prescaler {}", prescaler);
+            assert_eq!(riot.read(READ_FLAGS) & FLAG_TIMER, 0, "prescaler {}", prescaler);
+            riot.tick(1);
+            assert_eq!(riot.read(READ_FLAGS) & FLAG_TIMER, FLAG_TIMER, "prescaler {}", prescaler);
+            assert_eq!(riot.read(READ_TIMER), 0xff, "prescaler {}", prescaler);
+        }
+    }
+
+    #[test]
+    fn counts_every_cycle_after_expiry() {
+        let mut riot = Riot::new(RAM);
+        // Expires after 16 cycles with the 8T prescaler
+        riot.write(WRITE_TIMER + 1, 1);
+        riot.tick(16 + 5);
+        assert_eq!(riot.read(READ_TIMER), 0xfa);
+        riot.tick(0x10);
+        assert_eq!(riot.read(READ_TIMER), 0xea);
+        // Loading the timer goes back to the prescaler
+        riot.write(WRITE_TIMER + 1, 1);
+        riot.tick(7);
+        assert_eq!(riot.read(READ_TIMER), 1);
+    }
+
+    #[test]
+    fn timer_interrupt() {
+        let mut riot = Riot::new(RAM);
+        riot.write(WRITE_TIMER + TIMER_IRQ, 0);
+        riot.tick(1);
+        assert!(riot.irq());
+        // Reading the flags leaves the timer flag alone
+        assert_eq!(riot.read(READ_FLAGS), FLAG_TIMER);
+        assert!(riot.irq());
+        // Reading the timer clears it, keeping the interrupt enabled with A3
+        riot.read(READ_TIMER + TIMER_IRQ);
+        assert!(!riot.irq());
+        assert_eq!(riot.read(READ_FLAGS), 0);
+
+        riot.tick(0x100);
+        assert!(riot.irq());
+        riot.read(READ_TIMER);
+        riot.write(WRITE_TIMER, 0);
+        riot.tick(1);
+        // Flagged but not enabled
+        assert!(!riot.irq());
+        assert_eq!(riot.read(READ_FLAGS), FLAG_TIMER);
+    }
+
+    #[test]
+    fn pa7_edge_interrupt() {
+        let mut riot = Riot::new(RAM);
+        riot.write(PA7_EDGE + 2, 0);
+        // Driving the floating pin low is a negative edge
+        riot.write(DDR_A, 0x80);
+        assert!(riot.irq());
+        assert_eq!(riot.read(READ_FLAGS), FLAG_PA7);
+        assert!(!riot.irq());
+        assert_eq!(riot.read(READ_FLAGS), 0);
+
+        riot.write(PORT_A, 0x80);
+        assert!(!riot.irq());
+        riot.write(PA7_EDGE + 3, 0);
+        riot.write(PORT_A, 0);
+        assert!(!riot.irq());
+        riot.write(PORT_A, 0x80);
+        assert!(riot.irq());
+    }
+
+    #[test]
+    fn ports_mix_outputs_with_floating_inputs() {
+        let mut riot = Riot::new(RAM);
+        assert_eq!(riot.read(PORT_A), 0xff);
+        riot.write(PORT_A, 0xa5);
+        riot.write(DDR_A, 0x0f);
+        assert_eq!((riot.read(PORT_A), riot.read(DDR_A)), (0xf5, 0x0f));
+        riot.write(PORT_B, 0x00);
+        riot.write(DDR_B, 0xff);
+        assert_eq!((riot.read(PORT_B), riot.read(DDR_B)), (0x00, 0xff));
+        assert_eq!(riot.read(PORT_A), 0xf5);
+    }
+
+    #[test]
+    fn address_decoding() {
+        let mut riot = Riot::new(RAM);
+        riot.write(RAM + 0x7f, 0x42);
+        assert_eq!(riot.read(RAM + 0x7f), 0x42);
+        assert!(riot.handles(RAM) && riot.handles(IO + 0x1f));
+        assert!(!riot.handles(RAM - 1) && !riot.handles(IO + 0x20));
+
+        let timer = Riot::timer_only(IO);
+        assert!(!timer.handles(RAM) && timer.handles(IO) && timer.handles(IO + 0x1f));
+    }
+}
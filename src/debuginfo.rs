@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Source position of an address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    pub file: usize,
+    pub line: usize,
+}
+
+struct SourceFile {
+    name: String,
+    lines: Vec<String>,
+}
+
+struct Scope {
+    name: String,
+    parent: Option<usize>,
+}
+
+/// Symbols and line information read from an ld65 `--dbgfile`
+pub struct DebugInfo {
+    files: HashMap<usize, SourceFile>,
+    /// Address to (location, line type). Type 0 lines are plain source,
+    /// higher types come from macro expansions and are only used when
+    /// nothing better covers the address.
+    lines: HashMap<u16, (Location, u32)>,
+    labels: BTreeMap<u16, String>,
+    symbols: HashMap<String, u16>,
+    scopes: HashMap<usize, Scope>,
+    /// Address to (innermost scope, scope size)
+    scope_at: HashMap<u16, (usize, usize)>,
+}
+
+type Record = HashMap<String, String>;
+
+/// Split `key=value,key="quoted, value"` into a map, unquoting strings
+fn parse_attributes(text: &str) -> Record {
+    let mut record = Record::new();
+    let mut key = String::new();
+    let mut value = String::new();
+    let mut in_value = false;
+    let mut in_string = false;
+    for c in text.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                record.insert(key.clone(), value.clone());
+                key.clear();
+                value.clear();
+                in_value = false;
+            },
+            '=' if !in_string && !in_value => in_value = true,
+            _ => if in_value { value.push(c) } else { key.push(c) },
+        }
+    }
+    if !key.is_empty() {
+        record.insert(key, value);
+    }
+    record
+}
+
+fn number(record: &Record, key: &str) -> Option<usize> {
+    let value = record.get(key)?;
+    if let Some(digits) = value.strip_prefix("0x") {
+        usize::from_str_radix(digits, 16).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+/// Ids in a `+` separated list like `span=3+4+7`
+fn ids(record: &Record, key: &str) -> Vec<usize> {
+    match record.get(key) {
+        Some(list) => list.split('+').filter_map(|id| id.parse().ok()).collect(),
+        None => vec![],
+    }
+}
+
+impl DebugInfo {
+    /// Read a debug info file. Source files are looked up relative to it.
+    pub fn load(path: &str) -> Result<DebugInfo, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        Self::parse(&text, dir)
+    }
+
+    fn parse(text: &str, dir: &Path) -> Result<DebugInfo, String> {
+        let mut records: HashMap<String, Vec<Record>> = HashMap::new();
+        for line in text.lines() {
+            let mut parts = line.splitn(2, |c: char| c.is_whitespace());
+            let kind = parts.next().unwrap_or("");
+            let attributes = parts.next().unwrap_or("").trim();
+            records.entry(kind.to_string()).or_default().push(parse_attributes(attributes));
+        }
+        if !records.contains_key("version") {
+            return Err("not an ld65 debug info file".to_string());
+        }
+        let empty = vec![];
+        let all = |kind: &str| records.get(kind).unwrap_or(&empty).iter();
+
+        let segments: HashMap<usize, usize> = all("seg")
+            .filter_map(|r| Some((number(r, "id")?, number(r, "start")?)))
+            .collect();
+        // Span id to (first address, size)
+        let spans: HashMap<usize, (usize, usize)> = all("span")
+            .filter_map(|r| {
+                let seg = segments.get(&number(r, "seg")?)?;
+                Some((number(r, "id")?, (seg + number(r, "start")?, number(r, "size")?)))
+            })
+            .collect();
+        let span_addresses = |span: &usize| {
+            spans.get(span).map(|&(start, size)| (start..start + size).filter(|a| *a <= 0xffff).map(|a| a as u16))
+        };
+
+        let mut files = HashMap::new();
+        for r in all("file") {
+            if let (Some(id), Some(name)) = (number(r, "id"), r.get("name")) {
+                let lines = fs::read_to_string(dir.join(name))
+                    .map(|t| t.lines().map(|l| l.to_string()).collect())
+                    .unwrap_or_else(|_| vec![]);
+                files.insert(id, SourceFile { name: name.clone(), lines });
+            }
+        }
+
+        let mut lines: HashMap<u16, (Location, u32)> = HashMap::new();
+        for r in all("line") {
+            let location = match (number(r, "file"), number(r, "line")) {
+                (Some(file), Some(line)) => Location { file, line },
+                _ => continue,
+            };
+            let kind = number(r, "type").unwrap_or(0) as u32;
+            for span in ids(r, "span") {
+                for addr in span_addresses(&span).into_iter().flatten() {
+                    let better = match lines.get(&addr) {
+                        Some(&(_, existing)) => kind < existing,
+                        None => true,
+                    };
+                    if better {
+                        lines.insert(addr, (location, kind));
+                    }
+                }
+            }
+        }
+
+        let mut scopes = HashMap::new();
+        let mut scope_at: HashMap<u16, (usize, usize)> = HashMap::new();
+        for r in all("scope") {
+            let id = match number(r, "id") {
+                Some(id) => id,
+                None => continue,
+            };
+            let size = number(r, "size").unwrap_or(0);
+            for span in ids(r, "span") {
+                for addr in span_addresses(&span).into_iter().flatten() {
+                    let inner = match scope_at.get(&addr) {
+                        Some(&(_, existing)) => size < existing,
+                        None => true,
+                    };
+                    if inner {
+                        scope_at.insert(addr, (id, size));
+                    }
+                }
+            }
+            scopes.insert(id, Scope {
+                name: r.get("name").cloned().unwrap_or_default(),
+                parent: number(r, "parent"),
+            });
+        }
+
+        let mut info = DebugInfo {
+            files,
+            lines,
+            labels: BTreeMap::new(),
+            symbols: HashMap::new(),
+            scopes,
+            scope_at,
+        };
+
+        for r in all("sym") {
+            let (name, value) = match (r.get("name"), number(r, "val")) {
+                (Some(name), Some(value)) if value <= 0xffff => (name.clone(), value as u16),
+                _ => continue,
+            };
+            if r.get("type").map(|t| t.as_str()) == Some("lab") {
+                // Prefer global labels over cheap locals for display
+                let replace = match info.labels.get(&value) {
+                    Some(existing) => existing.starts_with('@') && !name.starts_with('@'),
+                    None => true,
+                };
+                if replace {
+                    info.labels.insert(value, name.clone());
+                }
+            }
+            if let Some(scope) = number(r, "scope").and_then(|s| info.scope_name(s)) {
+                info.symbols.insert(format!("{}::{}", scope, name), value);
+            }
+            info.symbols.entry(name).or_insert(value);
+        }
+
+        Ok(info)
+    }
+
+    /// Fully qualified name of a scope, `None` for the global scope
+    fn scope_name(&self, id: usize) -> Option<String> {
+        let scope = self.scopes.get(&id)?;
+        if scope.name.is_empty() {
+            return None;
+        }
+        match scope.parent.and_then(|p| self.scope_name(p)) {
+            Some(parent) => Some(format!("{}::{}", parent, scope.name)),
+            None => Some(scope.name.clone()),
+        }
+    }
+
+    /// Source file and line that generated the byte at `addr`
+    pub fn location(&self, addr: u16) -> Option<Location> {
+        self.lines.get(&addr).map(|&(location, _)| location)
+    }
+
+    pub fn file_name(&self, file: usize) -> &str {
+        self.files.get(&file).map(|f| f.name.as_str()).unwrap_or("?")
+    }
+
+    /// Text of a source line, numbered from 1
+    pub fn source_line(&self, file: usize, line: usize) -> Option<&str> {
+        let file = self.files.get(&file)?;
+        if line == 0 {
+            return None;
+        }
+        file.lines.get(line - 1).map(|l| l.as_str())
+    }
+
+    /// Label defined exactly at `addr`
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(|l| l.as_str())
+    }
+
+    /// Nearest label at or before `addr`, as `label` or `label+offset`
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let (&at, label) = self.labels.range(..=addr).next_back()?;
+        if at == addr {
+            Some(label.clone())
+        } else {
+            Some(format!("{}+{}", label, addr - at))
+        }
+    }
+
+    /// Innermost named scope containing `addr`
+    pub fn scope(&self, addr: u16) -> Option<String> {
+        self.scope_at.get(&addr).and_then(|&(id, _)| self.scope_name(id))
+    }
+
+    /// Value of a symbol, plain or qualified like `scope::name`
+    pub fn resolve(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello() -> DebugInfo {
+        DebugInfo::load(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/hello.dbg")).unwrap()
+    }
+
+    #[test]
+    fn attributes() {
+        let record = parse_attributes(r#"id=3,name="a, b=c",size=0x1F"#);
+        assert_eq!(record.get("name").map(|n| n.as_str()), Some("a, b=c"));
+        assert_eq!(number(&record, "id"), Some(3));
+        assert_eq!(number(&record, "size"), Some(0x1f));
+        assert_eq!(ids(&parse_attributes("span=3+4+17"), "span"), vec![3, 4, 17]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(DebugInfo::parse("seg\tid=0,start=0", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn lines() {
+        let info = hello();
+        // Plain source wins over the macro line covering the same bytes
+        assert_eq!(info.location(0x402), Some(Location { file: 0, line: 11 }));
+        assert_eq!(info.location(0x404), Some(Location { file: 0, line: 11 }));
+        assert_eq!(info.location(0x010), Some(Location { file: 0, line: 8 }));
+        assert_eq!(info.location(0x40d), None);
+        assert_eq!((info.file_name(0), info.file_name(1), info.file_name(9)), ("hello.s", "macros.inc", "?"));
+        assert_eq!(info.source_line(0, 11), Some("        call print"));
+        assert_eq!(info.source_line(1, 3), Some("        jsr target"));
+        assert_eq!(info.source_line(0, 0), None);
+    }
+
+    #[test]
+    fn missing_sources() {
+        let text = "version\tmajor=2,minor=0\nfile\tid=0,name=\"gone.s\",size=1,mtime=0,mod=0";
+        let info = DebugInfo::parse(text, Path::new("/nonexistent")).unwrap();
+        // Missing source files just have no lines
+        assert_eq!(info.file_name(0), "gone.s");
+        assert_eq!(info.source_line(0, 1), None);
+    }
+
+    #[test]
+    fn labels_and_scopes() {
+        let info = hello();
+        assert_eq!(info.label(0x408), Some("print"));
+        assert_eq!(info.label(0x409), None);
+        assert_eq!(info.describe(0x40a).as_deref(), Some("print+2"));
+        assert_eq!(info.describe(0x00f), None);
+        assert_eq!(info.scope(0x409).as_deref(), Some("print"));
+        assert_eq!(info.scope(0x400), None);
+    }
+
+    #[test]
+    fn symbols() {
+        let info = hello();
+        assert_eq!(info.resolve("start"), Some(0x400));
+        assert_eq!(info.resolve("@loop"), Some(0x408));
+        assert_eq!(info.resolve("print::@loop"), Some(0x408));
+        assert_eq!(info.resolve("COUNT"), Some(3));
+        assert_eq!(info.resolve("beef"), Some(0x40c));
+        assert_eq!(info.resolve("big"), None);
+    }
+}
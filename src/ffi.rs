@@ -0,0 +1,237 @@
+//! A C API around the core, for tools in C, C++ or Python (through
+//! ctypes or cffi). `include/emu6502.h` is generated from this file with
+//! `cbindgen --config cbindgen.toml --output include/emu6502.h src/ffi.rs`.
+//!
+//! Every function takes the handle `emu6502_new` returned, which must not
+//! be used after `emu6502_free` or from two threads at once. Pointers to
+//! buffers must be valid for the length given with them. Functions that
+//! can fail return 0 on success and -1 on failure, and
+//! `emu6502_last_error` then says why.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::ops::Range;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::slice;
+
+use crate::computer::device::Device;
+use crate::computer::protocol::Register;
+use crate::computer::{Computer, Variant};
+
+/// An emulated 6502 with 64 KiB of memory, opaque to C
+pub struct Emu6502 {
+    computer: Computer,
+    last_error: CString,
+}
+
+/// The registers, with B and bit 5 always clear in `flags`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Emu6502Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub flags: u8,
+    pub pc: u16,
+}
+
+/// Called for each read of a mapped address, returns the byte read
+pub type Emu6502ReadFn = Option<extern "C" fn(user: *mut c_void, addr: u16) -> u8>;
+
+/// Called for each write to a mapped address
+pub type Emu6502WriteFn = Option<extern "C" fn(user: *mut c_void, addr: u16, value: u8)>;
+
+/// Addresses whose reads and writes go to C callbacks instead of memory
+#[derive(Debug)]
+struct CallbackDevice {
+    start: u16,
+    end: u16,
+    read: extern "C" fn(*mut c_void, u16) -> u8,
+    write: extern "C" fn(*mut c_void, u16, u8),
+    user: *mut c_void,
+}
+
+// The computer, and so `user`, is only ever used from the caller's thread
+unsafe impl Send for CallbackDevice {}
+
+impl Device for CallbackDevice {
+    fn name(&self) -> String {
+        format!("C callbacks at {:#06x}-{:#06x}", self.start, self.end)
+    }
+
+    fn handles(&self, addr: u16) -> bool {
+        addr >= self.start && addr <= self.end
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        (self.read)(self.user, addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        (self.write)(self.user, addr, value)
+    }
+}
+
+impl Emu6502 {
+    /// Turn `result` into a status code, keeping the error for
+    /// `emu6502_last_error`
+    fn status(&mut self, result: Result<(), String>) -> c_int {
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                self.last_error = CString::new(e).unwrap_or_default();
+                -1
+            },
+        }
+    }
+}
+
+/// The memory `len` bytes from `addr` cover, or an error if they run past
+/// the end of the address space
+fn range(addr: u16, len: usize) -> Result<Range<usize>, String> {
+    let start = addr as usize;
+    match start.checked_add(len) {
+        Some(end) if end <= 0x10000 => Ok(start..end),
+        _ => Err(format!("{} bytes do not fit at {:#06x}", len, addr)),
+    }
+}
+
+/// A 6502 with zeroed memory, or a 65C02 when `cmos` is set. Free it with
+/// `emu6502_free`.
+#[no_mangle]
+pub extern "C" fn emu6502_new(cmos: bool) -> *mut Emu6502 {
+    let mut computer = Computer::headless(vec![0; 0x10000]);
+    computer.set_variant(if cmos { Variant::Cmos } else { Variant::Nmos });
+    Box::into_raw(Box::new(Emu6502 { computer, last_error: CString::default() }))
+}
+
+/// Free an emulator. Does nothing when given NULL.
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_free(emu: *mut Emu6502) {
+    if !emu.is_null() {
+        drop(Box::from_raw(emu));
+    }
+}
+
+/// Why the last call that returned -1 failed. The string belongs to the
+/// emulator and lasts until the next failure.
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_last_error(emu: *const Emu6502) -> *const c_char {
+    (*emu).last_error.as_ptr()
+}
+
+/// Copy the binary file at `path` into memory at `addr`
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_load(emu: *mut Emu6502, path: *const c_char, addr: u16) -> c_int {
+    let emu = &mut *emu;
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    let result = fs::read(&path)
+        .map_err(|e| format!("could not read {}: {}", path, e))
+        .and_then(|bytes| emu.computer.load(addr, &bytes));
+    emu.status(result)
+}
+
+/// Put the processor back in its power on state, with PC at $0400. Memory
+/// and the cycle count are left alone.
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_reset(emu: *mut Emu6502) {
+    (*emu).computer.reset();
+}
+
+/// Run one instruction and return the cycles it took
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_step(emu: *mut Emu6502) -> u32 {
+    (*emu).computer.run_one() as u32
+}
+
+/// Run until the program traps in a jump or branch to itself, or at least
+/// `max_cycles` have passed, and return the cycles run
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_run(emu: *mut Emu6502, max_cycles: u64) -> u64 {
+    let computer = &mut (*emu).computer;
+    let start = computer.processor().clock;
+    computer.run_to_trap(max_cycles);
+    computer.processor().clock - start
+}
+
+/// Cycles run since the emulator was created
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_clock(emu: *const Emu6502) -> u64 {
+    (*emu).computer.processor().clock
+}
+
+/// Copy the registers to `registers`
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_get_registers(emu: *const Emu6502, registers: *mut Emu6502Registers) {
+    let processor = (*emu).computer.processor();
+    *registers = Emu6502Registers {
+        a: processor.acc,
+        x: processor.rx,
+        y: processor.ry,
+        sp: processor.sp,
+        flags: processor.flags,
+        pc: processor.pc,
+    };
+}
+
+/// Set every register. B and bit 5 of `flags` are ignored.
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_set_registers(emu: *mut Emu6502, registers: *const Emu6502Registers) {
+    let r = *registers;
+    let computer = &mut (*emu).computer;
+    let set = [
+        (Register::A, r.a as u16), (Register::X, r.x as u16), (Register::Y, r.y as u16),
+        (Register::SP, r.sp as u16), (Register::Flags, r.flags as u16), (Register::PC, r.pc),
+    ];
+    for &(register, value) in set.iter() {
+        // Bytes always fit
+        let _ = computer.set_register(register, value);
+    }
+}
+
+/// Copy `len` bytes of memory from `addr` to `out`, bypassing devices
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_read_memory(emu: *mut Emu6502, addr: u16, out: *mut u8, len: usize) -> c_int {
+    let emu = &mut *emu;
+    let result = range(addr, len).map(|range| {
+        let memory = emu.computer.memory();
+        ptr::copy_nonoverlapping(memory[range].as_ptr(), out, len);
+    });
+    emu.status(result)
+}
+
+/// Copy `len` bytes from `bytes` into memory at `addr`, bypassing devices
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_write_memory(emu: *mut Emu6502, addr: u16, bytes: *const u8, len: usize) -> c_int {
+    let emu = &mut *emu;
+    let result = emu.computer.load(addr, slice::from_raw_parts(bytes, len));
+    emu.status(result)
+}
+
+/// Send the processor's reads and writes of `start` to `end`, inclusive,
+/// to `read` and `write`, which get `user` back. Later mappings do not
+/// take over addresses an earlier one has.
+#[no_mangle]
+pub unsafe extern "C" fn emu6502_map(
+    emu: *mut Emu6502,
+    start: u16,
+    end: u16,
+    read: Emu6502ReadFn,
+    write: Emu6502WriteFn,
+    user: *mut c_void,
+) -> c_int {
+    let emu = &mut *emu;
+    let result = match (read, write) {
+        _ if start > end => Err(format!("{:#06x} is after {:#06x}", start, end)),
+        (Some(read), Some(write)) => {
+            emu.computer.add_device(Box::new(CallbackDevice { start, end, read, write, user }));
+            Ok(())
+        },
+        _ => Err("both callbacks are needed".to_string()),
+    };
+    emu.status(result)
+}
@@ -0,0 +1,213 @@
+use std::fs;
+
+/// Where the functional test keeps its current test number, unless the
+/// listing says otherwise
+const TEST_CASE: u16 = 0x200;
+/// Test number stored once every opcode test has passed
+const TESTS_COMPLETE: u8 = 0xf0;
+/// Name of the code before the first `next_test`
+const SETUP: &str = "setup and RAM checksum";
+
+/// Progress of Klaus Dormann's 6502 functional test, decoded from its
+/// ca65 source or listing. Tests are numbered by the `next_test` macro and
+/// named after the first comment following it.
+pub struct FunctionalTest {
+    /// Address of the `test_case` variable
+    test_case: u16,
+    /// Section name for each test number
+    sections: Vec<String>,
+    /// Address of the `success` trap, only known from a listing
+    success: Option<u16>,
+}
+
+/// Outcome once the test has trapped
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// Test number and the address of the trap it failed in
+    Failed(u8, u16),
+}
+
+impl FunctionalTest {
+    /// Read the `.ca65` source or the `.lst` listing of the test. `None`
+    /// when the file is readable but is some other program.
+    pub fn load(path: &str) -> Result<Option<FunctionalTest>, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        Ok(Self::parse(&text))
+    }
+
+    fn parse(text: &str) -> Option<FunctionalTest> {
+        let mut test = FunctionalTest { test_case: TEST_CASE, sections: vec![SETUP.to_string()], success: None };
+        let mut in_macro = false;
+        let mut unnamed = false;
+        let mut found = false;
+        for line in text.lines() {
+            let (addr, source) = split_listing(line);
+            let source = source.trim();
+            let word = source.split_whitespace().next().unwrap_or("");
+            match word {
+                ".macro" => in_macro = true,
+                ".endmacro" => in_macro = false,
+                _ if in_macro => {},
+                "next_test" => {
+                    test.sections.push(format!("test {}", test.sections.len()));
+                    unnamed = true;
+                    found = true;
+                },
+                "success" => test.success = addr,
+                "test_case:" => {
+                    if let Some(addr) = addr {
+                        test.test_case = addr;
+                    }
+                },
+                _ if unnamed && source.starts_with(';') => {
+                    *test.sections.last_mut().unwrap() = source.trim_start_matches(';').trim().to_string();
+                    unnamed = false;
+                },
+                _ => {},
+            }
+        }
+        if found { Some(test) } else { None }
+    }
+
+    /// Address of the `test_case` variable
+    pub fn test_case(&self) -> u16 {
+        self.test_case
+    }
+
+    /// What test number `number` checks
+    pub fn section(&self, number: u8) -> String {
+        match self.sections.get(number as usize) {
+            Some(name) => name.clone(),
+            None if number == TESTS_COMPLETE => "final RAM check".to_string(),
+            None => "unknown test".to_string(),
+        }
+    }
+
+    /// Whether trapping at `pc` with `number` in `test_case` means the test
+    /// passed. Without a listing the success trap is recognised by the
+    /// test number alone.
+    pub fn outcome(&self, number: u8, pc: u16) -> Outcome {
+        let passed = match self.success {
+            Some(success) => pc == success,
+            None => number == TESTS_COMPLETE,
+        };
+        if passed { Outcome::Passed } else { Outcome::Failed(number, pc) }
+    }
+
+    /// Test number, status and section name of `memory`, `trapped` when it
+    /// is stuck in a trap at `pc`
+    pub fn describe(&self, memory: &[u8], pc: u16, trapped: bool) -> String {
+        let number = memory.get(self.test_case as usize).copied().unwrap_or(0);
+        let status = match (trapped, self.outcome(number, pc)) {
+            (false, _) => "running".to_string(),
+            (true, Outcome::Passed) => "PASSED".to_string(),
+            (true, Outcome::Failed(_, pc)) => format!("FAILED at {:#06x}", pc),
+        };
+        format!("Test {} ({:#04x})\n{}\n{}", number, number, status, self.section(number))
+    }
+}
+
+/// Address and source text of a listing line like
+/// `000444  1  A0 FE                ldy #$fe`. Plain source lines, and
+/// relocatable listing lines, have no address.
+fn split_listing(line: &str) -> (Option<u16>, &str) {
+    let hex = line.get(0..6).is_some_and(|a| a.chars().all(|c| c.is_ascii_hexdigit()));
+    if !hex || !line.get(6..9).is_some_and(|s| s.ends_with(" 1") || s.ends_with(" 2")) {
+        return (None, line);
+    }
+    let addr = if &line[6..7] == " " { u16::from_str_radix(&line[0..6], 16).ok() } else { None };
+    (addr, line.get(24..).unwrap_or(""))
+}
+
+/// Whether the instruction at `pc` jumps or branches to itself with the
+/// given flags, which is how the functional test stops
+pub fn trapped(memory: &[u8], pc: u16, flags: u8) -> bool {
+    let byte = |offset: u16| memory.get(pc.wrapping_add(offset) as usize).copied().unwrap_or(0);
+    match byte(0) {
+        0x4c => u16::from(byte(1)) | u16::from(byte(2)) << 8 == pc,
+        opcode if opcode & 0x1f == 0x10 => {
+            // Bits 6 and 7 pick N, V, C or Z, bit 5 the value that branches
+            let flag = [0x80, 0x40, 0x01, 0x02][(opcode >> 6) as usize];
+            byte(1) == 0xfe && (flags & flag != 0) == (opcode & 0x20 != 0)
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Source with a macro defining `next_test`, which must not count
+    const SOURCE: &str = "
+        .macro  next_test
+        lda test_case
+        .endmacro
+        next_test
+        next_test
+; testing loads
+        lda #0
+        next_test
+
+        ; testing stores
+";
+
+    #[test]
+    fn sections_from_source() {
+        let test = FunctionalTest::parse(SOURCE).unwrap();
+        assert_eq!(test.test_case(), TEST_CASE);
+        assert_eq!(test.section(0), SETUP);
+        assert_eq!(test.section(1), "test 1");
+        assert_eq!(test.section(2), "testing loads");
+        assert_eq!(test.section(3), "testing stores");
+        assert_eq!(test.section(4), "unknown test");
+        assert_eq!(test.section(TESTS_COMPLETE), "final RAM check");
+        // Without a listing only the test number says it passed
+        assert_eq!(test.outcome(TESTS_COMPLETE, 0x1234), Outcome::Passed);
+        assert_eq!(test.outcome(3, 0x1234), Outcome::Failed(3, 0x1234));
+        assert!(FunctionalTest::parse("        lda #0\n").is_none());
+    }
+
+    #[test]
+    fn sections_and_traps_from_the_listing() {
+        let test = FunctionalTest::load(concat!(env!("CARGO_MANIFEST_DIR"), "/6502_functional_test.lst")).unwrap().unwrap();
+        assert_eq!(test.test_case(), 0x200);
+        assert_eq!(test.section(1), "testing relative addressing with BEQ");
+        assert_eq!(test.section(2), "partial test BNE & CMP, CPX, CPY immediate");
+        assert_eq!(test.outcome(TESTS_COMPLETE, 0x3469), Outcome::Passed);
+        assert_eq!(test.outcome(TESTS_COMPLETE, 0x0442), Outcome::Failed(TESTS_COMPLETE, 0x0442));
+
+        let mut memory = vec![0; 0x10000];
+        memory[0x200] = 2;
+        assert_eq!(test.describe(&memory, 0x0590, true), "Test 2 (0x02)\nFAILED at 0x0590\npartial test BNE & CMP, CPX, CPY immediate");
+        assert!(test.describe(&memory, 0x0590, false).contains("\nrunning\n"));
+        assert!(FunctionalTest::load("no such listing").is_err());
+        assert!(FunctionalTest::load(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/hello.s")).unwrap().is_none());
+    }
+
+    #[test]
+    fn listing_lines() {
+        assert_eq!(split_listing("000444  1  A0 FE                ldy #$fe"), (Some(0x444), "        ldy #$fe"));
+        assert_eq!(split_listing("00043C  1  00 D0 FE A9  "), (Some(0x43c), ""));
+        assert_eq!(split_listing("000000r 1               .macro  success"), (None, ".macro  success"));
+        assert_eq!(split_listing("        lda #0"), (None, "        lda #0"));
+    }
+
+    #[test]
+    fn traps() {
+        let mut memory = vec![0; 0x10000];
+        memory[0x400..0x403].copy_from_slice(&[0x4c, 0x00, 0x04]);
+        memory[0x500..0x502].copy_from_slice(&[0xd0, 0xfe]);
+        memory[0x600..0x602].copy_from_slice(&[0xb0, 0xfe]);
+        memory[0x700..0x703].copy_from_slice(&[0x4c, 0x00, 0x05]);
+        assert!(trapped(&memory, 0x400, 0));
+        // BNE only traps with Z clear, BCS with C set
+        assert!(trapped(&memory, 0x500, 0));
+        assert!(!trapped(&memory, 0x500, 0x02));
+        assert!(trapped(&memory, 0x600, 0x01));
+        assert!(!trapped(&memory, 0x600, 0));
+        assert!(!trapped(&memory, 0x700, 0));
+        assert!(!trapped(&memory, 0xffff, 0));
+    }
+}
@@ -0,0 +1,255 @@
+use std::fs;
+
+use cursive::event::{Event, Key};
+
+/// Something a shortcut can do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Run,
+    Pause,
+    StepInto,
+    StepOver,
+    StepOut,
+    ToggleBreakpoint,
+    Reset,
+    Faster,
+    Slower,
+    Edit,
+    Memory,
+    Banks,
+    ScrollUp,
+    ScrollDown,
+    Help,
+    Quit,
+}
+
+/// Name used in key files, and what the help overlay says each does
+const ACTIONS: [(Action, &str, &str); 16] = [
+    (Action::Run, "run", "Run"),
+    (Action::Pause, "pause", "Pause"),
+    (Action::StepInto, "step_into", "Step one instruction"),
+    (Action::StepOver, "step_over", "Step over a JSR"),
+    (Action::StepOut, "step_out", "Run until the current call returns"),
+    (Action::ToggleBreakpoint, "toggle_breakpoint", "Set or clear a breakpoint"),
+    (Action::Reset, "reset", "Reset the processor"),
+    (Action::Faster, "faster", "Faster clock"),
+    (Action::Slower, "slower", "Slower clock"),
+    (Action::Edit, "edit", "Edit registers and memory"),
+    (Action::Memory, "memory", "Memory view menu"),
+    (Action::Banks, "banks", "Inspect banks"),
+    (Action::ScrollUp, "scroll_up", "Page up in the memory view"),
+    (Action::ScrollDown, "scroll_down", "Page down in the memory view"),
+    (Action::Help, "help", "Show these keys"),
+    (Action::Quit, "quit", "Quit"),
+];
+
+/// Named keys understood in key files, besides single characters
+const KEYS: [(&str, Key); 26] = [
+    ("F1", Key::F1), ("F2", Key::F2), ("F3", Key::F3), ("F4", Key::F4),
+    ("F5", Key::F5), ("F6", Key::F6), ("F7", Key::F7), ("F8", Key::F8),
+    ("F9", Key::F9), ("F10", Key::F10), ("F11", Key::F11), ("F12", Key::F12),
+    ("Enter", Key::Enter), ("Tab", Key::Tab), ("Backspace", Key::Backspace), ("Esc", Key::Esc),
+    ("Left", Key::Left), ("Right", Key::Right), ("Up", Key::Up), ("Down", Key::Down),
+    ("Home", Key::Home), ("End", Key::End), ("PageUp", Key::PageUp), ("PageDown", Key::PageDown),
+    ("Ins", Key::Ins), ("Del", Key::Del),
+];
+
+/// Which key triggers which action
+pub struct KeyMap {
+    bindings: Vec<(Action, Event)>,
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap {
+            bindings: vec![
+                (Action::Run, Event::Key(Key::F5)),
+                (Action::Pause, Event::Key(Key::F6)),
+                (Action::StepInto, Event::Key(Key::F11)),
+                (Action::StepOver, Event::Key(Key::F10)),
+                (Action::StepOut, Event::Shift(Key::F11)),
+                (Action::ToggleBreakpoint, Event::Key(Key::F9)),
+                (Action::Reset, Event::CtrlChar('r')),
+                (Action::Faster, Event::Char('+')),
+                (Action::Slower, Event::Char('-')),
+                (Action::Edit, Event::Char('e')),
+                (Action::Memory, Event::Char('m')),
+                (Action::Banks, Event::Char('b')),
+                (Action::ScrollUp, Event::Key(Key::PageUp)),
+                (Action::ScrollDown, Event::Key(Key::PageDown)),
+                (Action::Help, Event::Key(Key::F1)),
+                (Action::Quit, Event::CtrlChar('q')),
+            ],
+        }
+    }
+}
+
+impl KeyMap {
+    /// The default bindings, overridden by the lines of a key file like
+    /// `step_over = F10` or `reset = Ctrl+R`. `#` starts a comment and
+    /// `action = none` removes a binding.
+    pub fn load(path: &str) -> Result<KeyMap, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        Self::parse(&text, path)
+    }
+
+    /// Apply the lines of a key file read from `path` to the defaults
+    fn parse(text: &str, path: &str) -> Result<KeyMap, String> {
+        let mut keys = KeyMap::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let key = parts.next().ok_or(format!("{}:{}: expected <action> = <key>", path, number + 1))?.trim();
+            let action = ACTIONS.iter()
+                .find(|&&(_, n, _)| n == name)
+                .map(|&(action, _, _)| action)
+                .ok_or(format!("{}:{}: unknown action {}", path, number + 1, name))?;
+            keys.bindings.retain(|&(a, _)| a != action);
+            if key != "none" {
+                let event = parse_key(key).map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+                keys.bindings.push((action, event));
+            }
+        }
+        Ok(keys)
+    }
+
+    pub fn bindings(&self) -> &[(Action, Event)] {
+        &self.bindings
+    }
+
+    /// Name of the key bound to `action`, if any
+    pub fn key_for(&self, action: Action) -> Option<String> {
+        self.bindings.iter().find(|&&(a, _)| a == action).map(|(_, event)| key_name(event))
+    }
+
+    /// One line per binding, for the help overlay
+    pub fn help(&self) -> String {
+        ACTIONS.iter()
+            .filter_map(|&(action, _, text)| self.key_for(action).map(|key| format!("{:<12} {}", key, text)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Parse a key like `F5`, `Shift+F11`, `Ctrl+R`, `Alt+x` or `?`
+pub fn parse_key(s: &str) -> Result<Event, String> {
+    let mut parts: Vec<&str> = s.split('+').map(|p| p.trim()).collect();
+    // A lone `+`, or a modifier followed by it, splits into empty parts
+    if parts.len() > 1 && parts[parts.len() - 1].is_empty() {
+        parts.pop();
+        let last = parts.len() - 1;
+        parts[last] = "+";
+    }
+    let (name, modifiers) = parts.split_last().ok_or(format!("invalid key {}", s))?;
+    let mut ctrl = false;
+    let mut shift = false;
+    let mut alt = false;
+    for modifier in modifiers {
+        match modifier.to_lowercase().as_str() {
+            "ctrl" => ctrl = true,
+            "shift" => shift = true,
+            "alt" => alt = true,
+            _ => return Err(format!("unknown modifier {} in {}", modifier, s)),
+        }
+    }
+
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return match (ctrl, alt, shift) {
+            (false, false, false) => Ok(Event::Char(c)),
+            (true, false, false) => Ok(Event::CtrlChar(c.to_ascii_lowercase())),
+            (false, true, false) => Ok(Event::AltChar(c)),
+            _ => Err(format!("unsupported modifiers for {}", s)),
+        };
+    }
+
+    let key = KEYS.iter()
+        .find(|&&(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+        .ok_or(format!("unknown key {}", name))?;
+    match (ctrl, alt, shift) {
+        (false, false, false) => Ok(Event::Key(key)),
+        (false, false, true) => Ok(Event::Shift(key)),
+        (true, false, false) => Ok(Event::Ctrl(key)),
+        (false, true, false) => Ok(Event::Alt(key)),
+        (true, false, true) => Ok(Event::CtrlShift(key)),
+        (true, true, false) => Ok(Event::CtrlAlt(key)),
+        (false, true, true) => Ok(Event::AltShift(key)),
+        _ => Err(format!("unsupported modifiers for {}", s)),
+    }
+}
+
+/// How a key is written in key files and the help overlay
+pub fn key_name(event: &Event) -> String {
+    let name = |key: &Key| KEYS.iter()
+        .find(|&&(_, k)| k == *key)
+        .map(|&(n, _)| n.to_string())
+        .unwrap_or_else(|| format!("{:?}", key));
+    match event {
+        Event::Char(c) => c.to_string(),
+        Event::CtrlChar(c) => format!("Ctrl+{}", c.to_ascii_uppercase()),
+        Event::AltChar(c) => format!("Alt+{}", c),
+        Event::Key(key) => name(key),
+        Event::Shift(key) => format!("Shift+{}", name(key)),
+        Event::Ctrl(key) => format!("Ctrl+{}", name(key)),
+        Event::Alt(key) => format!("Alt+{}", name(key)),
+        Event::CtrlShift(key) => format!("Ctrl+Shift+{}", name(key)),
+        Event::CtrlAlt(key) => format!("Ctrl+Alt+{}", name(key)),
+        Event::AltShift(key) => format!("Alt+Shift+{}", name(key)),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse_key("F5"), Ok(Event::Key(Key::F5)));
+        assert_eq!(parse_key("pageup"), Ok(Event::Key(Key::PageUp)));
+        assert_eq!(parse_key("Shift+F11"), Ok(Event::Shift(Key::F11)));
+        assert_eq!(parse_key("Ctrl+Alt+Del"), Ok(Event::CtrlAlt(Key::Del)));
+        assert_eq!(parse_key("Ctrl+R"), Ok(Event::CtrlChar('r')));
+        assert_eq!(parse_key("Alt+x"), Ok(Event::AltChar('x')));
+        assert_eq!(parse_key("?"), Ok(Event::Char('?')));
+        assert_eq!(parse_key("+"), Ok(Event::Char('+')));
+        assert_eq!(parse_key("Alt++"), Ok(Event::AltChar('+')));
+        assert!(parse_key("F13").is_err());
+        assert!(parse_key("Hyper+F1").is_err());
+        assert!(parse_key("Shift+x").is_err());
+        assert!(parse_key("Ctrl+Alt+Shift+F1").is_err());
+    }
+
+    #[test]
+    fn names_read_back() {
+        for (action, event) in KeyMap::default().bindings() {
+            assert_eq!(parse_key(&key_name(event)).as_ref(), Ok(event), "{:?}", action);
+        }
+    }
+
+    #[test]
+    fn key_files() {
+        let keys = KeyMap::parse("# my keys\nstep_over = F8\n\nreset = Ctrl+Alt+Del  # safer\nquit = none\n", "keys.cfg").unwrap();
+        assert_eq!(keys.key_for(Action::StepOver).as_deref(), Some("F8"));
+        assert_eq!(keys.key_for(Action::Reset).as_deref(), Some("Ctrl+Alt+Del"));
+        assert_eq!(keys.key_for(Action::Quit), None);
+        assert_eq!(keys.key_for(Action::Run).as_deref(), Some("F5"));
+        assert_eq!(keys.bindings().iter().filter(|&&(a, _)| a == Action::StepOver).count(), 1);
+        assert!(!keys.help().contains("Quit"));
+        assert!(keys.help().contains("F8           Step over a JSR"));
+    }
+
+    #[test]
+    fn key_file_errors() {
+        let error = |text| KeyMap::parse(text, "keys.cfg").err().unwrap();
+        assert_eq!(error("run = F5\nstep_over\n"), "keys.cfg:2: expected <action> = <key>");
+        assert_eq!(error("jump = F5"), "keys.cfg:1: unknown action jump");
+        assert_eq!(error("\n\nrun = F13"), "keys.cfg:3: unknown key F13");
+        assert!(KeyMap::load("no such key file").is_err());
+    }
+}
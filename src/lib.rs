@@ -0,0 +1,12 @@
+//! The emulator core, without the terminal UI, so it can be driven
+//! headless from benchmarks and other front ends.
+
+pub mod computer;
+pub mod debuginfo;
+pub mod functest;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ffi")]
+pub mod ffi;
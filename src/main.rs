@@ -13,13 +13,51 @@ use cursive::theme::Color;
 use cursive::theme::Style;
 use cursive::utils::markup::StyledString;
 use std::sync::mpsc;
-use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::ops::Range;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-mod computer;
 mod utils;
+mod memview;
+mod keys;
 
-use computer::{Processor, Computer, ControllerMessage, ComputerMessage};
+use rust_test::{computer, debuginfo, functest};
+
+use computer::{Processor, Computer, Variant, OUTPUT_BTM, OUTPUT_TOP};
+use computer::device::Device;
+use computer::acia::{Acia, AciaHost};
+use computer::riot::Riot;
+use computer::banking::{BankInfo, BankSwitch};
+use computer::profiler::Profiler;
+use computer::coverage::{self, Coverage};
+use computer::memory::MemoryUpdate;
+use computer::assembler;
+use computer::callstack::{CallFrame, CallKind};
+use computer::machine::Machine;
+use computer::protocol::{Command, Event, Register, Reply, State};
+use debuginfo::DebugInfo;
+use functest::{FunctionalTest, Outcome};
+use memview::MemoryWindow;
+use keys::{Action, KeyMap};
+
+/// Number of instructions shown in the disassembly pane
+const DISASM_LINES: usize = 14;
+/// Source lines shown above and below the current one
+const SOURCE_CONTEXT: usize = 4;
+/// Lines in the memory pane, shared between its windows
+const MEMORY_LINES: usize = 32;
+/// Most memory windows open at once
+const MEMORY_WINDOWS: usize = 4;
+/// Innermost calls shown in the call stack pane
+const CALL_STACK_LINES: usize = 10;
+/// Status flag letters, bit 7 first
+const FLAG_NAMES: &str = "NV-BDIZC";
+/// Steps a changed memory byte stays highlighted, fading as it ages
+const FADE_STEPS: u8 = 4;
+/// Cycles after which `--bench` gives up waiting for a trap
+const BENCH_MAX_CYCLES: u64 = 1_000_000_000;
 
 pub struct Ui {
     cursive: Cursive,
@@ -27,22 +65,87 @@ pub struct Ui {
     ui_tx: mpsc::Sender<UiMessage>,
     controller_tx: mpsc::Sender<ControllerMessage>,
     data: Vec<u8>,
+    banks: Vec<BankInfo>,
+    debug: Option<DebugInfo>,
+    coverage: Option<Coverage>,
+    /// Test sections, when running the functional test
+    functest: Option<FunctionalTest>,
+    /// Last processor state shown, the starting point for edits
+    processor: Option<Processor>,
+    paused: bool,
+    /// Registers and memory at the previous pause or step, which changes
+    /// are highlighted against
+    baseline: Option<Processor>,
+    baseline_data: Vec<u8>,
+    /// Registers before the last step, while paused
+    previous: Option<Processor>,
+    /// Steps left before each changed byte stops being highlighted
+    ages: Vec<u8>,
+    windows: Vec<MemoryWindow>,
+    /// Window that scrolling, goto and search apply to
+    active_window: usize,
+    /// Pattern of the last memory search
+    search: Option<Vec<u8>>,
+    breakpoints: BTreeSet<u16>,
+    /// Key bindings, listed by the help overlay
+    help: String,
+    /// Target clock rate in Hz, 0 when unthrottled
+    target_hz: u64,
     clk: u64,
     t: u128,
 }
 
+/// Messages from the UI to the controller
+pub enum ControllerMessage {
+    /// Pass a command on to the emulator
+    Command(Command),
+    /// Run when paused, pause when running
+    TogglePause,
+    /// Move to the next preset clock rate, up when true
+    ChangeSpeed(bool),
+    SaveProfile,
+    SaveCoverage,
+}
+
 pub enum UiMessage {
     UpdateProcessor(Processor),
-    UpdateData(u16, u8),
     FullData(Vec<u8>),
-    UpdateStack(Vec<u8>),
-    UpdateOutput(Vec<u8>),
+    UpdateMemory(MemoryUpdate),
+    UpdateBanks(Vec<BankInfo>),
+    UpdateBank(usize, usize, Vec<u8>),
+    ShowBanks,
+    ShowEdit,
+    /// Ask for a line to assemble, given the address and text to start with
+    ShowAssemble(String, String),
+    /// Assemble a line at an address or symbol and write it to memory
+    Assemble(String, String),
+    ShowMemoryMenu,
+    /// Scroll the active memory window by pages
+    ScrollMemory(isize),
+    /// Pin the active memory window to an address or symbol
+    GotoMemory(String),
+    ToggleFollowPc,
+    SearchMemory(Vec<u8>),
+    FindNext,
+    NewMemoryWindow,
+    CloseMemoryWindow,
+    NextMemoryWindow,
+    /// Ask where to set or clear a breakpoint
+    PromptBreakpoint,
+    /// Set or clear a breakpoint at an address or symbol
+    ToggleBreakpoint(String),
+    ShowHelp,
+    UpdateCoverage(Coverage),
+    UpdateCallStack(Vec<CallFrame>),
+    UpdateClock(u64),
+    UpdatePaused(bool),
+    Notify(String),
 }
 
 impl Ui {
     /// Create a new Ui object.  The provided `mpsc` sender will be used
     /// by the UI to send messages to the controller.
-    pub fn new(controller_tx: mpsc::Sender<ControllerMessage>) -> Ui {
+    pub fn new(controller_tx: mpsc::Sender<ControllerMessage>, debug: Option<DebugInfo>, functest: Option<FunctionalTest>, keys: &KeyMap) -> Ui {
         let (ui_tx, ui_rx) = mpsc::channel::<UiMessage>();
         let t = SystemTime::now().duration_since(UNIX_EPOCH).expect("fail");
         let mut ui = Ui {
@@ -51,6 +154,22 @@ impl Ui {
             ui_rx: ui_rx,
             controller_tx: controller_tx,
             data: vec![],
+            banks: vec![],
+            debug,
+            coverage: None,
+            functest,
+            processor: None,
+            paused: true,
+            baseline: None,
+            baseline_data: vec![],
+            previous: None,
+            ages: vec![0; 0x10000],
+            windows: vec![MemoryWindow::following_pc()],
+            active_window: 0,
+            search: None,
+            breakpoints: BTreeSet::new(),
+            help: keys.help(),
+            target_hz: 0,
             clk: 0,
             t: t.as_millis(),
         };
@@ -61,6 +180,11 @@ impl Ui {
         let controller_tx_clone1 = ui.controller_tx.clone();
         let controller_tx_clone2 = ui.controller_tx.clone();
         let controller_tx_clone3 = ui.controller_tx.clone();
+        let controller_tx_clone4 = ui.controller_tx.clone();
+        let controller_tx_clone5 = ui.controller_tx.clone();
+        let ui_tx_clone = ui.ui_tx.clone();
+        let ui_tx_clone1 = ui.ui_tx.clone();
+        let ui_tx_clone2 = ui.ui_tx.clone();
 
 
 
@@ -71,37 +195,99 @@ impl Ui {
             
             .button("Faster", move |s| {
                 controller_tx_clone.send(
-                    ControllerMessage::ButtonPressed("faster".to_string())
+                    ControllerMessage::ChangeSpeed(true)
                 )
                 .unwrap();
             })
             .button("Slower", move |s| {
                 controller_tx_clone1.send(
-                    ControllerMessage::ButtonPressed("slower".to_string())
+                    ControllerMessage::ChangeSpeed(false)
                 )
                 .unwrap();
             })
             .button("Pause", move |s| {
                 controller_tx_clone2.send(
-                    ControllerMessage::ButtonPressed("pause".to_string())
+                    ControllerMessage::TogglePause
                 )
                 .unwrap();
             })
             .button("Step", move |s| {
                 controller_tx_clone3.send(
-                    ControllerMessage::ButtonPressed("step".to_string())
+                    ControllerMessage::Command(Command::Step(1))
+                )
+                .unwrap();
+            })
+            .button("Edit", move |s| {
+                ui_tx_clone1.send(UiMessage::ShowEdit).unwrap();
+            })
+            .button("Memory", move |s| {
+                ui_tx_clone2.send(UiMessage::ShowMemoryMenu).unwrap();
+            })
+            .button("Profile", move |s| {
+                controller_tx_clone4.send(
+                    ControllerMessage::SaveProfile
                 )
                 .unwrap();
             })
+            .button("Coverage", move |s| {
+                controller_tx_clone5.send(
+                    ControllerMessage::SaveCoverage
+                )
+                .unwrap();
+            })
+            .button("Banks", move |s| {
+                ui_tx_clone.send(UiMessage::ShowBanks).unwrap();
+            })
             .button("Quit", |s| {
                 s.quit();
                 // std::process::abort();
                 // std::process::exit(0);
             })
-            .title("6502 simulator")
+            .title(match keys.key_for(Action::Help) {
+                Some(key) => format!("6502 simulator ({} for keys)", key),
+                None => "6502 simulator".to_string(),
+            })
             .full_screen()
         );
 
+        for (action, event) in keys.bindings() {
+            let action = *action;
+            let controller_tx = ui.controller_tx.clone();
+            let ui_tx = ui.ui_tx.clone();
+            ui.cursive.add_global_callback(event.clone(), move |s| {
+                let command = match action {
+                    Action::Run => Command::Run,
+                    Action::Pause => Command::Pause,
+                    Action::StepInto => Command::Step(1),
+                    Action::StepOver => Command::StepOver,
+                    Action::StepOut => Command::StepOut,
+                    Action::Reset => Command::Reset,
+                    Action::Faster | Action::Slower => {
+                        controller_tx.send(ControllerMessage::ChangeSpeed(action == Action::Faster)).unwrap();
+                        return;
+                    },
+                    Action::Quit => {
+                        s.quit();
+                        return;
+                    },
+                    _ => {
+                        let message = match action {
+                            Action::ToggleBreakpoint => UiMessage::PromptBreakpoint,
+                            Action::Edit => UiMessage::ShowEdit,
+                            Action::Memory => UiMessage::ShowMemoryMenu,
+                            Action::Banks => UiMessage::ShowBanks,
+                            Action::ScrollUp => UiMessage::ScrollMemory(-1),
+                            Action::ScrollDown => UiMessage::ScrollMemory(1),
+                            _ => UiMessage::ShowHelp,
+                        };
+                        ui_tx.send(message).unwrap();
+                        return;
+                    },
+                };
+                controller_tx.send(ControllerMessage::Command(command)).unwrap();
+            });
+        }
+
         // Configure a callback
         ui.cursive.refresh();
         
@@ -120,32 +306,41 @@ impl Ui {
         while let Some(message) = self.ui_rx.try_iter().next() {
             match message {
                 UiMessage::UpdateProcessor(processor) => {
+                    self.processor = Some(processor.clone());
+                    if self.paused {
+                        // A step, an edit or the end of a run
+                        self.previous = self.baseline.replace(processor.clone());
+                        self.age_memory();
+                    }
+                    // Changes only mean something between pauses
+                    let previous = if self.paused { self.previous.clone() } else { None };
+                    let before = previous.as_ref().unwrap_or(&processor);
                     //println!("UpdateProcessor {}", processor.clock);
                     let mut output = self.cursive
                         .find_id::<TextView>("flags")
                         .unwrap();
-                    output.set_content(format!("{:08b}", processor.flags));
+                    output.set_content(flags_text(processor.flags, before.flags));
 
                     let mut output = self.cursive
                         .find_id::<TextView>("pc")
                         .unwrap();
-                    output.set_content(format!("{} ({:#06x})", processor.pc, processor.pc));
+                    output.set_content(changed_text(format!("{} ({:#06x})", processor.pc, processor.pc), processor.pc != before.pc));
                     let mut output = self.cursive
                         .find_id::<TextView>("acc")
                         .unwrap();
-                    output.set_content(format!("{:#04x}", processor.acc));
+                    output.set_content(changed_text(format!("{:#04x}", processor.acc), processor.acc != before.acc));
                     let mut output = self.cursive
                         .find_id::<TextView>("rx")
                         .unwrap();
-                    output.set_content(format!("{:#04x}", processor.rx));
+                    output.set_content(changed_text(format!("{:#04x}", processor.rx), processor.rx != before.rx));
                     let mut output = self.cursive
                         .find_id::<TextView>("ry")
                         .unwrap();
-                    output.set_content(format!("{:#04x}", processor.ry));
+                    output.set_content(changed_text(format!("{:#04x}", processor.ry), processor.ry != before.ry));
                     let mut output = self.cursive
                         .find_id::<TextView>("sp")
                         .unwrap();
-                    output.set_content(format!("{:#04x}", processor.sp));
+                    output.set_content(changed_text(format!("{:#04x}", processor.sp), processor.sp != before.sp));
 
                     let mut output = self.cursive
                         .find_id::<TextView>("clock")
@@ -163,7 +358,13 @@ impl Ui {
                         let mut speed = self.cursive
                             .find_id::<TextView>("speed")
                             .unwrap();
-                        speed.set_content(format!("{:.2} MHz", sp as f64 / 1000.0 / (t - self.t) as f64));
+                        let actual = sp as f64 / 1000.0 / (t - self.t) as f64;
+                        let target = if self.target_hz == 0 {
+                            "unlimited".to_string()
+                        } else {
+                            format!("{:.2}", self.target_hz as f64 / 1_000_000.0)
+                        };
+                        speed.set_content(format!("{:.2} / {} MHz", actual, target));
                         self.t = t;
                     }
                     
@@ -190,117 +391,203 @@ impl Ui {
                     let mut output = self.cursive
                         .find_id::<TextView>("test")
                         .unwrap();
-                    output.set_content(format!("{}", processor.test[0]));
-
-                    // chunk data
-                    
-                    //let out = (&self.data).chunks(16);
-                    let out: Vec<&[u8]> = self.data.chunks(16).collect();
-                    let center = processor.pc >> 4;
-
-                    //println!("center: {:#x} - number of lines : {}", center, out.len());
-
-                    let btm: u16 = if center - 16 < 0 { 0 } else { center - 16 };
-                    let top: u16 = if center + 16 > out.len() as u16 { out.len() as u16 } else { center + 16 };
-
-                    let mut iter = out[btm as usize ..=top as usize].iter();
-                    let mut cnt = 0;
-
-                    while let Some(line) = iter.next() {
-                        let mut inner = line.iter();
-                        let mut text = "".to_string();
-                        let addr = (btm << 4) + cnt;
-                        let mut text = StyledString::plain(format!("{:06x}  ", addr).as_str());
-                        // text.append(StyledString::styled("that ", Color::Dark(BaseColor::Red)));
-                        // styled.append(StyledString::styled(
-                        //     "cool?",
-                        //     Style::from(Color::Light(BaseColor::Blue)).combine(Effect::Bold),
-                        // ));
-                        let atv = self.cursive.find_id::<TextView>(format!("addr-{}", cnt >> 4).as_str());
-                        
-                        while let Some(item) = inner.next() {
-                            if cnt % 4 == 0 {
-                                if ((btm << 4) + cnt == processor.pc) {
-                                    text.append(StyledString::styled(format!("  {:02x}", item).as_str(), Style::from(Color::Dark(BaseColor::Red)).combine(Effect::Bold)));
-                                } else {
-                                    text.append(StyledString::plain(format!("  {:02x}", item).as_str()));
-                                }
-                            } else {
-                                if ((btm << 4) + cnt == processor.pc) {
-                                    text.append(StyledString::styled(format!(" {:02x}", item).as_str(), Style::from(Color::Dark(BaseColor::Red)).combine(Effect::Bold)));
-                                } else {
-                                    text.append(StyledString::plain(format!(" {:02x}", item).as_str()));
-                                }
-                            }
-                            cnt += 1;
-                        }
+                    match &self.functest {
+                        Some(test) => {
+                            let trapped = functest::trapped(&self.data, processor.pc, processor.flags);
+                            output.set_content(test.describe(&self.data, processor.pc, trapped));
+                        },
+                        None => output.set_content(format!("{}", processor.test[0])),
+                    }
 
-                        if let Some(mut aview) = atv {
-                            aview.set_content(text);
-                        }
+                    self.show_code(processor.pc);
 
+                    self.show_memory();
+                    self.show_stack();
+                },
+                UiMessage::UpdateMemory(update) => {
+                    update.apply(&mut self.data);
+                    if update.touches(0x100, 0x200) {
+                        self.show_stack();
+                    }
+                    if update.touches(OUTPUT_BTM as usize, OUTPUT_TOP as usize) {
+                        self.show_output();
                     }
-                    // Update memory display here
-
-                    // break full data by lines of 32 bytes
-                    // Center vertically on processor.pc
                 },
-                UiMessage::UpdateData(addr, data) => {
-                    self.data[addr as usize] = data;
+                UiMessage::FullData(data) => {
+                    self.data = data;
+                    self.data.resize(0x10000, 0);
+                    self.baseline_data = self.data.clone();
+                    self.show_stack();
+                    self.show_output();
+                    self.show_memory();
                 },
-                UiMessage::UpdateStack(data) => {
-                    let mut cnt = 0;
-                    let out: Vec<&[u8]> = data.chunks(16).collect();
-                    let mut iter = out.iter();
-
-                    while let Some(line) = iter.next() {
-                        let mut inner = line.iter();
-                        let mut text = "".to_string();
-
-                        let atv = self.cursive.find_id::<TextView>(format!("stack-{}", cnt >> 4).as_str());
-                        let addr = 0x100 + cnt;
-
-                        while let Some(item) = inner.next() {
-                            
-                            if cnt % 4 == 0 {
-                                text = format!("{}  {:02x}", text.as_str(), item);
-                            } else {
-                                text = format!("{} {:02x}", text.as_str(), item);
-                            }
-                            cnt += 1;
-                        }
-
-                        if let Some(mut aview) = atv {
-                            aview.set_content(format!("{:#06x}  {}", addr, text));
+                UiMessage::UpdateBanks(banks) => {
+                    if let Some(mut output) = self.cursive.find_id::<TextView>("banks") {
+                        let lines: Vec<String> = banks.iter().map(|b| {
+                            format!(
+                                "{:#06x}-{:#06x}  {} bank {} of {} (select at {:#06x})",
+                                b.window,
+                                b.window as usize + b.size - 1,
+                                if b.rom { "ROM" } else { "RAM" },
+                                b.active,
+                                b.count,
+                                b.register
+                            )
+                        }).collect();
+                        output.set_content(lines.join("\n"));
+                    }
+                    self.banks = banks;
+                },
+                UiMessage::ShowBanks => {
+                    if self.banks.is_empty() {
+                        self.cursive.add_layer(Dialog::info("No banked memory"));
+                        continue;
+                    }
+                    let mut select = SelectView::<(usize, usize)>::new();
+                    for (window, info) in self.banks.iter().enumerate() {
+                        for bank in 0..info.count {
+                            let active = if bank == info.active { " (active)" } else { "" };
+                            select.add_item(format!("{:#06x} bank {}{}", info.window, bank, active), (window, bank));
                         }
                     }
+                    let controller_tx = self.controller_tx.clone();
+                    select.set_on_submit(move |s, &(window, bank)| {
+                        s.pop_layer();
+                        controller_tx.send(
+                            ControllerMessage::Command(Command::GetBank(window, bank))
+                        )
+                        .unwrap();
+                    });
+                    self.cursive.add_layer(
+                        Dialog::around(select.scrollable())
+                            .title("Inspect bank")
+                            .dismiss_button("Close")
+                    );
+                },
+                UiMessage::ShowEdit => {
+                    self.show_edit();
+                },
+                UiMessage::ShowAssemble(addr, line) => {
+                    let ui_tx = self.ui_tx.clone();
+                    self.cursive.add_layer(utils::form("Assemble", &[("Address", addr), ("Instruction", line)], move |_, values| {
+                        ui_tx.send(UiMessage::Assemble(values[0].clone(), values[1].clone())).unwrap();
+                        Ok(())
+                    }));
+                },
+                UiMessage::Assemble(addr, line) => {
+                    self.assemble(addr, line);
+                },
+                UiMessage::ShowMemoryMenu => {
+                    self.show_memory_menu();
+                },
+                UiMessage::ScrollMemory(pages) => {
+                    let lines = self.window_lines();
+                    let pc = self.pc();
+                    self.windows[self.active_window].scroll(pages * lines as isize, lines, pc);
+                    self.show_memory();
+                },
+                UiMessage::GotoMemory(target) => {
+                    match parse_address(target.trim(), self.debug.as_ref()) {
+                        Ok(addr) => self.windows[self.active_window].goto(addr),
+                        Err(e) => self.cursive.add_layer(Dialog::info(e)),
+                    }
+                    self.show_memory();
+                },
+                UiMessage::ToggleFollowPc => {
+                    let window = &mut self.windows[self.active_window];
+                    if window.follows_pc() {
+                        let lines = self.window_lines();
+                        let pc = self.pc();
+                        self.windows[self.active_window].scroll(0, lines, pc);
+                    } else {
+                        window.follow_pc();
+                    }
+                    self.show_memory();
+                },
+                UiMessage::SearchMemory(pattern) => {
+                    self.search = Some(pattern);
+                    self.find_next(false);
+                },
+                UiMessage::FindNext => {
+                    self.find_next(true);
+                },
+                UiMessage::NewMemoryWindow => {
+                    if self.windows.len() < MEMORY_WINDOWS {
+                        self.windows.push(MemoryWindow::pinned(0));
+                        self.active_window = self.windows.len() - 1;
+                    }
+                    self.show_memory();
+                },
+                UiMessage::CloseMemoryWindow => {
+                    if self.windows.len() > 1 {
+                        self.windows.remove(self.active_window);
+                        self.active_window = self.active_window.min(self.windows.len() - 1);
+                    }
+                    self.show_memory();
                 },
-                UiMessage::UpdateOutput(data) => {
-                    if let Some(mut output) = self.cursive.find_id::<TextView>("output") {
-                        let mut cnt = 0;
-                        let out: Vec<&[u8]> = data.chunks(16).collect();
-                        let mut iter = out.iter();
-                        let mut text = "".to_string();
-                        while let Some(line) = iter.next() {
-                            let mut inner = line.iter();
-                            
-                            let addr = 0xf000 + cnt;
-                            text = format!("{}{:#06x}  ", text.as_str(), addr);
-                            while let Some(item) = inner.next() {
-                                if cnt % 4 == 0 {
-                                    text = format!("{}  {:02x}", text.as_str(), item);
-                                } else {
-                                    text = format!("{} {:02x}", text.as_str(), item);
-                                }
-                                cnt += 1;
+                UiMessage::PromptBreakpoint => {
+                    let pc = format!("{:04x}", self.pc());
+                    let ui_tx = self.ui_tx.clone();
+                    self.cursive.add_layer(utils::form("Toggle breakpoint", &[("Address", pc)], move |_, values| {
+                        ui_tx.send(UiMessage::ToggleBreakpoint(values[0].clone())).unwrap();
+                        Ok(())
+                    }));
+                },
+                UiMessage::ToggleBreakpoint(target) => {
+                    match parse_address(target.trim(), self.debug.as_ref()) {
+                        Ok(addr) => {
+                            let set = self.breakpoints.insert(addr);
+                            if !set {
+                                self.breakpoints.remove(&addr);
                             }
-                            text = format!("{}\n", text.as_str());
-                        }
-                        output.set_content(text);
-                    }   
+                            self.controller_tx.send(
+                                ControllerMessage::Command(Command::SetBreakpoint(addr, set))
+                            )
+                            .unwrap();
+                            let pc = self.pc();
+                            self.show_code(pc);
+                        },
+                        Err(e) => self.cursive.add_layer(Dialog::info(e)),
+                    }
                 },
-                UiMessage::FullData(data) => {
-                    self.data = data;
+                UiMessage::ShowHelp => {
+                    self.cursive.add_layer(
+                        Dialog::text(self.help.clone())
+                            .title("Keys")
+                            .dismiss_button("Close")
+                    );
+                },
+                UiMessage::NextMemoryWindow => {
+                    self.active_window = (self.active_window + 1) % self.windows.len();
+                    self.show_memory();
+                },
+                UiMessage::UpdateCoverage(coverage) => {
+                    self.coverage = Some(coverage);
+                },
+                UiMessage::UpdateCallStack(frames) => {
+                    self.show_call_stack(&frames);
+                },
+                UiMessage::UpdatePaused(paused) => {
+                    self.paused = paused;
+                },
+                UiMessage::UpdateClock(hz) => {
+                    self.target_hz = hz;
+                },
+                UiMessage::Notify(text) => {
+                    self.cursive.add_layer(Dialog::info(text));
+                },
+                UiMessage::UpdateBank(window, bank, data) => {
+                    if let Some(info) = self.banks.get(window) {
+                        let title = format!("Bank {} at {:#06x}", bank, info.window);
+                        self.cursive.add_layer(
+                            Dialog::around(
+                                TextView::new(utils::hex_dump(info.window, &data)).scrollable()
+                            )
+                            .title(title)
+                            .dismiss_button("Close")
+                            .max_height(36)
+                        );
+                    }
                 },
                 _ => {},
             }
@@ -311,33 +598,430 @@ impl Ui {
         self.cursive.refresh();
         true
     }
+
+    fn pc(&self) -> u16 {
+        self.processor.as_ref().map_or(0, |p| p.pc)
+    }
+
+    /// Lines of memory each window has room for
+    fn window_lines(&self) -> usize {
+        let count = self.windows.len();
+        (MEMORY_LINES - count) / count
+    }
+
+    /// Render every memory window into the memory pane, one under the other
+    fn show_memory(&mut self) {
+        if self.data.len() < 0x10000 {
+            return;
+        }
+        let lines = self.window_lines();
+        let pc = self.pc();
+        let style = |addr: u16| self.change_style(addr).or_else(|| self.coverage_style(addr));
+        let mut text = StyledString::new();
+        for (i, window) in self.windows.iter().enumerate() {
+            let marker = if i == self.active_window && self.windows.len() > 1 { ">" } else { " " };
+            let title = format!("{}{}", marker, i + 1);
+            text.append(window.render(&title, &self.data, lines, pc, &style));
+        }
+        if let Some(mut view) = self.cursive.find_id::<TextView>("memory") {
+            view.set_content(text);
+        }
+    }
+
+    /// Move the active window to the next match of the search pattern,
+    /// after the current match when `next`
+    fn find_next(&mut self, next: bool) {
+        let pattern = match &self.search {
+            Some(pattern) => pattern.clone(),
+            None => return,
+        };
+        let lines = self.window_lines();
+        let pc = self.pc();
+        let window = &mut self.windows[self.active_window];
+        let from = match window.found() {
+            Some(found) if next => found + 1,
+            Some(found) => found,
+            None => {
+                window.scroll(0, lines, pc);
+                0
+            },
+        };
+        match memview::find(&self.data, &pattern, from) {
+            Some(start) => window.show_match(start, pattern.len()),
+            None => self.cursive.add_layer(Dialog::info("Not found")),
+        }
+        self.show_memory();
+    }
+
+    /// Offer goto, follow, search and window management for memory
+    fn show_memory_menu(&mut self) {
+        let ui_tx = self.ui_tx.clone();
+        let follow = if self.windows[self.active_window].follows_pc() { "Pin here" } else { "Follow PC" };
+        let mut select = SelectView::<&'static str>::new();
+        for &choice in ["Goto", follow, "Search bytes", "Search text", "Find next", "New window", "Close window", "Next window"].iter() {
+            select.add_item(choice, choice);
+        }
+        select.set_on_submit(move |s, &choice| {
+            s.pop_layer();
+            let tx = ui_tx.clone();
+            let message = match choice {
+                "Goto" => {
+                    s.add_layer(utils::form("Goto", &[("Address", String::new())], move |_, values| {
+                        tx.send(UiMessage::GotoMemory(values[0].clone())).unwrap();
+                        Ok(())
+                    }));
+                    return;
+                },
+                "Search bytes" => {
+                    s.add_layer(utils::form("Search bytes", &[("Bytes", String::new())], move |_, values| {
+                        let pattern = values[0].split_whitespace().map(parse_byte).collect::<Result<Vec<u8>, String>>()?;
+                        tx.send(UiMessage::SearchMemory(pattern)).unwrap();
+                        Ok(())
+                    }));
+                    return;
+                },
+                "Search text" => {
+                    s.add_layer(utils::form("Search text", &[("Text", String::new())], move |_, values| {
+                        tx.send(UiMessage::SearchMemory(values[0].as_bytes().to_vec())).unwrap();
+                        Ok(())
+                    }));
+                    return;
+                },
+                "Find next" => UiMessage::FindNext,
+                "New window" => UiMessage::NewMemoryWindow,
+                "Close window" => UiMessage::CloseMemoryWindow,
+                "Next window" => UiMessage::NextMemoryWindow,
+                _ => UiMessage::ToggleFollowPc,
+            };
+            tx.send(message).unwrap();
+        });
+        self.cursive.add_layer(
+            Dialog::around(select)
+                .title("Memory")
+                .dismiss_button("Close")
+        );
+    }
+
+    /// List the active calls, innermost first, with their symbols
+    fn show_call_stack(&mut self, frames: &[CallFrame]) {
+        let debug = &self.debug;
+        let name = |addr: u16| match debug.as_ref().and_then(|d| d.describe(addr)) {
+            Some(name) => format!("{:#06x} {}", addr, name),
+            None => format!("{:#06x}", addr),
+        };
+        let mut lines: Vec<String> = frames.iter().rev().take(CALL_STACK_LINES).map(|frame| {
+            let kind = match frame.kind {
+                CallKind::Subroutine => "JSR",
+                CallKind::Break => "BRK",
+                CallKind::Interrupt => "IRQ",
+            };
+            format!("{} {}\n    from {} returns to {:#06x}", kind, name(frame.routine), name(frame.call_site), frame.return_addr)
+        }).collect();
+        if frames.len() > CALL_STACK_LINES {
+            lines.push(format!("... {} more", frames.len() - CALL_STACK_LINES));
+        }
+        if lines.is_empty() {
+            lines.push("No active calls".to_string());
+        }
+        if let Some(mut view) = self.cursive.find_id::<TextView>("callstack") {
+            view.set_content(lines.join("\n"));
+        }
+    }
+
+    /// Offer the register, flag and memory editors. The emulator only
+    /// accepts the edits while paused.
+    fn show_edit(&mut self) {
+        let processor = match &self.processor {
+            Some(processor) => processor.clone(),
+            None => return,
+        };
+        let controller_tx = self.controller_tx.clone();
+        let ui_tx = self.ui_tx.clone();
+        let mut select = SelectView::<&'static str>::new();
+        for &choice in ["Registers", "Flags", "Write bytes", "Fill range", "Copy range", "Assemble"].iter() {
+            select.add_item(choice, choice);
+        }
+        select.set_on_submit(move |s, &choice| {
+            s.pop_layer();
+            let tx = controller_tx.clone();
+            let dialog = match choice {
+                "Registers" => register_editor(&processor, tx),
+                "Flags" => flag_editor(processor.flags, tx),
+                "Assemble" => {
+                    ui_tx.send(UiMessage::ShowAssemble(format!("{:04x}", processor.pc), String::new())).unwrap();
+                    return;
+                },
+                _ => memory_editor(choice, tx),
+            };
+            s.add_layer(dialog);
+        });
+        self.cursive.add_layer(
+            Dialog::around(select)
+                .title("Edit")
+                .dismiss_button("Close")
+        );
+    }
+
+    /// Assemble `line` at `addr` and write it to memory, then ask for the
+    /// next line after it, like a monitor's assemble command. An empty line
+    /// stops, an error asks for the line again.
+    fn assemble(&mut self, addr: String, line: String) {
+        if line.trim().is_empty() {
+            return;
+        }
+        let debug = self.debug.as_ref();
+        let assembled = parse_address(addr.trim(), debug).and_then(|start| {
+            let symbol = |name: &str| debug.and_then(|d| d.resolve(name));
+            assembler::assemble_with(&line, start, &symbol).map(|assembly| (start, assembly))
+        });
+        match assembled {
+            Ok((start, assembly)) => {
+                let mut next = start;
+                for (start, bytes) in assembly.segments {
+                    next = start.wrapping_add(bytes.len() as u16);
+                    self.controller_tx.send(ControllerMessage::Command(Command::WriteMemory(start, bytes))).unwrap();
+                }
+                self.ui_tx.send(UiMessage::ShowAssemble(format!("{:04x}", next), String::new())).unwrap();
+            },
+            Err(e) => {
+                self.ui_tx.send(UiMessage::ShowAssemble(addr, line)).unwrap();
+                self.ui_tx.send(UiMessage::Notify(e)).unwrap();
+            },
+        }
+    }
+
+    /// Show the stack page, highlighting recently changed bytes
+    fn show_stack(&mut self) {
+        for (line, chunk) in self.data[0x100..0x200].chunks(16).enumerate() {
+            let addr = 0x100 + line * 16;
+            let mut text = StyledString::plain(format!("{:#06x}  ", addr));
+            for (i, item) in chunk.iter().enumerate() {
+                let byte = if i % 4 == 0 { format!("  {:02x}", item) } else { format!(" {:02x}", item) };
+                match self.change_style((addr + i) as u16) {
+                    Some(style) => text.append(StyledString::styled(byte, style)),
+                    None => text.append(StyledString::plain(byte)),
+                }
+            }
+            if let Some(mut view) = self.cursive.find_id::<TextView>(format!("stack-{}", line).as_str()) {
+                view.set_content(text);
+            }
+        }
+    }
+
+    fn show_output(&mut self) {
+        if let Some(mut output) = self.cursive.find_id::<TextView>("output") {
+            let mut cnt = 0;
+            let out: Vec<&[u8]> = self.data[OUTPUT_BTM as usize..OUTPUT_TOP as usize].chunks(16).collect();
+            let mut text = "".to_string();
+            for line in out.iter() {
+                let addr = OUTPUT_BTM + cnt;
+                text = format!("{}{:#06x}  ", text.as_str(), addr);
+                for item in line.iter() {
+                    if cnt % 4 == 0 {
+                        text = format!("{}  {:02x}", text.as_str(), item);
+                    } else {
+                        text = format!("{} {:02x}", text.as_str(), item);
+                    }
+                    cnt += 1;
+                }
+                text = format!("{}\n", text.as_str());
+            }
+            output.set_content(text);
+        }
+    }
+
+    /// Start a new step: bytes that differ from the previous pause become
+    /// freshly changed and older changes fade by one step
+    fn age_memory(&mut self) {
+        if self.baseline_data.len() != self.data.len() {
+            self.baseline_data = self.data.clone();
+            return;
+        }
+        for (i, age) in self.ages.iter_mut().enumerate() {
+            if self.data[i] != self.baseline_data[i] {
+                *age = FADE_STEPS;
+            } else if *age > 0 {
+                *age -= 1;
+            }
+        }
+        self.baseline_data.copy_from_slice(&self.data);
+    }
+
+    /// Highlight for a byte changed in the last few steps, brightest for
+    /// the last one. Nothing is highlighted while running.
+    fn change_style(&self, addr: u16) -> Option<Style> {
+        if !self.paused {
+            return None;
+        }
+        let color = match self.ages[addr as usize] {
+            0 => return None,
+            FADE_STEPS => return Some(changed_style()),
+            age if age + 1 == FADE_STEPS => Color::Light(BaseColor::Yellow),
+            age if age + 2 == FADE_STEPS => Color::Dark(BaseColor::Yellow),
+            _ => Color::Dark(BaseColor::White),
+        };
+        Some(Style::from(color).combine(Effect::Bold))
+    }
+
+    /// Colour for a byte in the memory pane by how it has been accessed:
+    /// green executed, cyan operand, magenta read and written, yellow
+    /// written, blue read
+    fn coverage_style(&self, addr: u16) -> Option<Style> {
+        let access = self.coverage.as_ref()?.map()[addr as usize];
+        let color = match coverage::marker(access) {
+            'X' => Color::Dark(BaseColor::Green),
+            'o' => Color::Dark(BaseColor::Cyan),
+            'B' => Color::Dark(BaseColor::Magenta),
+            'W' => Color::Dark(BaseColor::Yellow),
+            'R' => Color::Dark(BaseColor::Blue),
+            _ => return None,
+        };
+        Some(Style::from(color))
+    }
+
+    /// Show the disassembly from `pc` and the source line that produced it
+    fn show_code(&mut self, pc: u16) {
+        if self.data.len() <= 0xffff {
+            return;
+        }
+        let debug = &self.debug;
+        let label = |addr: u16| debug.as_ref().and_then(|d| d.label(addr)).map(|l| l.to_string());
+        let current = Style::from(Color::Dark(BaseColor::Red)).combine(Effect::Bold);
+
+        let mut text = StyledString::new();
+        let mut addr = pc;
+        for _ in 0..DISASM_LINES {
+            if let Some(name) = label(addr) {
+                text.append(StyledString::plain(format!("{}:\n", name)));
+            }
+            let (inst, len) = computer::decode::disassemble(&self.data, addr, &label);
+            let mark = if self.breakpoints.contains(&addr) { "*" } else { " " };
+            let line = format!("{} {:#06x}  {}\n", mark, addr, inst);
+            if addr == pc {
+                text.append(StyledString::styled(line, current));
+            } else {
+                text.append(StyledString::plain(line));
+            }
+            addr = addr.wrapping_add(len);
+        }
+
+        let source = match debug {
+            None => StyledString::plain("No debug info, load one with --dbg"),
+            Some(debug) => match debug.location(pc) {
+                None => StyledString::plain(format!("No source for {:#06x}", pc)),
+                Some(location) => {
+                    let mut source = StyledString::plain(format!("{}:{}", debug.file_name(location.file), location.line));
+                    if let Some(scope) = debug.scope(pc) {
+                        source.append(StyledString::plain(format!("  in {}", scope)));
+                    }
+                    if let Some(name) = debug.describe(pc) {
+                        source.append(StyledString::plain(format!("  at {}", name)));
+                    }
+                    source.append(StyledString::plain("\n\n"));
+                    let first = location.line.saturating_sub(SOURCE_CONTEXT).max(1);
+                    for number in first..=location.line + SOURCE_CONTEXT {
+                        if let Some(line) = debug.source_line(location.file, number) {
+                            let line = format!("{:5}  {}\n", number, line.replace('\t', "    "));
+                            if number == location.line {
+                                source.append(StyledString::styled(line, current));
+                            } else {
+                                source.append(StyledString::plain(line));
+                            }
+                        }
+                    }
+                    source
+                },
+            },
+        };
+
+        if let Some(mut view) = self.cursive.find_id::<TextView>("disasm") {
+            view.set_content(text);
+        }
+        if let Some(mut view) = self.cursive.find_id::<TextView>("source") {
+            view.set_content(source);
+        }
+    }
 }
 
 
 pub struct Controller {
     rx: mpsc::Receiver<ControllerMessage>,
-    ctx: mpsc::Sender<ComputerMessage>,
+    /// The emulator, on its own thread
+    machine: Machine,
+    /// Id of the `GetState` request in flight, if any
+    state_request: Option<u64>,
+    /// Whether the emulator may have changed since the last state
+    stale: bool,
+    paused: bool,
+    target_hz: u64,
     ui: Ui,
+    profile: Option<String>,
+    callgrind: Option<String>,
+    coverage: Option<String>,
+    listing: Option<String>,
+}
+
+/// Command line options following the image file name
+#[derive(Default)]
+pub struct Options {
+    devices: Vec<Box<dyn Device>>,
+    debug: Option<DebugInfo>,
+    /// Where to write the profiler's text report
+    profile: Option<String>,
+    /// Where to write the profile in callgrind format
+    callgrind: Option<String>,
+    /// Where to write the coverage report
+    coverage: Option<String>,
+    /// ca65 listing to annotate with coverage
+    listing: Option<String>,
+    /// Sections of the functional test, read from the listing
+    functest: Option<FunctionalTest>,
+    /// Target clock rate in Hz, 0 when unthrottled
+    clock: Option<u64>,
+    /// Run a bus cycle at a time, with the dummy accesses
+    cycle_exact: bool,
+    /// Processor to emulate, the 6502 unless given
+    variant: Option<Variant>,
+    /// Run headless as fast as possible and report the speed
+    bench: bool,
+    /// Script to run headless instead of the UI
+    script: Option<String>,
+    keys: KeyMap,
 }
 
 impl Controller {
     /// Create a new controller
-    pub fn new(filename: String) -> Result<Controller, String> {
+    pub fn new(filename: String, options: Options) -> Result<Controller, String> {
         let data = fs::read(filename).expect("could not read file");
         
         let (tx, rx) = mpsc::channel::<ControllerMessage>();
-        let controller_tx = tx.clone();
-        let (computer_tx, computer_rx) = mpsc::channel::<ComputerMessage>();
         let computer_data = data.clone();
-        let child = thread::spawn(move || {
-            let mut computer = Computer::new(controller_tx, computer_rx, computer_data);
-            loop {
-                computer.step();
+        let devices = options.devices;
+        let profiling = options.profile.is_some() || options.callgrind.is_some();
+        let covering = options.coverage.is_some();
+        let clock = options.clock;
+        let cycle_exact = options.cycle_exact;
+        let variant = options.variant;
+        let machine = Machine::spawn(computer_data, move |computer| {
+            for device in devices {
+                computer.add_device(device);
+            }
+            if profiling {
+                computer.enable_profiler();
+            }
+            if covering {
+                computer.enable_coverage();
+            }
+            if let Some(hz) = clock {
+                computer.set_clock(hz);
+            }
+            if let Some(variant) = variant {
+                computer.set_variant(variant);
             }
+            computer.set_cycle_exact(cycle_exact);
         });
-        
 
-        let ui = Ui::new(tx.clone());
+        let ui = Ui::new(tx.clone(), options.debug, options.functest, &options.keys);
 
         ui
             .ui_tx
@@ -346,54 +1030,543 @@ impl Controller {
 
         Ok(Controller {
             rx: rx,
-            ctx: computer_tx.clone(),
+            machine,
+            state_request: None,
+            stale: true,
+            paused: true,
+            target_hz: clock.unwrap_or(0),
             ui,
+            profile: options.profile,
+            callgrind: options.callgrind,
+            coverage: options.coverage,
+            listing: options.listing,
         })
     }
+
+    /// Write the profile reports requested on the command line
+    fn write_profile(&self, profiler: &Profiler) -> Result<String, String> {
+        let debug = self.ui.debug.as_ref();
+        let name = |addr: u16| {
+            debug.and_then(|d| d.label(addr))
+                .map(|l| l.to_string())
+                .unwrap_or_else(|| format!("${:04x}", addr))
+        };
+        let mut written = vec![];
+        if let Some(path) = &self.profile {
+            let mut file = fs::File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+            profiler.write_report(&mut file, &name).map_err(|e| format!("could not write {}: {}", path, e))?;
+            written.push(path.clone());
+        }
+        if let Some(path) = &self.callgrind {
+            let mut file = fs::File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+            profiler.write_callgrind(&mut file, &name).map_err(|e| format!("could not write {}: {}", path, e))?;
+            written.push(path.clone());
+        }
+        Ok(format!("Profile written to {}", written.join(" and ")))
+    }
+
+    /// Write the coverage report, as an annotated listing when one was given
+    fn write_coverage(&self, coverage: &Coverage) -> Result<String, String> {
+        let path = match &self.coverage {
+            Some(path) => path,
+            None => return Err("coverage is off".to_string()),
+        };
+        let mut file = fs::File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+        match &self.listing {
+            Some(listing) => {
+                let text = fs::read_to_string(listing).map_err(|e| format!("could not read {}: {}", listing, e))?;
+                coverage.write_listing(&text, &mut file)
+            },
+            None => coverage.write_summary(&mut file),
+        }.map_err(|e| format!("could not write {}: {}", path, e))?;
+        Ok(format!("Coverage written to {}", path))
+    }
+    /// Send a command to the emulator, returning the id its reply will
+    /// carry, or None when its thread has gone
+    fn send(&mut self, command: Command) -> Option<u64> {
+        self.machine.send(command).ok()
+    }
+
+    fn notify(&self, text: String) {
+        self.ui
+            .ui_tx
+            .send(UiMessage::Notify(text))
+            .unwrap();
+    }
+
+    /// Handle a message arriving from the UI
+    fn handle_ui(&mut self, message: ControllerMessage) {
+        let command = match message {
+            ControllerMessage::Command(command) => command,
+            ControllerMessage::TogglePause => {
+                if self.paused { Command::Run } else { Command::Pause }
+            },
+            ControllerMessage::ChangeSpeed(faster) => {
+                Command::SetSpeed(computer::next_clock_rate(self.target_hz, faster))
+            },
+            ControllerMessage::SaveProfile => {
+                if self.profile.is_none() && self.callgrind.is_none() {
+                    self.notify("Profiling is off, start with --profile <file> or --callgrind <file>".to_string());
+                    return;
+                }
+                Command::GetProfile
+            },
+            ControllerMessage::SaveCoverage => {
+                if self.coverage.is_none() {
+                    self.notify("Coverage is off, start with --coverage <file>".to_string());
+                    return;
+                }
+                Command::GetCoverage
+            },
+        };
+        self.send(command);
+        self.stale = true;
+    }
+
+    /// Handle an event arriving from the emulator
+    fn handle_event(&mut self, event: Event) {
+        let (id, reply) = match event {
+            Event::Reply(id, reply) => (id, reply),
+            Event::Breakpoint(_) => {
+                self.stale = true;
+                return;
+            },
+        };
+        if self.state_request == Some(id) {
+            self.state_request = None;
+        }
+        let text = match reply {
+            Reply::State(state) => {
+                self.show_state(state);
+                return;
+            },
+            Reply::Bank(window, bank, data) => {
+                self.ui
+                    .ui_tx
+                    .send(UiMessage::UpdateBank(window, bank, data))
+                    .unwrap();
+                return;
+            },
+            Reply::Profile(profiler) => self.write_profile(&profiler),
+            Reply::Coverage(coverage) => self.write_coverage(&coverage),
+            Reply::Error(e) => Err(e),
+            Reply::Done | Reply::Memory(..) => return,
+        };
+        self.notify(text.unwrap_or_else(|e| format!("Error: {}", e)));
+    }
+
+    /// Pass a state snapshot on to the UI
+    fn show_state(&mut self, state: State) {
+        self.paused = state.paused;
+        self.ui.ui_tx.send(UiMessage::UpdatePaused(state.paused)).unwrap();
+        self.target_hz = state.target_hz;
+        // Memory goes first so the processor view renders against it
+        if !state.memory.pages.is_empty() {
+            self.ui.ui_tx.send(UiMessage::UpdateMemory(state.memory)).unwrap();
+        }
+        self.ui.ui_tx.send(UiMessage::UpdateCallStack(state.call_stack)).unwrap();
+        self.ui.ui_tx.send(UiMessage::UpdateProcessor(state.processor)).unwrap();
+        self.ui.ui_tx.send(UiMessage::UpdateClock(state.target_hz)).unwrap();
+        if !state.banks.is_empty() {
+            self.ui.ui_tx.send(UiMessage::UpdateBanks(state.banks)).unwrap();
+        }
+        if let Some(coverage) = state.coverage {
+            self.ui.ui_tx.send(UiMessage::UpdateCoverage(coverage)).unwrap();
+        }
+    }
+
     /// Run the controller
     pub fn run(&mut self) {
-        let mut speed = 2;
-        let mut i = 1;
-        let mut paused: bool = true;
-        let mut step: bool = false;
         while self.ui.step() {
-            self.ctx.send(ComputerMessage::GetData());
+            // Ask for state only while something can change, and never
+            // with a request already in flight
+            if self.state_request.is_none() && (self.stale || !self.paused) {
+                self.stale = false;
+                self.state_request = self.send(Command::GetState);
+            }
             while let Some(message) = self.rx.try_iter().next() {
-                // Handle messages arriving from the UI.
-                match message {
-                    ControllerMessage::ButtonPressed(btn) => {
-                        self.ctx.send(ComputerMessage::ButtonPressed(btn));
-                    },
-                    ControllerMessage::UpdatedProcessorAvailable(processor) => {
-                        self.ui
-                            .ui_tx
-                            .send(UiMessage::UpdateProcessor(processor))
-                            .unwrap();
-                        //self.computer.step();
-                    },
-                    ControllerMessage::UpdatedDataAvailable(addr, data) => {
-                        // self.ui
-                        //     .ui_tx
-                        //     .send(UiMessage::UpdateData(data))
-                        //     .unwrap();
-                    },
+                self.handle_ui(message);
+            }
+            while let Some(event) = self.machine.try_event() {
+                self.handle_event(event);
+            }
+        }
 
-                    ControllerMessage::UpdatedStackAvailable(data) => {
-                        self.ui
-                            .ui_tx
-                            .send(UiMessage::UpdateStack(data))
-                            .unwrap();
-                    },
-                    ControllerMessage::UpdatedOutputAvailable(data) => {
-                            self.ui
-                            .ui_tx
-                            .send(UiMessage::UpdateOutput(data))
-                            .unwrap();
-                    },
-                };
+        // Save the final reports before the emulator thread goes away
+        let mut results = vec![];
+        if self.profile.is_some() || self.callgrind.is_some() {
+            results.push(match self.machine.request(Command::GetProfile) {
+                Ok(Reply::Profile(profiler)) => self.write_profile(&profiler),
+                Ok(_) => Err("the emulator sent no profile".to_string()),
+                Err(e) => Err(e),
+            });
+        }
+        if self.coverage.is_some() {
+            results.push(match self.machine.request(Command::GetCoverage) {
+                Ok(Reply::Coverage(coverage)) => self.write_coverage(&coverage),
+                Ok(_) => Err("the emulator sent no coverage".to_string()),
+                Err(e) => Err(e),
+            });
+        }
+        results.push(self.machine.shutdown().map(|_| String::new()));
+        for result in results {
+            if let Err(e) = result {
+                println!("Error: {}", e);
+            }
+        }
+    }
+}
+
+/// Status flags as NV-BDIZC letters, `.` for a clear flag, with the flags
+/// that differ from `previous` highlighted
+fn flags_text(flags: u8, previous: u8) -> StyledString {
+    let mut text = StyledString::new();
+    for (i, name) in FLAG_NAMES.chars().enumerate() {
+        let mask = 0x80 >> i;
+        let letter = if flags & mask != 0 { name.to_string() } else { ".".to_string() };
+        if (flags ^ previous) & mask != 0 {
+            text.append(StyledString::styled(letter, changed_style()));
+        } else {
+            text.append(StyledString::plain(letter));
+        }
+    }
+    text
+}
+
+/// How values that changed in the last step are shown
+fn changed_style() -> Style {
+    Style::from(Color::Light(BaseColor::Yellow)).combine(Effect::Reverse)
+}
+
+/// A register value, highlighted when it changed in the last step
+fn changed_text(text: String, changed: bool) -> StyledString {
+    if changed {
+        StyledString::styled(text, changed_style())
+    } else {
+        StyledString::plain(text)
+    }
+}
+
+/// Form for A, X, Y, SP and PC, sending the registers that changed
+fn register_editor(processor: &Processor, controller_tx: mpsc::Sender<ControllerMessage>) -> Dialog {
+    let current = [
+        ("A", Register::A, processor.acc as u16),
+        ("X", Register::X, processor.rx as u16),
+        ("Y", Register::Y, processor.ry as u16),
+        ("SP", Register::SP, processor.sp as u16),
+        ("PC", Register::PC, processor.pc),
+    ];
+    let fields: Vec<(&str, String)> = current.iter()
+        .map(|&(name, _, value)| (name, format!("{:02x}", value)))
+        .collect();
+    utils::form("Registers", &fields, move |_, values| {
+        let mut commands = vec![];
+        for (&(_, register, old), value) in current.iter().zip(values) {
+            let new = parse_address(value.trim(), None)?;
+            if new != old {
+                commands.push(Command::SetRegister(register, new));
             }
         }
+        for command in commands {
+            controller_tx.send(ControllerMessage::Command(command)).unwrap();
+        }
+        Ok(())
+    })
+}
+
+/// One checkbox per status flag, each toggle applied straight away. B and
+/// bit 5 are left out, as they only exist in the flags pushed on the stack.
+fn flag_editor(flags: u8, controller_tx: mpsc::Sender<ControllerMessage>) -> Dialog {
+    let names = [
+        ("N negative", 0x80), ("V overflow", 0x40), ("D decimal", 0x08),
+        ("I interrupt disable", 0x04), ("Z zero", 0x02), ("C carry", 0x01),
+    ];
+    let flags = Rc::new(Cell::new(flags));
+    let mut list = ListView::new();
+    for &(name, mask) in names.iter() {
+        let mut checkbox = Checkbox::new();
+        if flags.get() & mask != 0 {
+            checkbox = checkbox.checked();
+        }
+        let flags = flags.clone();
+        let controller_tx = controller_tx.clone();
+        list.add_child(name, checkbox.on_change(move |_, checked| {
+            let value = if checked { flags.get() | mask } else { flags.get() & !mask };
+            flags.set(value);
+            controller_tx.send(
+                ControllerMessage::Command(Command::SetRegister(Register::Flags, value as u16))
+            )
+            .unwrap();
+        }));
+    }
+    Dialog::around(list)
+        .title("Flags")
+        .dismiss_button("Close")
+}
+
+/// Form for writing, filling or copying memory. Ranges include their end.
+fn memory_editor(choice: &str, controller_tx: mpsc::Sender<ControllerMessage>) -> Dialog {
+    let send = move |command| {
+        controller_tx.send(ControllerMessage::Command(command)).unwrap();
+        Ok(())
+    };
+    match choice {
+        "Write bytes" => utils::form("Write bytes", &[("Address", String::new()), ("Bytes", String::new())], move |_, values| {
+            let addr = parse_address(values[0].trim(), None)?;
+            let bytes = values[1].split_whitespace().map(parse_byte).collect::<Result<Vec<u8>, String>>()?;
+            send(Command::WriteMemory(addr, bytes))
+        }),
+        "Fill range" => utils::form("Fill range", &[("Start", String::new()), ("End", String::new()), ("Value", String::new())], move |_, values| {
+            let range = parse_range(&values[0], &values[1])?;
+            send(Command::FillMemory(range, parse_byte(values[2].trim())?))
+        }),
+        _ => utils::form("Copy range", &[("Start", String::new()), ("End", String::new()), ("To", String::new())], move |_, values| {
+            let range = parse_range(&values[0], &values[1])?;
+            send(Command::CopyMemory(range, parse_address(values[2].trim(), None)?))
+        }),
+    }
+}
+
+/// Parse a byte in hexadecimal as `a9`, `0xa9` or `$a9`
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches('$');
+    u8::from_str_radix(digits, 16).map_err(|e| format!("invalid byte {}: {}", s, e))
+}
+
+/// Parse the inclusive range `start` to `end` given in hexadecimal
+fn parse_range(start: &str, end: &str) -> Result<Range<usize>, String> {
+    let start = parse_address(start.trim(), None)? as usize;
+    let end = parse_address(end.trim(), None)? as usize;
+    if end < start {
+        return Err(format!("range ends at {:#06x} before it starts at {:#06x}", end, start));
+    }
+    Ok(start..end + 1)
+}
+
+/// Parse an address written in hexadecimal as `0xa000` or `$a000`, or as
+/// a symbol from the debug info or bare hexadecimal like `a000`. Symbols
+/// win over bare hexadecimal, so write `$beef` when a label is `beef`.
+fn parse_address(s: &str, debug: Option<&DebugInfo>) -> Result<u16, String> {
+    let prefixed = s.strip_prefix("0x").or_else(|| s.strip_prefix('$'));
+    if prefixed.is_none() {
+        if let Some(addr) = debug.and_then(|d| d.resolve(s)) {
+            return Ok(addr);
+        }
+    }
+    let digits = prefixed.unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|e| format!("invalid address {}: {}", s, e))
+}
+
+/// Load the debug info given with `--dbg <file>`, if any
+fn load_debug_info(args: &[String]) -> Result<Option<DebugInfo>, String> {
+    match args.iter().position(|a| a == "--dbg") {
+        Some(i) => {
+            let path = args.get(i + 1).ok_or("--dbg needs a file")?;
+            DebugInfo::load(path).map(Some)
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parse the options following the image file name. Devices are given as
+/// e.g. `--acia a000:pty`, `--acia a000:tcp:6551`, `--riot 1780` or
+/// `--bank bfff:8000:16:8:firmware.bin`
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { debug: load_debug_info(args)?, ..Default::default() };
+    let debug = options.debug.as_ref();
+    // stdin and stdout belong to the UI unless it is not shown
+    let headless = args.iter().any(|a| a == "--bench" || a == "--script");
+    let devices = &mut options.devices;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--acia" => {
+                let spec = iter.next().ok_or("--acia needs <address>:<host>")?;
+                let mut parts = spec.splitn(2, ':');
+                let base = parse_address(parts.next().unwrap(), debug)?;
+                let host = AciaHost::parse(parts.next().unwrap_or("pty"))?;
+                if host == AciaHost::Stdio && !headless {
+                    return Err("--acia can only use stdio with --bench or --script, the UI needs the terminal".to_string());
+                }
+                devices.push(Box::new(Acia::new(base, &host)?));
+            },
+            "--riot" => {
+                let base = parse_address(iter.next().ok_or("--riot needs an address")?, debug)?;
+                devices.push(Box::new(Riot::new(base)));
+            },
+            "--bank" | "--rom-bank" => {
+                let spec = iter.next().ok_or("--bank needs <register>:<window>:<KiB>:<count>[:<file>]")?;
+                devices.push(Box::new(parse_bank(spec, arg == "--rom-bank", debug)?));
+            },
+            "--timer" => {
+                let base = parse_address(iter.next().ok_or("--timer needs an address")?, debug)?;
+                devices.push(Box::new(Riot::timer_only(base)));
+            },
+            "--dbg" => {
+                iter.next();
+            },
+            "--profile" => {
+                options.profile = Some(iter.next().ok_or("--profile needs a file")?.clone());
+            },
+            "--callgrind" => {
+                options.callgrind = Some(iter.next().ok_or("--callgrind needs a file")?.clone());
+            },
+            "--coverage" => {
+                options.coverage = Some(iter.next().ok_or("--coverage needs a file")?.clone());
+            },
+            "--listing" => {
+                let listing = iter.next().ok_or("--listing needs a file")?;
+                options.functest = FunctionalTest::load(listing)?;
+                options.listing = Some(listing.clone());
+            },
+            "--keys" => {
+                options.keys = KeyMap::load(iter.next().ok_or("--keys needs a file")?)?;
+            },
+            "--bench" => {
+                options.bench = true;
+            },
+            "--script" => {
+                options.script = Some(iter.next().ok_or("--script needs a file")?.clone());
+            },
+            "--clock" => {
+                options.clock = Some(parse_clock(iter.next().ok_or("--clock needs a rate in MHz or unlimited")?)?);
+            },
+            "--cycle-exact" => {
+                options.cycle_exact = true;
+            },
+            "--cpu" => {
+                options.variant = Some(parse_variant(iter.next().ok_or("--cpu needs 6502 or 65c02")?)?);
+            },
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Parse a clock rate given in MHz, like `1`, `1.79` or `unlimited`
+fn parse_clock(s: &str) -> Result<u64, String> {
+    if s == "unlimited" {
+        return Ok(0);
+    }
+    let mhz = s.parse::<f64>().map_err(|e| format!("invalid clock rate {}: {}", s, e))?;
+    if mhz.is_nan() || mhz <= 0.0 {
+        return Err(format!("invalid clock rate {}", s));
+    }
+    // 0 Hz would mean unlimited
+    let hz = (mhz * 1_000_000.0).round() as u64;
+    if hz == 0 {
+        return Err(format!("clock rate {} MHz is below 1 Hz", s));
+    }
+    Ok(hz)
+}
+
+/// Parse a processor name, `6502` or `65c02`
+fn parse_variant(s: &str) -> Result<Variant, String> {
+    match s.to_lowercase().as_str() {
+        "6502" => Ok(Variant::Nmos),
+        "65c02" => Ok(Variant::Cmos),
+        _ => Err(format!("unknown processor {}, expected 6502 or 65c02", s)),
+    }
+}
+
+/// Parse a banked window, `<register>:<window>:<KiB per bank>:<bank count>[:<file>]`
+fn parse_bank(spec: &str, rom: bool, debug: Option<&DebugInfo>) -> Result<BankSwitch, String> {
+    let parts: Vec<&str> = spec.splitn(5, ':').collect();
+    if parts.len() < 4 {
+        return Err(format!("invalid bank spec {}, expected <register>:<window>:<KiB>:<count>[:<file>]", spec));
     }
+    let register = parse_address(parts[0], debug)?;
+    let window = parse_address(parts[1], debug)?;
+    let kib = parts[2].parse::<usize>().map_err(|e| format!("invalid bank size {}: {}", parts[2], e))?;
+    let count = parts[3].parse::<usize>().map_err(|e| format!("invalid bank count {}: {}", parts[3], e))?;
+    let contents = match parts.get(4) {
+        Some(file) => fs::read(file).map_err(|e| format!("could not read {}: {}", file, e))?,
+        None => vec![],
+    };
+    BankSwitch::new(register, window, kib * 1024, count, &contents, rom)
+}
+
+/// Run the image headless until it traps and report the emulated speed
+fn bench(filename: &str, options: Options) -> Result<(), String> {
+    let data = fs::read(filename).map_err(|e| format!("could not read {}: {}", filename, e))?;
+    let mut computer = Computer::headless(data);
+    for device in options.devices {
+        computer.add_device(device);
+    }
+    if let Some(variant) = options.variant {
+        computer.set_variant(variant);
+    }
+    computer.set_cycle_exact(options.cycle_exact);
+
+    let start = Instant::now();
+    let instructions = computer.run_to_trap(BENCH_MAX_CYCLES);
+    let seconds = start.elapsed().as_secs_f64();
+    let processor = computer.processor().clone();
+    println!(
+        "Stopped at {:#06x} after {} cycles and {} instructions in {:.2}s",
+        processor.pc, processor.clock, instructions, seconds
+    );
+    println!(
+        "{:.2} MHz, {:.2} million instructions per second",
+        processor.clock as f64 / seconds / 1_000_000.0,
+        instructions as f64 / seconds / 1_000_000.0
+    );
+    if let Some(test) = options.functest {
+        let number = computer.read_byte(test.test_case());
+        if processor.clock >= BENCH_MAX_CYCLES {
+            return Err(format!("functional test still in test {} ({}) without trapping", number, test.section(number)));
+        }
+        match test.outcome(number, processor.pc) {
+            Outcome::Passed => println!("Functional test passed"),
+            Outcome::Failed(number, pc) => {
+                return Err(format!("functional test failed in test {} ({}) at {:#06x}", number, test.section(number), pc));
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Assemble a source file into a 64 KiB image, for
+/// `asm <source> <image> [--org <address>]`
+fn assemble_file(args: &[String]) -> Result<(), String> {
+    let usage = "usage: asm <source> <image> [--org <address>]";
+    let source = args.first().ok_or(usage)?;
+    let output = args.get(1).ok_or(usage)?;
+    let mut origin = computer::START_PC;
+    let mut iter = args[2..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--org" => origin = parse_address(iter.next().ok_or("--org needs an address")?, None)?,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    let text = fs::read_to_string(source).map_err(|e| format!("could not read {}: {}", source, e))?;
+    let assembly = assembler::assemble(&text, origin).map_err(|e| format!("{}: {}", source, e))?;
+    fs::write(output, assembly.image(0)).map_err(|e| format!("could not write {}: {}", output, e))?;
+    for (start, bytes) in &assembly.segments {
+        println!("{:#06x}-{:#06x} {} bytes", start, *start as usize + bytes.len() - 1, bytes.len());
+    }
+    Ok(())
+}
+
+/// Run a script against the image instead of the UI
+#[cfg(feature = "scripting")]
+fn run_script(filename: &str, script: &str, options: Options) -> Result<(), String> {
+    let data = fs::read(filename).map_err(|e| format!("could not read {}: {}", filename, e))?;
+    let mut computer = Computer::headless(data);
+    for device in options.devices {
+        computer.add_device(device);
+    }
+    if let Some(variant) = options.variant {
+        computer.set_variant(variant);
+    }
+    computer.set_cycle_exact(options.cycle_exact);
+    rust_test::script::run(computer, script)
+}
+
+#[cfg(not(feature = "scripting"))]
+fn run_script(_filename: &str, _script: &str, _options: Options) -> Result<(), String> {
+    Err("built without scripting, enable the scripting feature".to_string())
 }
 
 fn main() {
@@ -402,11 +1575,68 @@ fn main() {
     if args.len() < 2 {
         panic!("Please enter a filename to run");
     }
+    if args[1] == "asm" {
+        if let Err(e) = assemble_file(&args[2..]) {
+            println!("Error: {}", e);
+        }
+        return;
+    }
     let filename = &args[1];
 
-    let controller = Controller::new(filename.to_string());
+    let options = match parse_options(&args[2..]) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        },
+    };
+
+    if options.bench {
+        if let Err(e) = bench(filename, options) {
+            println!("Error: {}", e);
+        }
+        return;
+    }
+
+    if let Some(script) = options.script.clone() {
+        if let Err(e) = run_script(filename, &script, options) {
+            println!("Error: {}", e);
+        }
+        return;
+    }
+
+    let controller = Controller::new(filename.to_string(), options);
     match controller {
         Ok(mut controller) => controller.run(),
         Err(e) => println!("Error: {}", e),
     };
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixed_addresses_are_never_symbols() {
+        let debug = DebugInfo::load(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/hello.dbg")).unwrap();
+        let debug = Some(&debug);
+        assert_eq!(parse_address("beef", debug), Ok(0x040c));
+        assert_eq!(parse_address("$beef", debug), Ok(0xbeef));
+        assert_eq!(parse_address("0xbeef", debug), Ok(0xbeef));
+        assert_eq!(parse_address("print::@loop", debug), Ok(0x0408));
+        assert_eq!(parse_address("cafe", debug), Ok(0xcafe));
+        assert_eq!(parse_address("beef", None), Ok(0xbeef));
+        assert!(parse_address("$start", debug).is_err());
+        assert!(parse_address("nowhere", debug).is_err());
+    }
+
+    #[test]
+    fn clock_rates() {
+        assert_eq!(parse_clock("1.79"), Ok(1_790_000));
+        assert_eq!(parse_clock("unlimited"), Ok(0));
+        assert_eq!(parse_clock("0.000001"), Ok(1));
+        assert!(parse_clock("0.0000004").is_err());
+        assert!(parse_clock("0").is_err());
+        assert!(parse_clock("-1").is_err());
+        assert!(parse_clock("fast").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use cursive::theme::{BaseColor, Color, Effect, Style};
+use cursive::utils::markup::StyledString;
+
+/// Bytes per line of a memory window
+const LINE: usize = 16;
+
+/// A view onto part of memory, either kept centred on the program counter
+/// or pinned to an address
+pub struct MemoryWindow {
+    /// Address of the first line, a multiple of `LINE`
+    top: usize,
+    follow_pc: bool,
+    /// Start and length of the last search match
+    found: Option<(usize, usize)>,
+}
+
+impl MemoryWindow {
+    pub fn following_pc() -> MemoryWindow {
+        MemoryWindow { top: 0, follow_pc: true, found: None }
+    }
+
+    pub fn pinned(addr: u16) -> MemoryWindow {
+        let mut window = MemoryWindow::following_pc();
+        window.goto(addr);
+        window
+    }
+
+    pub fn follows_pc(&self) -> bool {
+        self.follow_pc
+    }
+
+    /// Go back to following the program counter
+    pub fn follow_pc(&mut self) {
+        self.follow_pc = true;
+    }
+
+    /// Pin the window with `addr` on its first line
+    pub fn goto(&mut self, addr: u16) {
+        self.follow_pc = false;
+        self.top = addr as usize / LINE * LINE;
+    }
+
+    /// Move by `by` lines from where a window `lines` tall is showing,
+    /// pinning it where it ends up
+    pub fn scroll(&mut self, by: isize, lines: usize, pc: u16) {
+        let top = self.first_line(lines, pc) as isize + by * LINE as isize;
+        self.follow_pc = false;
+        self.top = top.max(0).min((0x10000 - LINE) as isize) as usize;
+    }
+
+    /// Where the last search matched
+    pub fn found(&self) -> Option<usize> {
+        self.found.map(|(start, _)| start)
+    }
+
+    /// Show a search match, `len` bytes from `start`
+    pub fn show_match(&mut self, start: usize, len: usize) {
+        self.goto(start as u16);
+        self.found = Some((start, len));
+    }
+
+    /// First line to show, `lines` tall, with `pc` in the middle when
+    /// following it. Stays inside the address space at both ends.
+    fn first_line(&self, lines: usize, pc: u16) -> usize {
+        let top = if self.follow_pc {
+            (pc as usize / LINE).saturating_sub(lines / 2) * LINE
+        } else {
+            self.top
+        };
+        top.min(0x10000usize.saturating_sub(lines * LINE))
+    }
+
+    /// A header line and `lines` lines of hex and ASCII. `style` picks the
+    /// colour of each byte that is neither the program counter nor part of
+    /// a search match.
+    pub fn render(&self, title: &str, data: &[u8], lines: usize, pc: u16, style: &dyn Fn(u16) -> Option<Style>) -> StyledString {
+        let top = self.first_line(lines, pc);
+        let mode = if self.follow_pc { "following PC".to_string() } else { format!("at {:#06x}", top) };
+        let mut text = StyledString::styled(format!("{} {}\n", title, mode), Effect::Bold);
+        let current = Style::from(Color::Dark(BaseColor::Red)).combine(Effect::Bold);
+        for line in 0..lines {
+            let start = top + line * LINE;
+            if start + LINE > data.len() {
+                break;
+            }
+            text.append(StyledString::plain(format!("{:06x}  ", start)));
+            for (i, item) in data[start..start + LINE].iter().enumerate() {
+                let addr = start + i;
+                let byte = if i % 4 == 0 { format!("  {:02x}", item) } else { format!(" {:02x}", item) };
+                if addr == pc as usize {
+                    text.append(StyledString::styled(byte, current));
+                } else if self.found.is_some_and(|(found, len)| addr >= found && addr < found + len) {
+                    text.append(StyledString::styled(byte, Effect::Reverse));
+                } else if let Some(style) = style(addr as u16) {
+                    text.append(StyledString::styled(byte, style));
+                } else {
+                    text.append(StyledString::plain(byte));
+                }
+            }
+            let ascii: String = data[start..start + LINE].iter()
+                .map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' })
+                .collect();
+            text.append(StyledString::plain(format!("  {}\n", ascii)));
+        }
+        text
+    }
+}
+
+/// Next occurrence of `pattern` starting at `from`, wrapping around the
+/// end of memory
+pub fn find(data: &[u8], pattern: &[u8], from: usize) -> Option<usize> {
+    if pattern.is_empty() || pattern.len() > data.len() {
+        return None;
+    }
+    let last = data.len() - pattern.len();
+    let from = from.min(last + 1);
+    (from..=last).chain(0..from)
+        .find(|&start| &data[start..start + pattern.len()] == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_wraps_around() {
+        let mut data = vec![0; 0x100];
+        data[0x10..0x12].copy_from_slice(&[0xa9, 0x00]);
+        data[0x80..0x82].copy_from_slice(&[0xa9, 0x00]);
+        assert_eq!(find(&data, &[0xa9, 0x00], 0), Some(0x10));
+        assert_eq!(find(&data, &[0xa9, 0x00], 0x10), Some(0x10));
+        assert_eq!(find(&data, &[0xa9, 0x00], 0x11), Some(0x80));
+        assert_eq!(find(&data, &[0xa9, 0x00], 0x81), Some(0x10));
+        assert_eq!(find(&data, &[0xa9, 0x00], 0x1000), Some(0x10));
+        assert_eq!(find(&data, &[0xa9, 0x01], 0), None);
+        assert_eq!(find(&data, &[], 0), None);
+        assert_eq!(find(&[1, 2], &[1, 2, 3], 0), None);
+        // Matches do not run across the end of memory into the start
+        data[0xff] = 0xea;
+        data[0] = 0x60;
+        assert_eq!(find(&data, &[0xea, 0x60], 0), None);
+        assert_eq!(find(&data, &[0xea], 0x100), Some(0xff));
+    }
+
+    #[test]
+    fn first_line_centres_pc_inside_memory() {
+        let window = MemoryWindow::following_pc();
+        assert_eq!(window.first_line(8, 0x1234), 0x11f0);
+        assert_eq!(window.first_line(8, 0x0020), 0);
+        assert_eq!(window.first_line(8, 0xfff0), 0xff80);
+
+        let mut window = MemoryWindow::pinned(0x1234);
+        assert!(!window.follows_pc());
+        assert_eq!(window.first_line(8, 0), 0x1230);
+        window.goto(0xfff0);
+        assert_eq!(window.first_line(8, 0), 0xff80);
+        window.follow_pc();
+        assert_eq!(window.first_line(8, 0x1234), 0x11f0);
+    }
+
+    #[test]
+    fn scroll_pins_the_window() {
+        let mut window = MemoryWindow::following_pc();
+        window.scroll(2, 8, 0x1234);
+        assert!(!window.follows_pc());
+        assert_eq!(window.first_line(8, 0), 0x1210);
+        window.scroll(-0x200, 8, 0);
+        assert_eq!(window.first_line(8, 0), 0);
+        window.scroll(0x2000, 8, 0);
+        assert_eq!(window.first_line(8, 0), 0xff80);
+
+        window.show_match(0x4321, 3);
+        assert_eq!(window.found(), Some(0x4321));
+        assert_eq!(window.first_line(8, 0), 0x4320);
+    }
+}
@@ -0,0 +1,313 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Map, NativeCallContext, INT};
+
+use crate::computer::Computer;
+use crate::computer::acia::{Acia, SerialPort};
+use crate::computer::protocol::Register;
+
+/// Cycles `run()` gives up after when the script does not say
+const RUN_CYCLES: u64 = 1_000_000_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The machine a script drives, and the callbacks it registered
+struct Session {
+    computer: Computer,
+    on_break: Option<FnPtr>,
+    on_write: HashMap<u16, FnPtr>,
+    serial: Option<SerialPort>,
+}
+
+type Shared = Rc<RefCell<Session>>;
+
+/// Run the Rhai script at `path` against `computer`. The script drives the
+/// machine through the functions listed in the README, without the UI.
+pub fn run(computer: Computer, path: &str) -> Result<(), String> {
+    engine(computer).run_file(PathBuf::from(path)).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// An engine whose script functions drive `computer`
+fn engine(computer: Computer) -> Engine {
+    let session = Rc::new(RefCell::new(Session { computer, on_break: None, on_write: HashMap::new(), serial: None }));
+    let mut engine = Engine::new();
+    register(&mut engine, &session);
+    engine
+}
+
+fn address(value: INT) -> ScriptResult<u16> {
+    if !(0..=0xffff).contains(&value) {
+        return Err(format!("address {:#x} out of range", value).into());
+    }
+    Ok(value as u16)
+}
+
+fn byte(value: INT) -> ScriptResult<u8> {
+    if !(-128..=0xff).contains(&value) {
+        return Err(format!("{:#x} does not fit in a byte", value).into());
+    }
+    Ok(value as u8)
+}
+
+fn register_named(name: &str) -> ScriptResult<Register> {
+    match name.to_lowercase().as_str() {
+        "a" => Ok(Register::A),
+        "x" => Ok(Register::X),
+        "y" => Ok(Register::Y),
+        "sp" => Ok(Register::SP),
+        "pc" => Ok(Register::PC),
+        "p" | "flags" => Ok(Register::Flags),
+        _ => Err(format!("unknown register {}, expected a, x, y, sp, pc or p", name).into()),
+    }
+}
+
+/// Run up to `count` instructions, stopping early at breakpoints when
+/// `stop` is set and at traps, calling the write and breakpoint callbacks
+/// as they come. Returns the program counter it stopped at.
+fn execute(context: &NativeCallContext, session: &Shared, count: u64, max_cycles: u64, stop: bool) -> ScriptResult<INT> {
+    let end = session.borrow().computer.processor().clock.saturating_add(max_cycles);
+    for _ in 0..count {
+        // The callbacks may use the machine, so it must not stay borrowed
+        let (pc, trapped, writes, clock) = {
+            let mut session = session.borrow_mut();
+            let before = session.computer.processor().pc;
+            session.computer.run_one();
+            let (pc, clock) = (session.computer.processor().pc, session.computer.processor().clock);
+            (pc, pc == before, session.computer.take_writes(), clock)
+        };
+        deliver_writes(context, session, writes)?;
+        if stop && session.borrow().computer.is_breakpoint(pc) {
+            let callback = session.borrow().on_break.clone();
+            let carry_on = match callback {
+                Some(callback) => callback.call_within_context::<Dynamic>(context, (pc as INT,))?.as_bool().unwrap_or(false),
+                None => false,
+            };
+            if !carry_on {
+                return Ok(pc as INT);
+            }
+        }
+        if (stop && trapped) || clock >= end {
+            return Ok(pc as INT);
+        }
+    }
+    Ok(session.borrow().computer.processor().pc as INT)
+}
+
+/// Call the `on_write` callbacks for `writes`
+fn deliver_writes(context: &NativeCallContext, session: &Shared, writes: Vec<(u16, u8)>) -> ScriptResult<()> {
+    for (addr, value) in writes {
+        let callback = session.borrow().on_write.get(&addr).cloned();
+        if let Some(callback) = callback {
+            // What the callback returns does not matter
+            let _ = callback.call_within_context::<Dynamic>(context, (addr as INT, value as INT))?;
+        }
+    }
+    Ok(())
+}
+
+fn register(engine: &mut Engine, session: &Shared) {
+    let s = session.clone();
+    engine.register_fn("load", move |path: &str| -> ScriptResult<()> {
+        let data = fs::read(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        s.borrow_mut().computer.load(0, &data).map_err(|e| e.into())
+    });
+    let s = session.clone();
+    engine.register_fn("load", move |path: &str, addr: INT| -> ScriptResult<()> {
+        let data = fs::read(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        s.borrow_mut().computer.load(address(addr)?, &data).map_err(|e| e.into())
+    });
+    let s = session.clone();
+    engine.register_fn("reset", move || s.borrow_mut().computer.reset());
+
+    let s = session.clone();
+    engine.register_fn("step", move |context: NativeCallContext| execute(&context, &s, 1, u64::MAX, false));
+    let s = session.clone();
+    engine.register_fn("step", move |context: NativeCallContext, count: INT| {
+        execute(&context, &s, count.max(0) as u64, u64::MAX, false)
+    });
+    let s = session.clone();
+    engine.register_fn("run", move |context: NativeCallContext| execute(&context, &s, u64::MAX, RUN_CYCLES, true));
+    let s = session.clone();
+    engine.register_fn("run", move |context: NativeCallContext, cycles: INT| {
+        execute(&context, &s, u64::MAX, cycles.max(0) as u64, true)
+    });
+
+    let s = session.clone();
+    engine.register_fn("tick", move |context: NativeCallContext| -> ScriptResult<Map> {
+        let (access, writes) = {
+            let mut session = s.borrow_mut();
+            (session.computer.tick(), session.computer.take_writes())
+        };
+        deliver_writes(&context, &s, writes)?;
+        let mut map = Map::new();
+        map.insert("address".into(), (access.address as INT).into());
+        map.insert("value".into(), (access.value as INT).into());
+        map.insert("write".into(), access.write.into());
+        Ok(map)
+    });
+    let s = session.clone();
+    engine.register_fn("cycle_exact", move |on: bool| s.borrow_mut().computer.set_cycle_exact(on));
+
+    let s = session.clone();
+    engine.register_fn("read", move |addr: INT| -> ScriptResult<INT> {
+        Ok(s.borrow_mut().computer.read_byte(address(addr)?) as INT)
+    });
+    let s = session.clone();
+    engine.register_fn("read_word", move |addr: INT| -> ScriptResult<INT> {
+        let addr = address(addr)?;
+        let mut session = s.borrow_mut();
+        let low = session.computer.read_byte(addr) as INT;
+        Ok(low | (session.computer.read_byte(addr.wrapping_add(1)) as INT) << 8)
+    });
+    let s = session.clone();
+    engine.register_fn("write", move |addr: INT, value: INT| -> ScriptResult<()> {
+        s.borrow_mut().computer.write_byte(address(addr)?, byte(value)?);
+        Ok(())
+    });
+
+    let s = session.clone();
+    engine.register_fn("reg", move |name: &str| -> ScriptResult<INT> {
+        let session = s.borrow();
+        let processor = session.computer.processor();
+        Ok(match register_named(name)? {
+            Register::A => processor.acc as INT,
+            Register::X => processor.rx as INT,
+            Register::Y => processor.ry as INT,
+            Register::SP => processor.sp as INT,
+            Register::PC => processor.pc as INT,
+            Register::Flags => processor.flags as INT,
+        })
+    });
+    let s = session.clone();
+    engine.register_fn("set_reg", move |name: &str, value: INT| -> ScriptResult<()> {
+        if !(0..=0xffff).contains(&value) {
+            return Err(format!("{:#x} out of range", value).into());
+        }
+        s.borrow_mut().computer.set_register(register_named(name)?, value as u16).map_err(|e| e.into())
+    });
+    let s = session.clone();
+    engine.register_fn("cycles", move || s.borrow().computer.processor().clock as INT);
+
+    let s = session.clone();
+    engine.register_fn("break_at", move |addr: INT| -> ScriptResult<()> {
+        s.borrow_mut().computer.set_breakpoint(address(addr)?, true);
+        Ok(())
+    });
+    let s = session.clone();
+    engine.register_fn("clear_break", move |addr: INT| -> ScriptResult<()> {
+        s.borrow_mut().computer.set_breakpoint(address(addr)?, false);
+        Ok(())
+    });
+    let s = session.clone();
+    engine.register_fn("on_break", move |callback: FnPtr| s.borrow_mut().on_break = Some(callback));
+    let s = session.clone();
+    engine.register_fn("on_write", move |addr: INT, callback: FnPtr| -> ScriptResult<()> {
+        let addr = address(addr)?;
+        let mut session = s.borrow_mut();
+        session.computer.watch_writes(addr, true);
+        session.on_write.insert(addr, callback);
+        Ok(())
+    });
+
+    let s = session.clone();
+    engine.register_fn("serial", move |addr: INT| -> ScriptResult<()> {
+        let (acia, port) = Acia::with_port(address(addr)?);
+        let mut session = s.borrow_mut();
+        session.computer.add_device(Box::new(acia));
+        session.serial = Some(port);
+        Ok(())
+    });
+    let s = session.clone();
+    engine.register_fn("send_serial", move |text: &str| -> ScriptResult<()> {
+        match &s.borrow().serial {
+            Some(port) => port.send(text.as_bytes()).map_err(|e| e.into()),
+            None => Err("no serial port, attach one with serial(address)".into()),
+        }
+    });
+    let s = session.clone();
+    engine.register_fn("serial_output", move || -> ScriptResult<String> {
+        match &s.borrow().serial {
+            Some(port) => Ok(String::from_utf8_lossy(&port.take_output()).into_owned()),
+            None => Err("no serial port, attach one with serial(address)".into()),
+        }
+    });
+
+    engine.register_fn("assert", |condition: bool, message: &str| -> ScriptResult<()> {
+        if condition { Ok(()) } else { Err(format!("assertion failed: {}", message).into()) }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computer::{assembler, START_PC};
+
+    /// Counts X down from 3, storing each value at $0200
+    const PROGRAM: &str = "
+        LDX #3
+LOOP:   DEX
+        STX $0200
+        BNE LOOP
+DONE:   JMP DONE
+";
+
+    fn script(text: &str) -> Result<(), String> {
+        let image = assembler::assemble(PROGRAM, START_PC).unwrap().image(0);
+        engine(Computer::headless(image)).run(text).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn executes_the_program() {
+        script(r#"
+            assert(reg("pc") == 0x400, "starts at $0400");
+            assert(step() == 0x402, "LDX is two bytes");
+            assert(reg("x") == 3, "LDX loads X");
+            assert(step(3) == 0x402, "round the loop once");
+            assert(run() == 0x408, "run stops at the trap");
+            assert(reg("x") == 0 && read(0x200) == 0, "counted down");
+            assert(cycles() == 2 + 3 * 9 - 1 + 3, "cycles");
+            write(0x300, -1);
+            write(0x301, 0x12);
+            assert(read_word(0x300) == 0x12ff, "little endian");
+            set_reg("pc", 0x400);
+            set_reg("x", 0x55);
+            assert(reg("x") == 0x55 && step() == 0x402, "registers set");
+        "#).unwrap();
+    }
+
+    #[test]
+    fn calls_back_on_writes_and_breakpoints() {
+        script(r#"
+            let written = [];
+            on_write(0x200, |addr, value| written.push(value));
+            let breaks = 0;
+            break_at(0x406);
+            // Keep going at the first breakpoint, stop at the second
+            on_break(|pc| { breaks += 1; breaks < 2 });
+            assert(run() == 0x406, "stopped at the breakpoint");
+            assert(breaks == 2, "both breakpoints called back");
+            assert(written == [2, 1], "both writes called back");
+            clear_break(0x406);
+            assert(run() == 0x408, "no breakpoint after clearing it");
+            assert(written == [2, 1, 0], "the last write");
+        "#).unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let error = |text| script(text).err().unwrap();
+        assert!(error("read(0x10000);").contains("address 0x10000 out of range"));
+        assert!(error("break_at(-1);").contains("out of range"));
+        assert!(error("write(0x200, 0x100);").contains("0x100 does not fit in a byte"));
+        assert!(error("write(0x200, -129);").contains("does not fit in a byte"));
+        assert!(error(r#"set_reg("pc", 0x10000);"#).contains("0x10000 out of range"));
+        assert!(error(r#"set_reg("a", 0x100);"#).contains("does not fit in A"));
+        assert!(error(r#"reg("q");"#).contains("unknown register q"));
+        assert!(error(r#"assert(false, "on purpose");"#).contains("assertion failed: on purpose"));
+        script("write(0x200, -128); write(0x201, 0xff); assert(read(0x200) == 0x80, \"signed bytes\");").unwrap();
+    }
+}
@@ -1,20 +1,58 @@
+use cursive::Cursive;
 use cursive::views::*;
 use cursive::view::*;
 
+/// Format `data` as lines of 16 bytes, labelled with addresses starting at `start`
+pub fn hex_dump(start: u16, data: &[u8]) -> String {
+    let mut text = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        text.push_str(&format!("{:#06x} ", start as usize + i * 16));
+        for (j, item) in line.iter().enumerate() {
+            if j % 4 == 0 {
+                text.push_str(&format!("  {:02x}", item));
+            } else {
+                text.push_str(&format!(" {:02x}", item));
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
 
-pub fn layout() -> cursive::views::LinearLayout {
-    let mut mem_view = LinearLayout::vertical();
-    let mut cnt = 0;
-
-    for i in 0..32 {
-        let mut h = LinearLayout::horizontal();
-        h.add_child(
-            TextView::new("aa").with_id(format!("addr-{}", cnt).as_str())
+/// A dialog with a labelled `EditView` for each field. `on_ok` gets the
+/// field contents in order and closes the dialog unless it returns an error,
+/// which is shown instead.
+pub fn form<F>(title: &str, fields: &[(&str, String)], on_ok: F) -> Dialog
+where
+    F: Fn(&mut Cursive, &[String]) -> Result<(), String> + 'static,
+{
+    let mut list = ListView::new();
+    for (i, (label, value)) in fields.iter().enumerate() {
+        list.add_child(
+            label,
+            EditView::new().content(value.clone()).with_id(format!("form-{}", i).as_str()).fixed_width(24)
         );
-        mem_view.add_child(h);
-        cnt += 1;
     }
-    cnt = 0;
+    let count = fields.len();
+    Dialog::around(list)
+        .title(title)
+        .button("Ok", move |s| {
+            let values: Vec<String> = (0..count).map(|i| {
+                s.call_on_id(format!("form-{}", i).as_str(), |view: &mut EditView| view.get_content().to_string())
+                    .unwrap_or_default()
+            }).collect();
+            match on_ok(s, &values) {
+                Ok(()) => {
+                    s.pop_layer();
+                },
+                Err(e) => s.add_layer(Dialog::info(e)),
+            }
+        })
+        .dismiss_button("Cancel")
+}
+
+pub fn layout() -> cursive::views::LinearLayout {
+    let mut cnt = 0;
 
     let mut st = LinearLayout::vertical();
     for i in 0..16 {
@@ -30,9 +68,12 @@ pub fn layout() -> cursive::views::LinearLayout {
             LinearLayout::vertical()
             .child(
                 Dialog::around(
-                    mem_view
+                    TextView::new("").with_id("memory")
                 ).title("Memory")
             )
+            .child(
+                TextView::new("").with_id("banks")
+            )
             // .child(
             //     Dialog::around(
             //         TextView::new("OUTPUT").with_id("output")
@@ -134,7 +175,7 @@ pub fn layout() -> cursive::views::LinearLayout {
                         TextView::new("Speed")
                     )
                     .child(
-                        TextView::new("").with_id("speed").fixed_width(8)
+                        TextView::new("").with_id("speed").fixed_width(22)
                     )
                 )
                 
@@ -157,6 +198,25 @@ pub fn layout() -> cursive::views::LinearLayout {
             )
             
         )
+
+        .child(
+            LinearLayout::vertical()
+            .child(
+                Dialog::around(
+                    TextView::new("").with_id("disasm")
+                ).title("Disassembly").fixed_width(48)
+            )
+            .child(
+                Dialog::around(
+                    TextView::new("").with_id("source")
+                ).title("Source").fixed_width(48)
+            )
+            .child(
+                Dialog::around(
+                    TextView::new("").with_id("callstack")
+                ).title("Call stack").fixed_width(48)
+            )
+        )
             
 }
 
@@ -0,0 +1,102 @@
+//! The core for JavaScript, through wasm-bindgen, so documentation pages
+//! can run 6502 examples in the browser. Built for
+//! `wasm32-unknown-unknown` with `--no-default-features --features wasm`.
+//!
+//! There are no threads there, so the page drives the computer directly
+//! instead of through `step()` and the protocol.
+
+use wasm_bindgen::prelude::*;
+
+use crate::computer::{Computer, Variant};
+
+#[wasm_bindgen]
+pub struct Emulator {
+    computer: Computer,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// A 6502 with 64 KiB of zeroed memory, or a 65C02 when `cmos` is set
+    #[wasm_bindgen(constructor)]
+    pub fn new(cmos: bool) -> Emulator {
+        let mut computer = Computer::headless(vec![0; 0x10000]);
+        computer.set_variant(if cmos { Variant::Cmos } else { Variant::Nmos });
+        Emulator { computer }
+    }
+
+    /// Copy `bytes` into memory at `addr`
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) -> Result<(), String> {
+        self.computer.load(addr, bytes)
+    }
+
+    /// Put the processor back in its power on state, with PC at `START_PC`
+    pub fn reset(&mut self) {
+        self.computer.reset();
+    }
+
+    /// Run one instruction and return the cycles it took
+    pub fn step(&mut self) -> u32 {
+        self.computer.run_one() as u32
+    }
+
+    /// Run whole instructions until at least `cycles` have passed, and
+    /// return how many did
+    #[wasm_bindgen(js_name = runCycles)]
+    pub fn run_cycles(&mut self, cycles: u32) -> u32 {
+        let mut ran = 0;
+        while ran < cycles {
+            match self.computer.run_one() as u32 {
+                0 => break,
+                taken => ran += taken,
+            }
+        }
+        ran
+    }
+
+    /// `len` bytes of memory from `addr`, as a `Uint8Array`
+    #[wasm_bindgen(js_name = readMemory)]
+    pub fn read_memory(&self, addr: u16, len: u32) -> Result<Vec<u8>, String> {
+        let start = addr as usize;
+        match start.checked_add(len as usize) {
+            Some(end) if end <= 0x10000 => Ok(self.computer.memory()[start..end].to_vec()),
+            _ => Err(format!("{} bytes do not fit at {:#06x}", len, addr)),
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn a(&self) -> u8 {
+        self.computer.processor().acc
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn x(&self) -> u8 {
+        self.computer.processor().rx
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn y(&self) -> u8 {
+        self.computer.processor().ry
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sp(&self) -> u8 {
+        self.computer.processor().sp
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn pc(&self) -> u16 {
+        self.computer.processor().pc
+    }
+
+    /// P, without B and bit 5, which only exist on the stack
+    #[wasm_bindgen(getter)]
+    pub fn flags(&self) -> u8 {
+        self.computer.processor().flags
+    }
+
+    /// Cycles since power on, as a number rather than a BigInt
+    #[wasm_bindgen(getter)]
+    pub fn clock(&self) -> f64 {
+        self.computer.processor().clock as f64
+    }
+}
@@ -0,0 +1,147 @@
+/*
+ * Exercises the C API in include/emu6502.h. Build and run it from the
+ * top of the repository with
+ *
+ *   cargo build --release --no-default-features --features ffi
+ *   cc -Wall -Iinclude tests/c/emu6502_test.c -Ltarget/release -lrust_test -o target/emu6502_test
+ *   LD_LIBRARY_PATH=target/release target/emu6502_test 6502_functional_test.bin
+ *
+ * The image argument is optional. When given, it is run until it traps
+ * and must trap at the functional test's success address.
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "emu6502.h"
+
+/* Where 6502_functional_test.bin traps when every test passed */
+#define FUNCTIONAL_TEST_PASSED 0x3469
+
+static int failures = 0;
+
+#define CHECK(condition) \
+    do { \
+        if (!(condition)) { \
+            fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #condition); \
+            failures++; \
+        } \
+    } while (0)
+
+/* A port at $D000-$D00F that reads back as $55 and records writes */
+struct port {
+    uint16_t last_addr;
+    uint8_t last_value;
+    int writes;
+};
+
+static uint8_t port_read(void *user, uint16_t addr) {
+    (void)user;
+    (void)addr;
+    return 0x55;
+}
+
+static void port_write(void *user, uint16_t addr, uint8_t value) {
+    struct port *port = user;
+    port->last_addr = addr;
+    port->last_value = value;
+    port->writes++;
+}
+
+/*
+ *         LDA #$2A
+ *         STA $D000
+ *         LDA $D001
+ *         STA $0200
+ *         LDX #3
+ * LOOP:   DEX
+ *         BNE LOOP
+ * DONE:   JMP DONE
+ */
+static const uint8_t program[] = {
+    0xa9, 0x2a, 0x8d, 0x00, 0xd0, 0xad, 0x01, 0xd0, 0x8d, 0x00, 0x02,
+    0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x4c, 0x10, 0x04,
+};
+
+static void test_program(void) {
+    struct port port = {0};
+    Emu6502Registers registers;
+    uint8_t byte = 0;
+    Emu6502 *emu = emu6502_new(false);
+
+    CHECK(emu6502_write_memory(emu, 0x0400, program, sizeof program) == 0);
+    CHECK(emu6502_map(emu, 0xd000, 0xd00f, port_read, port_write, &port) == 0);
+
+    CHECK(emu6502_step(emu) == 2);
+    emu6502_get_registers(emu, &registers);
+    CHECK(registers.a == 0x2a);
+    CHECK(registers.pc == 0x0402);
+
+    CHECK(emu6502_run(emu, 1000) < 1000);
+    emu6502_get_registers(emu, &registers);
+    CHECK(registers.pc == 0x0410);
+    CHECK(registers.x == 0);
+    CHECK(port.writes == 1 && port.last_addr == 0xd000 && port.last_value == 0x2a);
+    CHECK(emu6502_read_memory(emu, 0x0200, &byte, 1) == 0);
+    CHECK(byte == 0x55);
+
+    emu6502_reset(emu);
+    emu6502_get_registers(emu, &registers);
+    CHECK(registers.pc == 0x0400);
+
+    registers.a = 0x12;
+    registers.pc = 0x040b;
+    emu6502_set_registers(emu, &registers);
+    CHECK(emu6502_step(emu) == 2);
+    emu6502_get_registers(emu, &registers);
+    CHECK(registers.a == 0x12 && registers.x == 3);
+
+    emu6502_free(emu);
+}
+
+static void test_errors(void) {
+    uint8_t bytes[2] = {0};
+    Emu6502 *emu = emu6502_new(true);
+
+    CHECK(emu6502_write_memory(emu, 0xffff, bytes, 2) == -1);
+    CHECK(strlen(emu6502_last_error(emu)) > 0);
+    CHECK(emu6502_read_memory(emu, 0xffff, bytes, 2) == -1);
+    CHECK(emu6502_load(emu, "no such file", 0) == -1);
+    CHECK(strstr(emu6502_last_error(emu), "no such file") != NULL);
+    CHECK(emu6502_map(emu, 0xd000, 0xd00f, NULL, port_write, NULL) == -1);
+
+    emu6502_free(emu);
+    emu6502_free(NULL);
+}
+
+static void test_image(const char *path) {
+    Emu6502Registers registers;
+    Emu6502 *emu = emu6502_new(false);
+
+    if (emu6502_load(emu, path, 0) != 0) {
+        fprintf(stderr, "%s\n", emu6502_last_error(emu));
+        failures++;
+    } else {
+        emu6502_run(emu, 200000000);
+        emu6502_get_registers(emu, &registers);
+        printf("%s trapped at $%04x after %llu cycles\n", path, registers.pc,
+               (unsigned long long)emu6502_clock(emu));
+        CHECK(registers.pc == FUNCTIONAL_TEST_PASSED);
+    }
+
+    emu6502_free(emu);
+}
+
+int main(int argc, char **argv) {
+    test_program();
+    test_errors();
+    if (argc > 1) {
+        test_image(argv[1]);
+    }
+    if (failures) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("All checks passed\n");
+    return 0;
+}
@@ -0,0 +1,18 @@
+; Source for hello.dbg, the debug info parser's test fixture. Both are
+; written by hand in the shape of ca65/ld65 output, not assembled, so keep
+; their line numbers, addresses and sizes in step when changing either.
+        .include "macros.inc"
+COUNT = 3
+big = $12345
+        .segment "ZEROPAGE"
+ptr:    .res 2
+        .segment "CODE"
+start:  lda #COUNT
+        call print
+done:   jmp done
+.proc print
+@loop:  dex
+        bne @loop
+        rts
+.endproc
+beef:   .byte 0
@@ -0,0 +1,174 @@
+#!/usr/bin/env python3
+"""Write single step vectors for a few NMOS 6502 opcodes, in the format of
+Tom Harte's SingleStepTests/65x02 (one `<opcode>.json` per opcode).
+
+These are not Harte's vectors. They come from the small model below,
+written from the MOS datasheet, 64doc's bus cycles and Bruce Clark's
+decimal mode notes, so that tests/differential.rs has a reference that
+shares no code with the emulator. Run it from this directory to rewrite
+the files; the seed keeps the output stable.
+"""
+
+import json
+import random
+
+C, Z, I, D, B, U, V, N = 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80
+CASES = 12
+
+
+def nz(p, value):
+    p &= ~(N | Z)
+    if value == 0:
+        p |= Z
+    return p | (value & N)
+
+
+def adc(a, b, p):
+    carry = p & C
+    binary = a + b + carry
+    p &= ~(C | V)
+    if not p & D:
+        result = binary & 0xff
+        if binary > 0xff:
+            p |= C
+        if ~(a ^ b) & (a ^ result) & 0x80:
+            p |= V
+        return result, nz(p, result)
+    # Clark, sequences 1 and 2: N and V come from the sum before the high
+    # digit is adjusted, Z from the binary sum
+    low = (a & 0x0f) + (b & 0x0f) + carry
+    if low >= 0x0a:
+        low = ((low + 0x06) & 0x0f) + 0x10
+    total = (a & 0xf0) + (b & 0xf0) + low
+    p = nz(p, total & 0xff)
+    p = (p & ~Z) | (Z if binary & 0xff == 0 else 0)
+    if ~(a ^ b) & (a ^ total) & 0x80:
+        p |= V
+    if total >= 0xa0:
+        total += 0x60
+    if total >= 0x100:
+        p |= C
+    return total & 0xff, p
+
+
+def sbc(a, b, p):
+    borrow = 1 - (p & C)
+    binary = a - b - borrow
+    p &= ~(C | V)
+    if binary >= 0:
+        p |= C
+    if (a ^ b) & (a ^ binary) & 0x80:
+        p |= V
+    p = nz(p, binary & 0xff)
+    if not p & D:
+        return binary & 0xff, p
+    # Clark, sequence 3: the flags are those of the binary subtraction
+    low = (a & 0x0f) - (b & 0x0f) - borrow
+    if low < 0:
+        low = ((low - 0x06) & 0x0f) - 0x10
+    total = (a & 0xf0) - (b & 0xf0) + low
+    if total < 0:
+        total -= 0x60
+    return total & 0xff, p
+
+
+def compare(register, b, p):
+    diff = register - b
+    p = nz(p, diff & 0xff) & ~C
+    return p | (C if diff >= 0 else 0)
+
+
+def shift(opcode, value, p):
+    carry = p & C
+    if opcode == 0x0a:
+        out, value = value >> 7, value << 1
+    elif opcode == 0x4a:
+        out, value = value & 1, value >> 1
+    elif opcode == 0x2a:
+        out, value = value >> 7, (value << 1) | carry
+    else:
+        out, value = value & 1, (value >> 1) | (carry << 7)
+    value &= 0xff
+    return value, nz(p & ~C, value) | out
+
+
+def step(opcode, state, ram, cycles):
+    """Run one instruction, filling in `cycles` as [address, value, kind]"""
+    def read(addr):
+        addr &= 0xffff
+        value = ram.setdefault(addr, random.randrange(256))
+        cycles.append([addr, value, "read"])
+        return value
+
+    def write(addr, value):
+        ram[addr] = value
+        cycles.append([addr, value, "write"])
+
+    pc = state["pc"]
+    read(pc)
+    operand = read(pc + 1)
+    state["pc"] = (pc + 2) & 0xffff
+    a, p = state["a"], state["p"]
+    if opcode == 0x69:
+        state["a"], state["p"] = adc(a, operand, p)
+    elif opcode == 0x65:
+        state["a"], state["p"] = adc(a, read(operand), p)
+    elif opcode == 0xe9:
+        state["a"], state["p"] = sbc(a, operand, p)
+    elif opcode == 0xc9:
+        state["p"] = compare(a, operand, p)
+    elif opcode in (0x0a, 0x4a, 0x2a, 0x6a):
+        # A one byte instruction still reads the next byte
+        state["pc"] = (pc + 1) & 0xffff
+        state["a"], state["p"] = shift(opcode, a, p)
+    elif opcode == 0x24:
+        value = read(operand)
+        p = (p & ~(N | V | Z)) | (value & (N | V))
+        state["p"] = p | (Z if a & value == 0 else 0)
+    elif opcode == 0xe6:
+        # Read-modify-write stores the old value before the new one
+        value = read(operand)
+        write(operand, value)
+        value = (value + 1) & 0xff
+        write(operand, value)
+        state["p"] = nz(p, value)
+    else:
+        raise ValueError("no model for opcode %02x" % opcode)
+
+
+def case(opcode):
+    state = {
+        "pc": random.randrange(0x10000),
+        "s": random.randrange(256),
+        "a": random.randrange(256),
+        "x": random.randrange(256),
+        "y": random.randrange(256),
+        "p": random.randrange(256) | U | B,
+    }
+    ram = {state["pc"]: opcode}
+    initial = dict(state)
+    cycles = []
+    step(opcode, state, ram, cycles)
+    touched = sorted({cycle[0] for cycle in cycles})
+    # Memory as it was before the instruction, for every address it touched
+    before = {}
+    for address, value, kind in cycles:
+        if address not in before:
+            before[address] = value if kind == "read" else None
+    initial["ram"] = [[address, before[address]] for address in touched]
+    final = dict(state)
+    final["ram"] = [[address, ram[address]] for address in touched]
+    name = " ".join("%02x" % ram[(initial["pc"] + i) & 0xffff] for i in range(3) if (initial["pc"] + i) & 0xffff in ram)
+    return {"name": name, "initial": initial, "final": final, "cycles": cycles}
+
+
+def main():
+    random.seed(6502)
+    for opcode in (0x0a, 0x24, 0x2a, 0x4a, 0x65, 0x69, 0x6a, 0xc9, 0xe6, 0xe9):
+        cases = [case(opcode) for _ in range(CASES)]
+        with open("%02x.json" % opcode, "w") as out:
+            out.write("[\n" + ",\n".join(json.dumps(case) for case in cases) + "\n]\n")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,44 @@
+//! Bruce Clark's exhaustive decimal mode test, in `6502_decimal_test.s`,
+//! run on both processors and in both execution modes.
+
+use std::fs;
+
+use rust_test::computer::{assembler, Computer, Variant, START_PC};
+
+/// More than the test takes on either processor
+const MAX_CYCLES: u64 = 100_000_000;
+
+/// Run the test and return what it left in ERROR, 0 when it passed
+fn run(variant: Variant, cycle_exact: bool) -> u8 {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/6502_decimal_test.s");
+    let source = fs::read_to_string(path).unwrap();
+    let assembly = assembler::assemble(&source, START_PC).unwrap();
+    let mut computer = Computer::headless(assembly.image(0));
+    computer.set_variant(variant);
+    computer.set_cycle_exact(cycle_exact);
+    computer.write_byte(assembly.symbols["CHIP"], if variant == Variant::Cmos { 1 } else { 0 });
+
+    computer.run_to_trap(MAX_CYCLES);
+    assert_eq!(computer.processor().pc, assembly.symbols["TRAP"], "the test did not finish");
+    computer.read_byte(assembly.symbols["ERROR"])
+}
+
+#[test]
+fn nmos_decimal_mode() {
+    assert_eq!(run(Variant::Nmos, false), 0);
+}
+
+#[test]
+fn cmos_decimal_mode() {
+    assert_eq!(run(Variant::Cmos, false), 0);
+}
+
+#[test]
+fn nmos_decimal_mode_cycle_exact() {
+    assert_eq!(run(Variant::Nmos, true), 0);
+}
+
+#[test]
+fn cmos_decimal_mode_cycle_exact() {
+    assert_eq!(run(Variant::Cmos, true), 0);
+}
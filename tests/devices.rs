@@ -0,0 +1,137 @@
+//! Devices see every access the program makes to their addresses, stack
+//! pushes and pulls and pointer reads included, in both execution modes.
+
+use std::sync::{Arc, Mutex};
+
+use rust_test::computer::device::Device;
+use rust_test::computer::riot::Riot;
+use rust_test::computer::{assembler, Computer, START_PC};
+
+const PROGRAM: &str = "
+        LDX #$FF
+        TXS
+        LDA #$42
+        PHA
+        PHP
+        PLP
+        PLA
+        JSR SUB
+        BRK
+        NOP
+DONE:   JMP DONE
+SUB:    RTS
+IRQ:    RTI
+        .org $FFFE
+        .word IRQ
+";
+
+/// Device accesses as address, value and whether it was a write
+type AccessLog = Vec<(u16, u8, bool)>;
+
+/// Page 1 as a device, logging each access
+#[derive(Debug, Default)]
+struct Stack {
+    bytes: Vec<u8>,
+    log: Arc<Mutex<AccessLog>>,
+}
+
+impl Device for Stack {
+    fn name(&self) -> String {
+        "stack".to_string()
+    }
+
+    fn handles(&self, addr: u16) -> bool {
+        addr & 0xff00 == 0x0100
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.bytes[addr as usize & 0xff];
+        self.log.lock().unwrap().push((addr, value, false));
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.bytes[addr as usize & 0xff] = value;
+        self.log.lock().unwrap().push((addr, value, true));
+    }
+}
+
+fn stack_traffic(cycle_exact: bool) -> (AccessLog, Vec<(u16, u8)>) {
+    let image = assembler::assemble(PROGRAM, START_PC).unwrap().image(0);
+    let mut computer = Computer::headless(image);
+    computer.set_cycle_exact(cycle_exact);
+    let log = Arc::new(Mutex::new(vec![]));
+    computer.add_device(Box::new(Stack { bytes: vec![0; 0x100], log: log.clone() }));
+    for addr in 0x0100..0x0200 {
+        computer.watch_writes(addr, true);
+    }
+    computer.run_to_trap(1000);
+    let log = log.lock().unwrap().clone();
+    (log, computer.take_writes())
+}
+
+#[test]
+fn stack_goes_through_devices() {
+    let (log, watched) = stack_traffic(false);
+    let writes: Vec<_> = log.iter().filter(|&&(_, _, write)| write).map(|&(addr, value, _)| (addr, value)).collect();
+    let pc_after_jsr = START_PC + 11;
+    let pc_after_brk = START_PC + 14;
+    assert_eq!(writes, vec![
+        (0x01ff, 0x42),
+        (0x01fe, 0x30),
+        (0x01ff, (pc_after_jsr >> 8) as u8), (0x01fe, pc_after_jsr as u8),
+        (0x01ff, (pc_after_brk >> 8) as u8), (0x01fe, pc_after_brk as u8), (0x01fd, 0x30),
+    ]);
+    assert_eq!(watched, writes);
+
+    let reads: Vec<_> = log.iter().filter(|&&(_, _, write)| !write).map(|&(addr, value, _)| (addr, value)).collect();
+    assert!(reads.starts_with(&[(0x01fe, 0x30), (0x01ff, 0x42)]), "{:x?}", reads);
+    assert!(reads.ends_with(&[(0x01fd, 0x30), (0x01fe, pc_after_brk as u8), (0x01ff, (pc_after_brk >> 8) as u8)]), "{:x?}", reads);
+}
+
+#[test]
+fn both_modes_write_the_stack_alike() {
+    let (instructions, _) = stack_traffic(false);
+    let (cycles, _) = stack_traffic(true);
+    let writes = |log: &[(u16, u8, bool)]| log.iter().filter(|&&(_, _, write)| write).cloned().collect::<Vec<_>>();
+    assert_eq!(writes(&instructions), writes(&cycles));
+}
+
+/// Follows pointers kept in page zero, which is RIOT RAM as on the 2600
+const POINTERS: &str = "
+        LDA #$00
+        STA $80
+        LDA #$12
+        STA $81
+        LDA #$99
+        STA $1200
+        LDY #0
+        LDA ($80),Y
+        STA $0300
+        LDX #0
+        LDA ($80,X)
+        STA $0301
+        LDA #<DONE
+        STA $82
+        LDA #>DONE
+        STA $83
+        JMP ($0082)
+        BRK
+DONE:   JMP DONE
+";
+
+#[test]
+fn pointers_are_read_through_devices() {
+    let program = assembler::assemble(POINTERS, START_PC).unwrap();
+    let done = program.symbols["DONE"];
+    for &cycle_exact in [false, true].iter() {
+        let mut computer = Computer::headless(program.image(0));
+        computer.set_cycle_exact(cycle_exact);
+        computer.add_device(Box::new(Riot::new(0x0080)));
+        computer.run_to_trap(1000);
+        assert_eq!(computer.processor().pc, done, "cycle exact {}", cycle_exact);
+        assert_eq!(&computer.memory()[0x0300..0x0302], &[0x99, 0x99], "cycle exact {}", cycle_exact);
+        // Nothing reached the memory under the RIOT
+        assert_eq!(&computer.memory()[0x0080..0x0084], &[0, 0, 0, 0], "cycle exact {}", cycle_exact);
+    }
+}
@@ -0,0 +1,463 @@
+//! Differential tests of the instruction core. Each case runs one
+//! instruction from a random processor state and memory, in the
+//! instruction core and in a reference, and reports every register, flag,
+//! memory byte and cycle count that differs.
+//!
+//! The reference is the cycle stepped core, written separately from the
+//! bus timings in 64doc. It shares the ALU and flag code with the
+//! instruction core, so bugs there show up in both: `known_results` checks
+//! both cores against results worked out by hand, `sample_vectors`
+//! against vectors from a separate model of a few opcodes, and the
+//! ignored `single_step_vectors` against Tom Harte's 6502 vectors.
+
+// proptest is left out of the wasm32 build
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use proptest::prelude::*;
+use serde_json::Value;
+
+use rust_test::computer::cycle::BusCycle;
+use rust_test::computer::protocol::Register;
+use rust_test::computer::{decode, Computer, Variant};
+
+const FLAG_NAMES: &str = "NV-BDIZC";
+/// B and bit 5, which only exist on the stack
+const PUSHED_ONLY: u8 = 0x30;
+/// Divergences listed before the rest are only counted
+const MAX_REPORTED: usize = 50;
+
+/// Registers and flags before or after an instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Registers {
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u8,
+    p: u8,
+    pc: u16,
+}
+
+/// What running one instruction left behind
+struct Outcome {
+    registers: Registers,
+    memory: Vec<u8>,
+    cycles: u64,
+    /// Bus accesses, from the cycle stepped core only
+    bus: Option<Vec<BusCycle>>,
+}
+
+/// Run the instruction at `registers.pc`, in the cycle stepped core when
+/// `cycle_exact` is set
+fn run(variant: Variant, cycle_exact: bool, registers: Registers, memory: Vec<u8>) -> Outcome {
+    let mut computer = Computer::headless(memory);
+    computer.set_variant(variant);
+    computer.set_cycle_exact(cycle_exact);
+    let set = [
+        (Register::A, registers.a as u16), (Register::X, registers.x as u16), (Register::Y, registers.y as u16),
+        (Register::SP, registers.sp as u16), (Register::Flags, registers.p as u16), (Register::PC, registers.pc),
+    ];
+    for &(register, value) in set.iter() {
+        computer.set_register(register, value).unwrap();
+    }
+
+    let (cycles, bus) = if cycle_exact {
+        let mut bus = vec![computer.tick()];
+        while !computer.at_boundary() {
+            bus.push(computer.tick());
+        }
+        (bus.len() as u64, Some(bus))
+    } else {
+        (computer.run_one(), None)
+    };
+    let processor = computer.processor();
+    Outcome {
+        registers: Registers {
+            a: processor.acc, x: processor.rx, y: processor.ry, sp: processor.sp, p: processor.flags, pc: processor.pc,
+        },
+        memory: computer.memory().to_vec(),
+        cycles,
+        bus,
+    }
+}
+
+fn flags(p: u8) -> String {
+    FLAG_NAMES.chars().enumerate()
+        .map(|(i, name)| if p & (0x80 >> i) != 0 { name } else { '.' })
+        .collect()
+}
+
+fn bus_text(bus: &[BusCycle]) -> String {
+    bus.iter()
+        .map(|c| format!("{}${:04x}=${:02x}", if c.write { "W" } else { "R" }, c.address, c.value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Every way `ours` differs from `expected`
+fn differences(ours: &Outcome, expected: &Outcome) -> Vec<String> {
+    let (o, e) = (ours.registers, expected.registers);
+    let mut found = vec![];
+    for &(name, ours, expected) in [("A", o.a, e.a), ("X", o.x, e.x), ("Y", o.y, e.y), ("SP", o.sp, e.sp)].iter() {
+        if ours != expected {
+            found.push(format!("{} ${:02x}, expected ${:02x}", name, ours, expected));
+        }
+    }
+    if o.pc != e.pc {
+        found.push(format!("PC ${:04x}, expected ${:04x}", o.pc, e.pc));
+    }
+    if (o.p ^ e.p) & !PUSHED_ONLY != 0 {
+        found.push(format!("flags {}, expected {}", flags(o.p), flags(e.p)));
+    }
+    for (addr, (ours, expected)) in ours.memory.iter().zip(expected.memory.iter()).enumerate() {
+        if ours != expected {
+            found.push(format!("memory ${:04x} ${:02x}, expected ${:02x}", addr, ours, expected));
+        }
+    }
+    if ours.cycles != expected.cycles {
+        found.push(format!("{} cycles, expected {}", ours.cycles, expected.cycles));
+    }
+    if let (Some(ours), Some(expected)) = (&ours.bus, &expected.bus) {
+        if ours != expected {
+            found.push(format!("bus {}, expected {}", bus_text(ours), bus_text(expected)));
+        }
+    }
+    found
+}
+
+/// 64 KiB of noise from `seed`, so pointers and operands land anywhere
+fn noise(seed: u64) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..0x10000).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 32) as u8
+    }).collect()
+}
+
+fn registers() -> impl Strategy<Value = Registers> {
+    (any::<u8>(), any::<u8>(), any::<u8>(), any::<u8>(), any::<u8>(), any::<u16>())
+        .prop_map(|(a, x, y, sp, p, pc)| Registers { a, x, y, sp, p: p & !PUSHED_ONLY, pc })
+}
+
+/// Undocumented opcodes are left out, as the cores do not agree on them
+fn opcode() -> impl Strategy<Value = u8> {
+    prop::sample::select((0..=255).filter(|&opcode| decode::is_documented(opcode)).collect::<Vec<u8>>())
+}
+
+fn variant() -> impl Strategy<Value = Variant> {
+    prop_oneof![Just(Variant::Nmos), Just(Variant::Cmos)]
+}
+
+proptest! {
+    /// `PROPTEST_CASES` runs more than the default 256 cases
+    #[test]
+    fn instruction_core_matches_cycle_core(
+        variant in variant(),
+        registers in registers(),
+        opcode in opcode(),
+        operands in any::<[u8; 2]>(),
+        seed in any::<u64>(),
+    ) {
+        let mut memory = noise(seed);
+        memory[registers.pc as usize] = opcode;
+        memory[registers.pc.wrapping_add(1) as usize] = operands[0];
+        memory[registers.pc.wrapping_add(2) as usize] = operands[1];
+
+        let ours = run(variant, false, registers, memory.clone());
+        let reference = run(variant, true, registers, memory);
+        let found = differences(&ours, &reference);
+        prop_assert!(found.is_empty(), "{:02x} {:02x} {:02x} at ${:04x}: {}",
+            opcode, operands[0], operands[1], registers.pc, found.join(", "));
+    }
+}
+
+const C: u8 = 0x01;
+const Z: u8 = 0x02;
+const V: u8 = 0x40;
+const N: u8 = 0x80;
+
+/// An instruction's effect, worked out by hand from the datasheets rather
+/// than taken from either core. Each is a divergence the harness found.
+struct Known {
+    name: &'static str,
+    before: Registers,
+    /// Memory before, zero elsewhere
+    memory: &'static [(u16, u8)],
+    after: Registers,
+    /// Memory written
+    written: &'static [(u16, u8)],
+    cycles: u64,
+}
+
+/// Registers at `pc` with everything else zero and the stack empty
+const fn at(pc: u16) -> Registers {
+    Registers { a: 0, x: 0, y: 0, sp: 0xff, p: 0, pc }
+}
+
+const KNOWN: &[Known] = &[
+    Known {
+        name: "ROL abs rotates memory, not A",
+        before: Registers { a: 0x55, ..at(0x0400) },
+        memory: &[(0x0400, 0x2e), (0x0401, 0x00), (0x0402, 0x02), (0x0200, 0x81)],
+        after: Registers { a: 0x55, p: C, ..at(0x0403) },
+        written: &[(0x0200, 0x02)],
+        cycles: 6,
+    },
+    Known {
+        name: "ROR abs rotates memory, not A",
+        before: Registers { a: 0x55, p: C, ..at(0x0400) },
+        memory: &[(0x0400, 0x6e), (0x0401, 0x00), (0x0402, 0x02), (0x0200, 0x01)],
+        after: Registers { a: 0x55, p: N | C, ..at(0x0403) },
+        written: &[(0x0200, 0x80)],
+        cycles: 6,
+    },
+    Known {
+        name: "ASL A sets Z from the result",
+        before: Registers { a: 0x80, ..at(0x0400) },
+        memory: &[(0x0400, 0x0a)],
+        after: Registers { a: 0x00, p: Z | C, ..at(0x0401) },
+        written: &[],
+        cycles: 2,
+    },
+    Known {
+        name: "LSR A sets Z from the result",
+        before: Registers { a: 0x01, ..at(0x0400) },
+        memory: &[(0x0400, 0x4a)],
+        after: Registers { a: 0x00, p: Z | C, ..at(0x0401) },
+        written: &[],
+        cycles: 2,
+    },
+    Known {
+        name: "CMP sets N from bit 7 of the difference",
+        before: Registers { a: 0x10, ..at(0x0400) },
+        memory: &[(0x0400, 0xc9), (0x0401, 0x20)],
+        after: Registers { a: 0x10, p: N, ..at(0x0402) },
+        written: &[],
+        cycles: 2,
+    },
+    Known {
+        name: "CPX sets C when X is not less",
+        before: Registers { x: 0x80, ..at(0x0400) },
+        memory: &[(0x0400, 0xe0), (0x0401, 0x00)],
+        after: Registers { x: 0x80, p: N | C, ..at(0x0402) },
+        written: &[],
+        cycles: 2,
+    },
+    Known {
+        name: "ADC sets V on signed overflow",
+        before: Registers { a: 0x50, ..at(0x0400) },
+        memory: &[(0x0400, 0x69), (0x0401, 0x50)],
+        after: Registers { a: 0xa0, p: N | V, ..at(0x0402) },
+        written: &[],
+        cycles: 2,
+    },
+    Known {
+        name: "SBC sets V on signed overflow and clears C on borrow",
+        before: Registers { a: 0x50, p: C, ..at(0x0400) },
+        memory: &[(0x0400, 0xe9), (0x0401, 0xb0)],
+        after: Registers { a: 0xa0, p: N | V, ..at(0x0402) },
+        written: &[],
+        cycles: 2,
+    },
+    Known {
+        name: "(zp,X) pointer wraps in the zero page",
+        before: at(0x0400),
+        memory: &[(0x0400, 0xa1), (0x0401, 0xff), (0x00ff, 0x34), (0x0000, 0x12), (0x1234, 0x99)],
+        after: Registers { a: 0x99, p: N, ..at(0x0402) },
+        written: &[],
+        cycles: 6,
+    },
+    Known {
+        name: "(zp),Y pointer wraps in the zero page and pays for a page cross",
+        before: Registers { y: 0x01, ..at(0x0400) },
+        memory: &[(0x0400, 0xb1), (0x0401, 0xff), (0x00ff, 0xff), (0x0000, 0x12), (0x1300, 0x77)],
+        after: Registers { a: 0x77, y: 0x01, ..at(0x0402) },
+        written: &[],
+        cycles: 6,
+    },
+    Known {
+        name: "JMP ($xxFF) takes the high byte from the start of the page",
+        before: at(0x0400),
+        memory: &[(0x0400, 0x6c), (0x0401, 0xff), (0x0402, 0x02), (0x02ff, 0x00), (0x0200, 0x03), (0x0300, 0x55)],
+        after: at(0x0300),
+        written: &[],
+        cycles: 5,
+    },
+    Known {
+        name: "abs,X read pays for a page cross",
+        before: Registers { x: 0x01, ..at(0x0400) },
+        memory: &[(0x0400, 0xbd), (0x0401, 0xff), (0x0402, 0x02), (0x0300, 0x01)],
+        after: Registers { a: 0x01, x: 0x01, ..at(0x0403) },
+        written: &[],
+        cycles: 5,
+    },
+    Known {
+        name: "abs,X write always takes 5 cycles",
+        before: Registers { a: 0x42, ..at(0x0400) },
+        memory: &[(0x0400, 0x9d), (0x0401, 0x00), (0x0402, 0x02)],
+        after: Registers { a: 0x42, ..at(0x0403) },
+        written: &[(0x0200, 0x42)],
+        cycles: 5,
+    },
+    Known {
+        name: "taken branch to another page takes 4 cycles",
+        before: at(0x0480),
+        memory: &[(0x0480, 0xd0), (0x0481, 0x7f)],
+        after: at(0x0501),
+        written: &[],
+        cycles: 4,
+    },
+    Known {
+        name: "taken branch in the same page takes 3 cycles",
+        before: at(0x0400),
+        memory: &[(0x0400, 0xd0), (0x0401, 0x10)],
+        after: at(0x0412),
+        written: &[],
+        cycles: 3,
+    },
+    Known {
+        name: "PC wraps from $FFFF to $0000",
+        before: at(0xffff),
+        memory: &[(0xffff, 0xa9), (0x0000, 0x42)],
+        after: Registers { a: 0x42, ..at(0x0001) },
+        written: &[],
+        cycles: 2,
+    },
+    Known {
+        name: "RTS to $FFFF+1 wraps to $0000",
+        before: Registers { sp: 0xfd, ..at(0x0400) },
+        memory: &[(0x0400, 0x60), (0x01fe, 0xff), (0x01ff, 0xff)],
+        after: at(0x0000),
+        written: &[],
+        cycles: 6,
+    },
+];
+
+#[test]
+fn known_results() {
+    let mut diverged = vec![];
+    for known in KNOWN {
+        let mut memory = vec![0; 0x10000];
+        for &(addr, value) in known.memory {
+            memory[addr as usize] = value;
+        }
+        let mut expected_memory = memory.clone();
+        for &(addr, value) in known.written {
+            expected_memory[addr as usize] = value;
+        }
+        let expected = Outcome { registers: known.after, memory: expected_memory, cycles: known.cycles, bus: None };
+        for &(cycle_exact, core) in [(false, "instruction"), (true, "cycle")].iter() {
+            let ours = run(Variant::Nmos, cycle_exact, known.before, memory.clone());
+            let found = differences(&ours, &expected);
+            if !found.is_empty() {
+                diverged.push(format!("{} in the {} core: {}", known.name, core, found.join(", ")));
+            }
+        }
+    }
+    assert!(diverged.is_empty(), "{}", diverged.join("\n"));
+}
+
+fn byte(value: &Value) -> u8 {
+    value.as_u64().expect("a number") as u8
+}
+
+fn registers_of(state: &Value) -> Registers {
+    Registers {
+        a: byte(&state["a"]),
+        x: byte(&state["x"]),
+        y: byte(&state["y"]),
+        sp: byte(&state["s"]),
+        p: byte(&state["p"]) & !PUSHED_ONLY,
+        pc: state["pc"].as_u64().expect("a number") as u16,
+    }
+}
+
+/// Store a vector's `[address, value]` pairs
+fn apply(memory: &mut [u8], ram: &Value) {
+    for pair in ram.as_array().expect("a list of bytes") {
+        memory[pair[0].as_u64().expect("an address") as usize] = byte(&pair[1]);
+    }
+}
+
+/// The vector files at `path`, by opcode
+fn vector_files(path: &Path) -> Vec<PathBuf> {
+    if path.is_file() {
+        return vec![path.to_path_buf()];
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path).expect("a directory of vectors")
+        .map(|entry| entry.unwrap().path())
+        .filter(|file| file.extension().and_then(|e| e.to_str()) == Some("json"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Run both cores on the single step vectors at `path`, a file or a
+/// directory of `<opcode>.json` files. Each case gives the state before
+/// and after one instruction and the bus cycles in between.
+fn check_vectors(path: &Path) {
+    let mut diverged = vec![];
+    let mut count = 0;
+    for file in vector_files(path) {
+        let text = fs::read_to_string(&file).unwrap();
+        let cases: Value = serde_json::from_str(&text).unwrap();
+        for case in cases.as_array().expect("a list of cases") {
+            let mut memory = vec![0; 0x10000];
+            apply(&mut memory, &case["initial"]["ram"]);
+            let opcode = memory[registers_of(&case["initial"]).pc as usize];
+            if !decode::is_documented(opcode) {
+                continue;
+            }
+            let mut expected_memory = memory.clone();
+            apply(&mut expected_memory, &case["final"]["ram"]);
+            let bus: Vec<BusCycle> = case["cycles"].as_array().expect("a list of cycles").iter()
+                .map(|cycle| BusCycle {
+                    address: cycle[0].as_u64().expect("an address") as u16,
+                    value: byte(&cycle[1]),
+                    write: cycle[2] == "write",
+                })
+                .collect();
+            let expected = Outcome {
+                registers: registers_of(&case["final"]),
+                memory: expected_memory,
+                cycles: bus.len() as u64,
+                bus: Some(bus),
+            };
+            for &(cycle_exact, core) in [(false, "instruction"), (true, "cycle")].iter() {
+                count += 1;
+                let ours = run(Variant::Nmos, cycle_exact, registers_of(&case["initial"]), memory.clone());
+                let found = differences(&ours, &expected);
+                if !found.is_empty() {
+                    diverged.push(format!("{} in the {} core: {}", case["name"].as_str().unwrap_or("?"), core, found.join(", ")));
+                }
+            }
+        }
+    }
+    assert!(count > 0, "no vectors at {}", path.display());
+    assert!(diverged.is_empty(), "{} of {} runs diverged:\n{}",
+        diverged.len(), count, diverged[..diverged.len().min(MAX_REPORTED)].join("\n"));
+}
+
+/// A few opcodes' vectors in Tom Harte's format, written by the model in
+/// `tests/data/single_step/generate.py` rather than taken from his set.
+/// The model shares no code with either core, so ALU and flag bugs that
+/// both cores have still show up here.
+#[test]
+fn sample_vectors() {
+    check_vectors(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/single_step"));
+}
+
+/// Tom Harte's vectors for the NMOS 6502, from
+/// https://github.com/SingleStepTests/65x02 (`6502/v1`). They are too big
+/// to keep in the repository, so run this with
+/// `SINGLE_STEP_TESTS=<path> cargo test --test differential -- --ignored`.
+#[test]
+#[ignore]
+fn single_step_vectors() {
+    check_vectors(&PathBuf::from(env::var("SINGLE_STEP_TESTS").expect("SINGLE_STEP_TESTS names the vectors")));
+}
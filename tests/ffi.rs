@@ -0,0 +1,68 @@
+//! The C API, called from Rust. `tests/c/emu6502_test.c` calls it from C.
+
+#![cfg(feature = "ffi")]
+
+use std::ffi::CStr;
+use std::os::raw::c_void;
+use std::ptr;
+
+use rust_test::computer::{assembler, START_PC};
+use rust_test::ffi::*;
+
+/// Stores A through a mapped address and reads it back through another
+const PROGRAM: &str = "
+        LDA #$2A
+        STA $D000
+        LDA $D001
+DONE:   JMP DONE
+";
+
+extern "C" fn read(user: *mut c_void, addr: u16) -> u8 {
+    let written = unsafe { &*(user as *const Vec<(u16, u8)>) };
+    written.last().map_or(0, |&(_, value)| value) + (addr & 0xf) as u8
+}
+
+extern "C" fn write(user: *mut c_void, addr: u16, value: u8) {
+    let written = unsafe { &mut *(user as *mut Vec<(u16, u8)>) };
+    written.push((addr, value));
+}
+
+#[test]
+fn callbacks_see_the_bus() {
+    let mut written: Vec<(u16, u8)> = vec![];
+    unsafe {
+        let emu = emu6502_new(false);
+        for (addr, bytes) in assembler::assemble(PROGRAM, START_PC).unwrap().segments {
+            assert_eq!(emu6502_write_memory(emu, addr, bytes.as_ptr(), bytes.len()), 0);
+        }
+        let user = &mut written as *mut Vec<(u16, u8)> as *mut c_void;
+        assert_eq!(emu6502_map(emu, 0xd000, 0xd00f, Some(read), Some(write), user), 0);
+
+        assert_eq!(emu6502_run(emu, 1000), 2 + 4 + 4 + 3);
+        let mut registers = Emu6502Registers::default();
+        emu6502_get_registers(emu, &mut registers);
+        assert_eq!((registers.a, registers.pc), (0x2b, START_PC + 8));
+
+        registers.flags = 0xff;
+        emu6502_set_registers(emu, &registers);
+        emu6502_get_registers(emu, &mut registers);
+        assert_eq!(registers.flags, 0xcf);
+        emu6502_free(emu);
+    }
+    assert_eq!(written, vec![(0xd000, 0x2a)]);
+}
+
+#[test]
+fn failures_leave_an_error() {
+    unsafe {
+        let emu = emu6502_new(true);
+        let mut bytes = [0u8; 2];
+        assert_eq!(emu6502_read_memory(emu, 0xffff, bytes.as_mut_ptr(), 2), -1);
+        assert_eq!(emu6502_write_memory(emu, 0xffff, bytes.as_ptr(), 2), -1);
+        let error = CStr::from_ptr(emu6502_last_error(emu)).to_str().unwrap();
+        assert_eq!(error, "2 bytes do not fit at 0xffff");
+        assert_eq!(emu6502_map(emu, 0xd00f, 0xd000, Some(read), Some(write), ptr::null_mut()), -1);
+        assert_eq!(emu6502_map(emu, 0xd000, 0xd00f, None, Some(write), ptr::null_mut()), -1);
+        emu6502_free(emu);
+    }
+}
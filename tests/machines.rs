@@ -0,0 +1,71 @@
+//! Several machines in one process, each on its own thread, stepped side
+//! by side and shut down.
+
+// There are no threads on wasm32
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::thread;
+use std::time::Duration;
+
+use rust_test::computer::machine::{Machine, Machines};
+use rust_test::computer::protocol::{Command, Register};
+use rust_test::computer::{assembler, START_PC};
+
+/// Counts X up, and Y each time X wraps
+const COUNTER: &str = "
+LOOP:   INX
+        BNE LOOP
+        INY
+        JMP LOOP
+";
+
+fn counter() -> Vec<u8> {
+    assembler::assemble(COUNTER, START_PC).unwrap().image(0)
+}
+
+#[test]
+fn machines_step_independently() {
+    let mut machines = Machines::new();
+    let ids: Vec<usize> = (0..8).map(|_| machines.create(counter(), |_| ())).collect();
+    for (i, &id) in ids.iter().enumerate() {
+        let machine = machines.get(id).unwrap();
+        machine.request(Command::SetRegister(Register::A, i as u16)).unwrap();
+    }
+
+    // 256 times round INX and BNE, then INY
+    machines.step_all(513).unwrap();
+    for (i, &id) in ids.iter().enumerate() {
+        let processor = machines.get(id).unwrap().state().unwrap().processor;
+        assert_eq!((processor.acc, processor.rx, processor.ry), (i as u8, 0, 1));
+    }
+
+    machines.destroy(ids[0]).unwrap();
+    assert!(machines.get(ids[0]).is_none());
+    assert!(machines.destroy(ids[0]).is_err());
+    assert_eq!(machines.len(), 7);
+    machines.destroy_all().unwrap();
+    assert!(machines.is_empty());
+}
+
+#[test]
+fn running_machine_pauses_and_shuts_down() {
+    let mut machine = Machine::spawn(counter(), |_| ());
+    machine.run().unwrap();
+    thread::sleep(Duration::from_millis(20));
+    machine.pause().unwrap();
+    let clock = machine.state().unwrap().processor.clock;
+    assert!(clock > 0);
+    assert_eq!(machine.state().unwrap().processor.clock, clock);
+
+    machine.run().unwrap();
+    machine.shutdown().unwrap();
+    assert!(machine.state().is_err());
+    machine.shutdown().unwrap();
+}
+
+#[test]
+fn errors_come_back_as_errors() {
+    let mut machine = Machine::spawn(counter(), |_| ());
+    machine.run().unwrap();
+    assert!(machine.request(Command::WriteMemory(0, vec![1])).is_err());
+}
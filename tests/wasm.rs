@@ -0,0 +1,78 @@
+//! The JavaScript bindings, run under Node with
+//! `wasm-pack test --node -- --no-default-features --features wasm`, or
+//! as ordinary tests on the host.
+
+#![cfg(feature = "wasm")]
+
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use rust_test::computer::{assembler, START_PC};
+use rust_test::wasm::Emulator;
+
+const PROGRAM: &str = "
+        LDA #$2A
+        STA $0200
+        LDX #5
+LOOP:   DEX
+        BNE LOOP
+DONE:   JMP DONE
+";
+
+fn emulator() -> Emulator {
+    let mut emulator = Emulator::new(false);
+    for (addr, bytes) in assembler::assemble(PROGRAM, START_PC).unwrap().segments {
+        emulator.load(addr, &bytes).unwrap();
+    }
+    emulator
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn steps_and_reads_memory() {
+    let mut emulator = emulator();
+    assert_eq!(emulator.step(), 2);
+    assert_eq!(emulator.a(), 0x2a);
+    assert_eq!(emulator.step(), 4);
+    assert_eq!(emulator.read_memory(0x0200, 1).unwrap(), vec![0x2a]);
+    assert_eq!(emulator.pc(), START_PC + 5);
+    assert_eq!(emulator.clock(), 6.0);
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn runs_whole_instructions() {
+    let mut emulator = emulator();
+    // LDA, STA, LDX, then four times round DEX and a taken BNE
+    assert_eq!(emulator.run_cycles(28), 28);
+    assert_eq!(emulator.x(), 1);
+    // The last DEX and BNE, not taken, go past the end
+    assert_eq!(emulator.run_cycles(3), 4);
+    assert_eq!(emulator.x(), 0);
+    assert_eq!(emulator.flags() & 0x02, 0x02);
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn resets_to_the_start() {
+    let mut emulator = emulator();
+    emulator.run_cycles(100);
+    emulator.reset();
+    assert_eq!(emulator.pc(), START_PC);
+    assert_eq!(emulator.step(), 2);
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn rejects_reads_and_loads_past_the_end() {
+    let mut emulator = emulator();
+    assert_eq!(emulator.read_memory(0xfff0, 16).unwrap().len(), 16);
+    assert!(emulator.read_memory(0xfff0, 17).is_err());
+    assert!(emulator.read_memory(0xffff, u32::MAX).is_err());
+    assert!(emulator.load(0xffff, &[1, 2]).is_err());
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn runs_undocumented_opcodes() {
+    // The core slows down to debug these, which must not read the wall
+    // clock, as that panics on wasm32
+    let mut emulator = Emulator::new(false);
+    emulator.load(START_PC, &[0x1a]).unwrap();
+    assert!(emulator.step() > 0);
+    assert!(emulator.pc() > START_PC);
+}